@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, Extensions, HeaderMap},
+    middleware::Next,
+    response::Response,
+};
+use mongodb::bson::oid::ObjectId;
+
+use crate::{
+    auth::{
+        permissions::{self, Permission, Role},
+        tokens::{self, JwtKeys},
+    },
+    common_struct::AppError,
+};
+
+/// The caller identified by a valid bearer token. `roles` are the ones the
+/// token was issued with; see `constants::ACCESS_TOKEN_TTL_SECS`.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: ObjectId,
+    pub email: String,
+    pub roles: Vec<Role>,
+}
+
+impl AuthUser {
+    pub fn has_permission(&self, permission: Permission) -> bool {
+        permissions::has_permission(&self.roles, permission)
+    }
+
+    /// Owners may always act on their own document; anyone else needs `permission`.
+    pub fn can_act_on(&self, user_id: &ObjectId, permission: Permission) -> bool {
+        &self.id == user_id || self.has_permission(permission)
+    }
+
+    /// Fails with `Forbidden`, naming `action`, unless the caller holds `permission`.
+    pub fn require(&self, permission: Permission, action: &str) -> Result<(), AppError> {
+        if self.has_permission(permission) {
+            Ok(())
+        } else {
+            Err(forbidden(permission, action))
+        }
+    }
+
+    /// Like [`AuthUser::require`], but owners of `user_id` are always allowed.
+    pub fn require_owner_or(
+        &self,
+        user_id: &ObjectId,
+        permission: Permission,
+        action: &str,
+    ) -> Result<(), AppError> {
+        if self.can_act_on(user_id, permission) {
+            Ok(())
+        } else {
+            Err(forbidden(permission, action))
+        }
+    }
+}
+
+fn forbidden(permission: Permission, action: &str) -> AppError {
+    AppError::Forbidden(format!("{} requires {}", action, permission.as_str()))
+}
+
+fn authenticate(extensions: &Extensions, headers: &HeaderMap) -> Result<AuthUser, AppError> {
+    let keys = extensions
+        .get::<Arc<JwtKeys>>()
+        .ok_or_else(|| AppError::Internal("JWT keys are not installed".to_string()))?;
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| AppError::Unauthorized("Missing bearer token".to_string()))?;
+
+    let claims = tokens::decode_access_token(keys, token.trim())?;
+    let id = ObjectId::parse_str(&claims.sub)
+        .map_err(|_| AppError::Unauthorized("Invalid token subject".to_string()))?;
+
+    Ok(AuthUser {
+        id,
+        email: claims.email,
+        roles: claims.roles,
+    })
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // Reuse the identity resolved by `require_auth` when the route has it.
+        if let Some(user) = parts.extensions.get::<AuthUser>() {
+            return Ok(user.clone());
+        }
+        authenticate(&parts.extensions, &parts.headers)
+    }
+}
+
+/// Route layer that rejects requests without a valid bearer token.
+pub async fn require_auth(mut request: Request, next: Next) -> Result<Response, AppError> {
+    let user = authenticate(request.extensions(), request.headers())?;
+    request.extensions_mut().insert(user);
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,99 @@
+use argon2::{
+    password_hash::{
+        self, rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Algorithm, Argon2, Params, Version,
+};
+
+use crate::common_struct::AppError;
+
+// Argon2id cost parameters used for every new hash. Raising any of them makes
+// `verify_password` report older hashes as outdated so they get upgraded the
+// next time their owner signs in.
+const MEMORY_COST_KIB: u32 = 19 * 1024;
+const TIME_COST: u32 = 2;
+const PARALLELISM: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Mismatch,
+    Match,
+    /// The password is correct but the stored value is outdated; holds the
+    /// replacement hash that should be written back.
+    MatchNeedsRehash(String),
+}
+
+fn argon2() -> Argon2<'static> {
+    let params = Params::new(MEMORY_COST_KIB, TIME_COST, PARALLELISM, None)
+        .expect("argon2 cost parameters are valid");
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+fn hash_blocking(password: &str) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(argon2()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+fn is_outdated(hash: &PasswordHash) -> bool {
+    if hash.algorithm != Algorithm::Argon2id.ident() || hash.version != Some(Version::V0x13.into())
+    {
+        return true;
+    }
+    match Params::try_from(hash) {
+        Ok(params) => {
+            params.m_cost() != MEMORY_COST_KIB
+                || params.t_cost() != TIME_COST
+                || params.p_cost() != PARALLELISM
+        }
+        Err(_) => true,
+    }
+}
+
+/// Compares in time that depends only on the lengths, not on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn verify_blocking(password: &str, stored: &str) -> Result<PasswordCheck, password_hash::Error> {
+    let parsed = match PasswordHash::new(stored) {
+        Ok(parsed) => parsed,
+        // Documents written before hashing was introduced still hold the
+        // plaintext; accept them once and hand back a proper hash.
+        Err(_) => {
+            return if constant_time_eq(password.as_bytes(), stored.as_bytes()) {
+                Ok(PasswordCheck::MatchNeedsRehash(hash_blocking(password)?))
+            } else {
+                Ok(PasswordCheck::Mismatch)
+            };
+        }
+    };
+
+    match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+        Ok(()) if is_outdated(&parsed) => {
+            Ok(PasswordCheck::MatchNeedsRehash(hash_blocking(password)?))
+        }
+        Ok(()) => Ok(PasswordCheck::Match),
+        Err(password_hash::Error::Password) => Ok(PasswordCheck::Mismatch),
+        Err(error) => Err(error),
+    }
+}
+
+/// Hashes `password` with Argon2id on the blocking thread pool.
+pub async fn hash_password(password: String) -> Result<String, AppError> {
+    tokio::task::spawn_blocking(move || hash_blocking(&password))
+        .await
+        .map_err(|error| AppError::Internal(format!("Password hashing task failed: {}", error)))?
+        .map_err(|error| AppError::Internal(format!("Password hashing failed: {}", error)))
+}
+
+/// Checks `password` against a stored hash, flagging hashes that need upgrading.
+pub async fn verify_password(password: String, stored: String) -> Result<PasswordCheck, AppError> {
+    tokio::task::spawn_blocking(move || verify_blocking(&password, &stored))
+        .await
+        .map_err(|error| {
+            AppError::Internal(format!("Password verification task failed: {}", error))
+        })?
+        .map_err(|error| AppError::Internal(format!("Password verification failed: {}", error)))
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Read any user document, not just your own.
+    UsersRead,
+    /// Create, update or delete any user document and assign roles.
+    UsersWrite,
+    /// Create, update or delete catalogue entries.
+    ProductsAdmin,
+    /// Adjust stock, read the ledger and commit or release anyone's reservations.
+    InventoryAdmin,
+    /// Read and manage anyone's orders and refund their payments.
+    OrdersAdmin,
+    /// Create and read promotions and their coupon codes.
+    PromotionsAdmin,
+}
+
+impl Permission {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Permission::UsersRead => "users:read",
+            Permission::UsersWrite => "users:write",
+            Permission::ProductsAdmin => "products:admin",
+            Permission::InventoryAdmin => "inventory:admin",
+            Permission::OrdersAdmin => "orders:admin",
+            Permission::PromotionsAdmin => "promotions:admin",
+        }
+    }
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        match self {
+            // Regular users only ever act on their own document.
+            Role::User => &[],
+            Role::Admin => &[
+                Permission::UsersRead,
+                Permission::UsersWrite,
+                Permission::ProductsAdmin,
+                Permission::InventoryAdmin,
+                Permission::OrdersAdmin,
+                Permission::PromotionsAdmin,
+            ],
+        }
+    }
+}
+
+pub fn has_permission(roles: &[Role], permission: Permission) -> bool {
+    roles
+        .iter()
+        .any(|role| role.permissions().contains(&permission))
+}
@@ -0,0 +1,80 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{auth::permissions::Role, common_struct::AppError, constants};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub email: String,
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// The HS256 keys for `Config::jwt_secret`, derived once at startup. The
+/// router hands them to every request as an extension.
+#[derive(Clone)]
+pub struct JwtKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl JwtKeys {
+    pub fn new(secret: &str) -> Self {
+        JwtKeys {
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+        }
+    }
+}
+
+pub fn now_secs() -> i64 {
+    DateTime::now().timestamp_millis() / 1000
+}
+
+/// Signs a short-lived HS256 access token for the given user.
+pub fn issue_access_token(
+    keys: &JwtKeys,
+    user_id: &ObjectId,
+    email: &str,
+    roles: &[Role],
+) -> Result<String, AppError> {
+    let iat = now_secs();
+    let claims = Claims {
+        sub: user_id.to_hex(),
+        email: email.to_string(),
+        roles: roles.to_vec(),
+        iat,
+        exp: iat + constants::ACCESS_TOKEN_TTL_SECS,
+    };
+    encode(&Header::default(), &claims, &keys.encoding)
+        .map_err(|error| AppError::Internal(format!("Failed to sign access token: {}", error)))
+}
+
+/// Verifies the signature and expiry of an access token and returns its claims.
+pub fn decode_access_token(keys: &JwtKeys, token: &str) -> Result<Claims, AppError> {
+    decode::<Claims>(token, &keys.decoding, &Validation::default())
+        .map(|data| data.claims)
+        .map_err(|_| AppError::Unauthorized("Invalid or expired token".to_string()))
+}
+
+/// Returns a new opaque token (refresh tokens, anonymous cart tokens). Only its
+/// hash is ever persisted.
+pub fn generate_opaque_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub fn hash_opaque_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
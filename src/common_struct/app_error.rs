@@ -0,0 +1,220 @@
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::error::{ErrorKind, WriteFailure};
+use serde_json::{json, Value};
+use validator::{Validate, ValidationErrors};
+
+use super::{ApiResponse, ErrorDetail};
+
+pub type ApiResult = Result<(StatusCode, Json<Value>), AppError>;
+
+/// Every failure a handler can report. Each variant maps to one HTTP status
+/// and a stable machine-readable code in `ApiResponse.errors`.
+#[derive(Debug)]
+pub enum AppError {
+    Validation(Vec<ErrorDetail>),
+    NotFound(String),
+    Conflict {
+        code: &'static str,
+        message: String,
+    },
+    Unauthorized(String),
+    Forbidden(String),
+    /// A feature that is switched off in this deployment.
+    Unavailable(String),
+    Database(mongodb::error::Error),
+    Internal(String),
+}
+
+impl AppError {
+    /// A single-field validation failure.
+    pub fn invalid(field: &str, code: &str, message: impl Into<String>) -> Self {
+        AppError::Validation(vec![ErrorDetail {
+            field: Some(field.to_string()),
+            code: code.to_string(),
+            message: message.into(),
+        }])
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_failed",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict { code, .. } => code,
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::Database(_) => "database_error",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "Validation failed",
+            AppError::NotFound(_) => "Not found",
+            AppError::Conflict { .. } => "Conflict",
+            AppError::Unauthorized(_) => "Unauthorized",
+            AppError::Forbidden(_) => "Forbidden",
+            AppError::Unavailable(_) => "Service unavailable",
+            AppError::Database(_) | AppError::Internal(_) => "Internal server error",
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppError::Validation(details) => {
+                let messages: Vec<&str> = details.iter().map(|d| d.message.as_str()).collect();
+                write!(f, "{}", messages.join("; "))
+            }
+            AppError::NotFound(message)
+            | AppError::Conflict { message, .. }
+            | AppError::Unauthorized(message)
+            | AppError::Forbidden(message)
+            | AppError::Unavailable(message)
+            | AppError::Internal(message) => write!(f, "{}", message),
+            AppError::Database(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/// True when Mongo rejected a write because it violated a unique index.
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    match error.kind.as_ref() {
+        ErrorKind::Write(WriteFailure::WriteError(failure)) => failure.code == DUPLICATE_KEY,
+        ErrorKind::Command(failure) => failure.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
+
+fn camel_case(field: &str) -> String {
+    let mut parts = field.split('_');
+    let mut name = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name
+}
+
+/// Flattens `validator` output into per-field details keyed by JSON field name.
+pub fn validation_details(errors: ValidationErrors) -> Vec<ErrorDetail> {
+    let mut details: Vec<ErrorDetail> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            let field = camel_case(&field);
+            errors.iter().map(move |error| ErrorDetail {
+                field: Some(field.clone()),
+                code: error.code.to_string(),
+                message: error
+                    .message
+                    .as_ref()
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|| format!("{} is invalid", field)),
+            })
+        })
+        .collect();
+    details.sort_by(|a, b| a.field.cmp(&b.field));
+    details
+}
+
+/// Runs `Validate` on `value` and also reports each `(field, missing)` pair
+/// that is missing as `required`, so the client sees every problem at once.
+pub fn validate_required<T: Validate>(value: &T, fields: &[(&str, bool)]) -> Result<(), AppError> {
+    let mut errors: Vec<ErrorDetail> = fields
+        .iter()
+        .filter(|(_, missing)| *missing)
+        .map(|(field, _)| ErrorDetail {
+            field: Some(field.to_string()),
+            code: "required".to_string(),
+            message: format!("{} is required", field),
+        })
+        .collect();
+    if let Err(invalid) = value.validate() {
+        errors.extend(validation_details(invalid));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Validation(errors))
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(errors: ValidationErrors) -> Self {
+        AppError::Validation(validation_details(errors))
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(error: mongodb::error::Error) -> Self {
+        if is_duplicate_key(&error) {
+            return AppError::Conflict {
+                code: "duplicate_key",
+                message: "A record with the same unique value already exists".to_string(),
+            };
+        }
+        AppError::Database(error)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        println!("{} ({}): {}", self.title(), self.code(), self);
+
+        // Server-side failures are logged above but never echoed to clients.
+        let message = match &self {
+            AppError::NotFound(_)
+            | AppError::Conflict { .. }
+            | AppError::Unauthorized(_)
+            | AppError::Forbidden(_)
+            | AppError::Unavailable(_) => self.to_string(),
+            _ => self.title().to_string(),
+        };
+        let errors = match self {
+            AppError::Validation(details) => details,
+            _ => vec![ErrorDetail {
+                field: None,
+                code: self.code().to_string(),
+                message: message.clone(),
+            }],
+        };
+        let body = Json(json!(ApiResponse {
+            status: "error".to_string(),
+            code: status.as_u16(),
+            message,
+            data: None,
+            errors: Some(errors),
+        }));
+
+        if status == StatusCode::UNAUTHORIZED {
+            (status, [(header::WWW_AUTHENTICATE, "Bearer")], body).into_response()
+        } else {
+            (status, body).into_response()
+        }
+    }
+}
@@ -1,3 +1,9 @@
+pub mod app_error;
+pub mod money;
+pub mod pagination;
+
+pub use app_error::{ApiResult, AppError};
+
 use axum::{http::StatusCode, Json};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
@@ -10,35 +16,27 @@ pub struct ApiResponse<T> {
     pub data: Option<T>,
     pub errors: Option<T>,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct ErrorDetail {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
     pub code: String,
     pub message: String,
 }
-pub async fn handle_db_error<T: std::fmt::Debug>(error: T) -> (StatusCode, Json<Value>) {
-    println!("Database Error: {:?}", error);
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        Json(json!(ApiResponse {
-            status: "error".to_string(),
-            code: 500,
-            message: "Internal server error".to_string(),
-            data: None,
-            errors: Some(format!("{:?}", error)),
-        })),
-    )
-}
 
-pub async fn handle_invalid_id_error<T>(params: String) -> (StatusCode, Json<Value>) {
-    println!("Invalid ID format: {}", params);
+pub fn success_response<T: Serialize>(
+    status: StatusCode,
+    message: &str,
+    data: Option<T>,
+) -> (StatusCode, Json<Value>) {
     (
-        StatusCode::BAD_REQUEST,
+        status,
         Json(json!(ApiResponse {
-            status: "error".to_string(),
-            code: 400,
-            message: "Invalid ID format".to_string(),
-            data: None,
-            errors: Some(format!("Invalid ID format: {}", params)),
+            status: "Success".to_string(),
+            code: status.as_u16(),
+            message: message.to_string(),
+            data,
+            errors: None,
         })),
     )
 }
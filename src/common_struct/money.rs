@@ -0,0 +1,583 @@
+//! Exact monetary amounts. Values are held as integer minor units (cents for
+//! USD, yen for JPY) next to their ISO 4217 currency, so no amount ever passes
+//! through a float.
+//!
+//! In JSON a `Money` is `{"amount": "19.99", "currency": "USD"}`, with the
+//! amount as a decimal string. In MongoDB it is stored through [`stored`] or
+//! [`stored_option`] as `{"amount": <minor units>, "currency": "USD"}`.
+
+use std::{cmp::Ordering, fmt};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{AppError, ErrorDetail};
+
+/// An ISO 4217 currency with its number of minor-unit digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Currency {
+    code: &'static str,
+    exponent: u32,
+}
+
+const CURRENCIES: &[Currency] = &[
+    Currency {
+        code: "AUD",
+        exponent: 2,
+    },
+    Currency {
+        code: "BHD",
+        exponent: 3,
+    },
+    Currency {
+        code: "CAD",
+        exponent: 2,
+    },
+    Currency {
+        code: "CHF",
+        exponent: 2,
+    },
+    Currency {
+        code: "CLP",
+        exponent: 0,
+    },
+    Currency {
+        code: "CNY",
+        exponent: 2,
+    },
+    Currency {
+        code: "DKK",
+        exponent: 2,
+    },
+    Currency {
+        code: "EUR",
+        exponent: 2,
+    },
+    Currency {
+        code: "GBP",
+        exponent: 2,
+    },
+    Currency {
+        code: "INR",
+        exponent: 2,
+    },
+    Currency {
+        code: "ISK",
+        exponent: 0,
+    },
+    Currency {
+        code: "JOD",
+        exponent: 3,
+    },
+    Currency {
+        code: "JPY",
+        exponent: 0,
+    },
+    Currency {
+        code: "KRW",
+        exponent: 0,
+    },
+    Currency {
+        code: "KWD",
+        exponent: 3,
+    },
+    Currency {
+        code: "NOK",
+        exponent: 2,
+    },
+    Currency {
+        code: "OMR",
+        exponent: 3,
+    },
+    Currency {
+        code: "SEK",
+        exponent: 2,
+    },
+    Currency {
+        code: "TND",
+        exponent: 3,
+    },
+    Currency {
+        code: "USD",
+        exponent: 2,
+    },
+    Currency {
+        code: "VND",
+        exponent: 0,
+    },
+];
+
+impl Currency {
+    pub fn from_code(code: &str) -> Option<Currency> {
+        CURRENCIES
+            .iter()
+            .copied()
+            .find(|currency| currency.code == code)
+    }
+
+    /// Every supported currency.
+    pub fn all() -> &'static [Currency] {
+        CURRENCIES
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Digits after the decimal point, e.g. 2 for USD and 0 for JPY.
+    pub fn exponent(&self) -> u32 {
+        self.exponent
+    }
+
+    fn scale(&self) -> i64 {
+        10_i64.pow(self.exponent)
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MoneyError {
+    UnsupportedCurrency(String),
+    InvalidAmount(String),
+    /// The amount has more decimal places than the currency allows.
+    TooPrecise {
+        amount: String,
+        currency: Currency,
+    },
+    CurrencyMismatch(Currency, Currency),
+    Overflow,
+}
+
+impl MoneyError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            MoneyError::UnsupportedCurrency(_) => "unsupported_currency",
+            MoneyError::InvalidAmount(_) => "invalid_amount",
+            MoneyError::TooPrecise { .. } => "too_precise",
+            MoneyError::CurrencyMismatch(..) => "currency_mismatch",
+            MoneyError::Overflow => "amount_overflow",
+        }
+    }
+
+    /// Reports the problem against a request field.
+    pub fn for_field(&self, field: &str) -> ErrorDetail {
+        ErrorDetail {
+            field: Some(field.to_string()),
+            code: self.code().to_string(),
+            message: format!("{}: {}", field, self),
+        }
+    }
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::UnsupportedCurrency(code) => write!(f, "unsupported currency {:?}", code),
+            MoneyError::InvalidAmount(amount) => {
+                write!(f, "{:?} is not a decimal amount", amount)
+            }
+            MoneyError::TooPrecise { amount, currency } => write!(
+                f,
+                "{} allows {} decimal place(s), got {}",
+                currency, currency.exponent, amount
+            ),
+            MoneyError::CurrencyMismatch(a, b) => write!(f, "cannot combine {} with {}", a, b),
+            MoneyError::Overflow => write!(f, "amount is out of range"),
+        }
+    }
+}
+
+/// Arithmetic failures are programming or data errors, not client mistakes.
+impl From<MoneyError> for AppError {
+    fn from(error: MoneyError) -> Self {
+        AppError::Internal(format!("Money arithmetic failed: {}", error))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    minor_units: i64,
+    currency: Currency,
+}
+
+impl Money {
+    pub fn from_minor(minor_units: i64, currency: Currency) -> Self {
+        Money {
+            minor_units,
+            currency,
+        }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Money::from_minor(0, currency)
+    }
+
+    /// Parses a plain decimal string such as `"19.99"` or `"-5"`. More decimal
+    /// places than the currency's exponent are rejected rather than rounded.
+    pub fn parse(amount: &str, currency: Currency) -> Result<Self, MoneyError> {
+        let invalid = || MoneyError::InvalidAmount(amount.to_string());
+        let (negative, digits) = match amount.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, amount),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty()
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+            || (digits.contains('.') && fraction.is_empty())
+        {
+            return Err(invalid());
+        }
+
+        let significant = fraction.trim_end_matches('0');
+        if significant.len() > currency.exponent as usize {
+            return Err(MoneyError::TooPrecise {
+                amount: amount.to_string(),
+                currency,
+            });
+        }
+
+        let whole: i64 = whole.parse().map_err(|_| MoneyError::Overflow)?;
+        let mut fraction_units: i64 = 0;
+        for (position, digit) in significant.bytes().enumerate() {
+            let weight = 10_i64.pow(currency.exponent - 1 - position as u32);
+            fraction_units += i64::from(digit - b'0') * weight;
+        }
+        let minor_units = whole
+            .checked_mul(currency.scale())
+            .and_then(|units| units.checked_add(fraction_units))
+            .ok_or(MoneyError::Overflow)?;
+        Ok(Money::from_minor(
+            if negative { -minor_units } else { minor_units },
+            currency,
+        ))
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    pub fn currency(&self) -> Currency {
+        self.currency
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.minor_units == 0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.minor_units < 0
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), MoneyError> {
+        if self.currency == other.currency {
+            Ok(())
+        } else {
+            Err(MoneyError::CurrencyMismatch(self.currency, other.currency))
+        }
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        self.minor_units
+            .checked_add(other.minor_units)
+            .map(|units| Money::from_minor(units, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    pub fn checked_sub(&self, other: &Money) -> Result<Money, MoneyError> {
+        self.same_currency(other)?;
+        self.minor_units
+            .checked_sub(other.minor_units)
+            .map(|units| Money::from_minor(units, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// Multiplies by a whole quantity, e.g. a unit price by a line quantity.
+    pub fn checked_mul(&self, quantity: i64) -> Result<Money, MoneyError> {
+        self.minor_units
+            .checked_mul(quantity)
+            .map(|units| Money::from_minor(units, self.currency))
+            .ok_or(MoneyError::Overflow)
+    }
+
+    /// Scales by `numerator / denominator`, rounding toward zero to a whole
+    /// minor unit, so a share never exceeds its exact value. Percentages are
+    /// `multiply_ratio(pct, 100)` and basis points `multiply_ratio(bp, 10_000)`.
+    pub fn multiply_ratio(&self, numerator: i64, denominator: i64) -> Result<Money, MoneyError> {
+        if denominator == 0 {
+            return Err(MoneyError::Overflow);
+        }
+        let product = i128::from(self.minor_units) * i128::from(numerator);
+        i64::try_from(product / i128::from(denominator))
+            .map(|units| Money::from_minor(units, self.currency))
+            .map_err(|_| MoneyError::Overflow)
+    }
+
+    /// Adds up amounts that must all be in `currency`.
+    pub fn sum<'a>(
+        currency: Currency,
+        amounts: impl IntoIterator<Item = &'a Money>,
+    ) -> Result<Money, MoneyError> {
+        amounts
+            .into_iter()
+            .try_fold(Money::zero(currency), |total, amount| {
+                total.checked_add(amount)
+            })
+    }
+
+    /// Orders two amounts of the same currency.
+    pub fn checked_cmp(&self, other: &Money) -> Result<Ordering, MoneyError> {
+        self.same_currency(other)?;
+        Ok(self.minor_units.cmp(&other.minor_units))
+    }
+
+    /// The amount as a decimal string with exactly the currency's exponent.
+    pub fn amount_string(&self) -> String {
+        let exponent = self.currency.exponent as usize;
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+        if exponent == 0 {
+            return format!("{}{}", sign, units);
+        }
+        let scale = self.currency.scale().unsigned_abs();
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            units / scale,
+            units % scale,
+            width = exponent
+        )
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount_string(), self.currency)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct MoneyJson {
+    amount: String,
+    currency: String,
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MoneyJson {
+            amount: self.amount_string(),
+            currency: self.currency.code.to_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = MoneyJson::deserialize(deserializer)?;
+        MoneyInput {
+            amount: raw.amount,
+            currency: raw.currency,
+        }
+        .to_money()
+        .map_err(de::Error::custom)
+    }
+}
+
+/// A price as a client sent it, before the amount is checked against the
+/// currency. Lets handlers report bad amounts as field errors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoneyInput {
+    pub amount: String,
+    pub currency: String,
+}
+
+impl MoneyInput {
+    pub fn to_money(&self) -> Result<Money, MoneyError> {
+        let currency = Currency::from_code(&self.currency)
+            .ok_or_else(|| MoneyError::UnsupportedCurrency(self.currency.clone()))?;
+        Money::parse(&self.amount, currency)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredMoney {
+    amount: i64,
+    currency: String,
+}
+
+impl StoredMoney {
+    fn from_money(money: &Money) -> Self {
+        StoredMoney {
+            amount: money.minor_units,
+            currency: money.currency.code.to_string(),
+        }
+    }
+
+    fn into_money<E: de::Error>(self) -> Result<Money, E> {
+        let currency = Currency::from_code(&self.currency)
+            .ok_or_else(|| E::custom(MoneyError::UnsupportedCurrency(self.currency)))?;
+        Ok(Money::from_minor(self.amount, currency))
+    }
+}
+
+/// `#[serde(with = "money::stored")]` for `Money` fields in MongoDB documents.
+pub mod stored {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(money: &Money, serializer: S) -> Result<S::Ok, S::Error> {
+        StoredMoney::from_money(money).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        StoredMoney::deserialize(deserializer)?.into_money()
+    }
+}
+
+/// `#[serde(with = "money::stored_option")]` for `Option<Money>` fields.
+pub mod stored_option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        money: &Option<Money>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        money
+            .as_ref()
+            .map(StoredMoney::from_money)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Money>, D::Error> {
+        Option::<StoredMoney>::deserialize(deserializer)?
+            .map(StoredMoney::into_money)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd() -> Currency {
+        Currency::from_code("USD").unwrap()
+    }
+
+    #[test]
+    fn parses_and_formats_with_the_currency_exponent() {
+        let jpy = Currency::from_code("JPY").unwrap();
+        let bhd = Currency::from_code("BHD").unwrap();
+
+        assert_eq!(Money::parse("19.99", usd()).unwrap().minor_units(), 1999);
+        assert_eq!(Money::parse("5", usd()).unwrap().amount_string(), "5.00");
+        assert_eq!(
+            Money::parse("-0.5", usd()).unwrap().amount_string(),
+            "-0.50"
+        );
+        assert_eq!(Money::parse("1200", jpy).unwrap().amount_string(), "1200");
+        assert_eq!(Money::parse("1.005", bhd).unwrap().minor_units(), 1005);
+        assert_eq!(Money::parse("2.500", usd()).unwrap().minor_units(), 250);
+    }
+
+    #[test]
+    fn rejects_malformed_or_too_precise_amounts() {
+        for amount in ["", "1.", ".5", "1e3", "1,00", "abc", "--1"] {
+            assert_eq!(
+                Money::parse(amount, usd()),
+                Err(MoneyError::InvalidAmount(amount.to_string())),
+                "{}",
+                amount
+            );
+        }
+        assert!(matches!(
+            Money::parse("1.999", usd()),
+            Err(MoneyError::TooPrecise { .. })
+        ));
+        assert!(matches!(
+            Money::parse("1.5", Currency::from_code("JPY").unwrap()),
+            Err(MoneyError::TooPrecise { .. })
+        ));
+    }
+
+    #[test]
+    fn arithmetic_is_checked() {
+        let a = Money::from_minor(150, usd());
+        let eur = Money::from_minor(100, Currency::from_code("EUR").unwrap());
+
+        assert_eq!(a.checked_add(&a).unwrap().minor_units(), 300);
+        assert_eq!(a.checked_sub(&a).unwrap(), Money::zero(usd()));
+        assert_eq!(a.checked_mul(3).unwrap().minor_units(), 450);
+        assert!(matches!(
+            a.checked_add(&eur),
+            Err(MoneyError::CurrencyMismatch(..))
+        ));
+        assert_eq!(
+            Money::from_minor(i64::MAX, usd()).checked_add(&a),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn ratios_round_toward_zero() {
+        let half = |units: i64| {
+            Money::from_minor(units, usd())
+                .multiply_ratio(1, 2)
+                .unwrap()
+                .minor_units()
+        };
+
+        assert_eq!(half(5), 2);
+        assert_eq!(half(-5), -2);
+        assert_eq!(half(4), 2);
+        assert_eq!(
+            Money::from_minor(1999, usd())
+                .multiply_ratio(1500, 10_000)
+                .unwrap()
+                .minor_units(),
+            299
+        );
+    }
+
+    #[test]
+    fn json_uses_string_amounts() {
+        let price = Money::from_minor(1999, usd());
+
+        let json = serde_json::to_value(price).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"amount": "19.99", "currency": "USD"})
+        );
+        assert_eq!(serde_json::from_value::<Money>(json).unwrap(), price);
+        assert!(serde_json::from_value::<Money>(
+            serde_json::json!({"amount": 19.99, "currency": "USD"})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn documents_store_minor_units() {
+        #[derive(Serialize, Deserialize)]
+        struct Doc {
+            #[serde(with = "stored")]
+            price: Money,
+        }
+
+        let doc = mongodb::bson::to_document(&Doc {
+            price: Money::from_minor(1999, usd()),
+        })
+        .unwrap();
+        assert_eq!(
+            doc,
+            mongodb::bson::doc! {"price": {"amount": 1999_i64, "currency": "USD"}}
+        );
+        let back: Doc = mongodb::bson::from_document(doc).unwrap();
+        assert_eq!(back.price.minor_units(), 1999);
+    }
+}
@@ -0,0 +1,293 @@
+use std::cmp::Ordering;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Document},
+    Collection,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{common_struct::AppError, constants};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Next,
+    Prev,
+}
+
+/// Opaque position in an `_id`-ordered listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub id: ObjectId,
+    pub direction: Direction,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let direction = match self.direction {
+            Direction::Next => "n",
+            Direction::Prev => "p",
+        };
+        URL_SAFE_NO_PAD.encode(format!("{}:{}", direction, self.id.to_hex()))
+    }
+
+    pub fn decode(value: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::invalid("cursor", "invalid_cursor", "Invalid cursor");
+        let raw = URL_SAFE_NO_PAD.decode(value).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (direction, id) = raw.split_once(':').ok_or_else(invalid)?;
+        let direction = match direction {
+            "n" => Direction::Next,
+            "p" => Direction::Prev,
+            _ => return Err(invalid()),
+        };
+        let id = ObjectId::parse_str(id).map_err(|_| invalid())?;
+        Ok(Cursor { id, direction })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PageRequest {
+    pub limit: i64,
+    pub offset: u64,
+    pub cursor: Option<Cursor>,
+    pub sort_field: String,
+    /// `1` for ascending, `-1` for descending.
+    pub sort_order: i32,
+}
+
+impl PageRequest {
+    /// Validates raw query parameters. `sort` is a field name from `sortable`,
+    /// optionally prefixed with `-` for descending order.
+    pub fn from_params(
+        limit: Option<i64>,
+        offset: Option<u64>,
+        cursor: Option<&str>,
+        sort: Option<&str>,
+        sortable: &[&str],
+    ) -> Result<Self, AppError> {
+        let limit = limit.unwrap_or(constants::DEFAULT_PAGE_SIZE);
+        if !(1..=constants::MAX_PAGE_SIZE).contains(&limit) {
+            return Err(AppError::invalid(
+                "limit",
+                "out_of_range",
+                format!("limit must be between 1 and {}", constants::MAX_PAGE_SIZE),
+            ));
+        }
+
+        let sort = sort.unwrap_or("_id");
+        let (sort_field, sort_order) = match sort.strip_prefix('-') {
+            Some(field) => (field, -1),
+            None => (sort, 1),
+        };
+        if !sortable.contains(&sort_field) {
+            return Err(AppError::invalid(
+                "sort",
+                "unsupported_sort",
+                format!("sort must be one of: {}", sortable.join(", ")),
+            ));
+        }
+
+        let cursor = cursor.map(Cursor::decode).transpose()?;
+        if cursor.is_some() {
+            if offset.is_some() {
+                return Err(AppError::invalid(
+                    "cursor",
+                    "conflicting_paging",
+                    "cursor and offset cannot be combined",
+                ));
+            }
+            if sort_field != "_id" {
+                return Err(AppError::invalid(
+                    "cursor",
+                    "unsupported_sort",
+                    "Cursor paging requires sorting by _id",
+                ));
+            }
+        }
+
+        Ok(PageRequest {
+            limit,
+            offset: offset.unwrap_or(0),
+            cursor,
+            sort_field: sort_field.to_string(),
+            sort_order,
+        })
+    }
+
+    fn is_keyset(&self) -> bool {
+        self.sort_field == "_id"
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub limit: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(rename = "nextCursor")]
+    pub next_cursor: Option<String>,
+    #[serde(rename = "prevCursor")]
+    pub prev_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            total: self.total,
+            limit: self.limit,
+            offset: self.offset,
+            next_cursor: self.next_cursor,
+            prev_cursor: self.prev_cursor,
+        }
+    }
+}
+
+impl PageRequest {
+    /// Whether the page is read forwards, and the effective sort order.
+    fn traversal(&self) -> (bool, i32) {
+        let forward = self
+            .cursor
+            .as_ref()
+            .is_none_or(|cursor| cursor.direction == Direction::Next);
+        let order = if forward {
+            self.sort_order
+        } else {
+            -self.sort_order
+        };
+        (forward, order)
+    }
+}
+
+/// Turns up to `limit + 1` fetched items into a page with cursors.
+fn finish_page<T>(
+    mut items: Vec<T>,
+    total: u64,
+    request: &PageRequest,
+    forward: bool,
+    id_of: fn(&T) -> Option<ObjectId>,
+) -> Page<T> {
+    let has_more = items.len() as i64 > request.limit;
+    items.truncate(request.limit as usize);
+    if !forward {
+        items.reverse();
+    }
+
+    let (mut next_cursor, mut prev_cursor) = (None, None);
+    if request.is_keyset() {
+        let cursor_at = |item: Option<&T>, direction| {
+            item.and_then(id_of)
+                .map(|id| Cursor { id, direction }.encode())
+        };
+        let more_after = if forward { has_more } else { !items.is_empty() };
+        let more_before = if forward {
+            request.cursor.is_some() || request.offset > 0
+        } else {
+            has_more
+        };
+        if more_after {
+            next_cursor = cursor_at(items.last(), Direction::Next);
+        }
+        if more_before {
+            prev_cursor = cursor_at(items.first(), Direction::Prev);
+        }
+    }
+
+    Page {
+        items,
+        total,
+        limit: request.limit,
+        offset: request.cursor.is_none().then_some(request.offset),
+        next_cursor,
+        prev_cursor,
+    }
+}
+
+/// Runs a paged `find`. Listings sorted on `_id` also get next/prev cursors;
+/// any other sort falls back to plain offset paging.
+pub async fn find_page<T>(
+    coll: &Collection<T>,
+    filter: Document,
+    projection: Option<Document>,
+    request: &PageRequest,
+    id_of: fn(&T) -> Option<ObjectId>,
+) -> mongodb::error::Result<Page<T>>
+where
+    T: DeserializeOwned + Send + Sync,
+{
+    let total = coll.count_documents(filter.clone()).await?;
+    let (forward, order) = request.traversal();
+
+    let mut query = filter;
+    if let Some(cursor) = &request.cursor {
+        let op = if order == 1 { "$gt" } else { "$lt" };
+        query = doc! {"$and": [query, {"_id": {op: cursor.id}}]};
+    }
+
+    let sort = if request.is_keyset() {
+        doc! {"_id": order}
+    } else {
+        doc! {request.sort_field.as_str(): order, "_id": order}
+    };
+
+    let mut found = coll
+        .find(query)
+        .sort(sort)
+        .skip(request.offset)
+        .limit(request.limit + 1)
+        .projection(projection.unwrap_or_default())
+        .await?;
+    let mut items = Vec::new();
+    while found.advance().await? {
+        items.push(found.deserialize_current()?);
+    }
+
+    Ok(finish_page(items, total, request, forward, id_of))
+}
+
+/// Pages an already filtered list with the same rules as `find_page`.
+/// `compare_field` orders two items by `request.sort_field`.
+pub fn page_in_memory<T>(
+    mut items: Vec<T>,
+    request: &PageRequest,
+    id_of: fn(&T) -> Option<ObjectId>,
+    compare_field: impl Fn(&T, &T) -> Ordering,
+) -> Page<T> {
+    let total = items.len() as u64;
+    let (forward, order) = request.traversal();
+
+    items.sort_by(|a, b| {
+        let ordering = if request.is_keyset() {
+            Ordering::Equal
+        } else {
+            compare_field(a, b)
+        }
+        .then_with(|| id_of(a).cmp(&id_of(b)));
+        if order == 1 {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    });
+    if let Some(cursor) = &request.cursor {
+        items.retain(|item| {
+            id_of(item).is_some_and(|id| {
+                if order == 1 {
+                    id > cursor.id
+                } else {
+                    id < cursor.id
+                }
+            })
+        });
+    }
+
+    let items = items
+        .into_iter()
+        .skip(request.offset as usize)
+        .take(request.limit as usize + 1)
+        .collect();
+    finish_page(items, total, request, forward, id_of)
+}
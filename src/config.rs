@@ -0,0 +1,454 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+use crate::{constants, payments::fake_payment_provider::DEV_WEBHOOK_SECRET};
+
+const DEFAULT_PORT: u16 = 3000;
+const DEFAULT_MIN_POOL_SIZE: u32 = 0;
+const DEFAULT_MAX_POOL_SIZE: u32 = 10;
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_SERVER_SELECTION_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_DELAY_MS: u64 = 500;
+
+// Each flag falls back to its environment variable, so flags win over env,
+// and env wins over the TOML file.
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "MongoDB CRUD API server")]
+pub struct Cli {
+    /// Path to an optional TOML config file.
+    #[arg(long, env = "APP_CONFIG_FILE")]
+    pub config: Option<PathBuf>,
+    #[arg(long, env = "BIND_ADDRESS")]
+    pub bind_address: Option<IpAddr>,
+    #[arg(long, env = "PORT")]
+    pub port: Option<u16>,
+    #[arg(long, env = "MONGO_DB_URI")]
+    pub mongo_uri: Option<String>,
+    #[arg(long, env = "DATABASE_NAME")]
+    pub database_name: Option<String>,
+    #[arg(long, env = "MONGO_MIN_POOL_SIZE")]
+    pub mongo_min_pool_size: Option<u32>,
+    #[arg(long, env = "MONGO_MAX_POOL_SIZE")]
+    pub mongo_max_pool_size: Option<u32>,
+    #[arg(long, env = "MONGO_CONNECT_TIMEOUT_SECS")]
+    pub mongo_connect_timeout_secs: Option<u64>,
+    #[arg(long, env = "MONGO_SERVER_SELECTION_TIMEOUT_SECS")]
+    pub mongo_server_selection_timeout_secs: Option<u64>,
+    #[arg(long, env = "REQUEST_TIMEOUT_SECS")]
+    pub request_timeout_secs: Option<u64>,
+    /// How many times to try reaching Mongo at startup before giving up.
+    #[arg(long, env = "MONGO_CONNECT_ATTEMPTS")]
+    pub mongo_connect_attempts: Option<u32>,
+    /// Delay before the first retry; it doubles after each failure.
+    #[arg(long, env = "MONGO_RETRY_DELAY_MS")]
+    pub mongo_retry_delay_ms: Option<u64>,
+    /// Drop indexes that are not declared in `db::schema`.
+    #[arg(long, env = "MONGO_DROP_UNKNOWN_INDEXES")]
+    pub drop_unknown_indexes: Option<bool>,
+    /// Where users and refresh tokens live. MongoDB is needed either way.
+    #[arg(long, env = "USER_STORE", value_enum)]
+    pub user_store: Option<UserStore>,
+    /// Serve the deprecated unversioned user paths (`/addUser`, `/getUser/:id`, ...).
+    #[arg(long, env = "LEGACY_ROUTES")]
+    pub legacy_routes: Option<bool>,
+    /// Signs and verifies access tokens.
+    #[arg(long, env = "JWT_SECRET", hide_env_values = true)]
+    pub jwt_secret: Option<String>,
+    #[arg(long, env = "PAYMENT_PROVIDER", value_enum)]
+    pub payment_provider: Option<PaymentProviderKind>,
+    #[arg(long, env = "PAYMENT_WEBHOOK_SECRET", hide_env_values = true)]
+    pub payment_webhook_secret: Option<String>,
+    /// Development and tests only: permit the fake payment provider and its
+    /// built-in webhook secret.
+    #[arg(long, env = "ALLOW_FAKE_PAYMENTS")]
+    pub allow_fake_payments: Option<bool>,
+}
+
+/// Where user records and their refresh tokens live. `memory` loses them on
+/// restart and is meant for demos; everything else still needs MongoDB.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStore {
+    #[default]
+    Mongo,
+    Memory,
+}
+
+/// Who takes payments. With `none` the payment routes answer 503; `fake`
+/// approves charges without moving money and needs `allow_fake_payments`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentProviderKind {
+    #[default]
+    None,
+    Fake,
+}
+
+/// The TOML file uses the same keys as the long flags, in snake_case.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub bind_address: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub mongo_uri: Option<String>,
+    pub database_name: Option<String>,
+    pub mongo_min_pool_size: Option<u32>,
+    pub mongo_max_pool_size: Option<u32>,
+    pub mongo_connect_timeout_secs: Option<u64>,
+    pub mongo_server_selection_timeout_secs: Option<u64>,
+    pub request_timeout_secs: Option<u64>,
+    pub mongo_connect_attempts: Option<u32>,
+    pub mongo_retry_delay_ms: Option<u64>,
+    pub drop_unknown_indexes: Option<bool>,
+    pub user_store: Option<UserStore>,
+    pub legacy_routes: Option<bool>,
+    pub jwt_secret: Option<String>,
+    pub payment_provider: Option<PaymentProviderKind>,
+    pub payment_webhook_secret: Option<String>,
+    pub allow_fake_payments: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub mongo_uri: String,
+    pub database_name: String,
+    pub mongo_min_pool_size: u32,
+    pub mongo_max_pool_size: u32,
+    pub mongo_connect_timeout: Duration,
+    pub mongo_server_selection_timeout: Duration,
+    pub request_timeout: Duration,
+    pub mongo_connect_attempts: u32,
+    pub mongo_retry_delay: Duration,
+    pub drop_unknown_indexes: bool,
+    pub user_store: UserStore,
+    pub legacy_routes: bool,
+    pub jwt_secret: String,
+    pub payment_provider: PaymentProviderKind,
+    /// Shared with the provider to sign webhooks.
+    pub payment_webhook_secret: String,
+}
+
+/// Every problem found while loading the config, reported together.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub problems: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for problem in &self.problems {
+            writeln!(f, "  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl ConfigError {
+    fn single(problem: String) -> Self {
+        ConfigError {
+            problems: vec![problem],
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Result<FileConfig, ConfigError> {
+    let contents = std::fs::read_to_string(path).map_err(|error| {
+        ConfigError::single(format!("cannot read {}: {}", path.display(), error))
+    })?;
+    toml::from_str(&contents)
+        .map_err(|error| ConfigError::single(format!("invalid {}: {}", path.display(), error)))
+}
+
+fn seconds(problems: &mut Vec<String>, name: &str, value: Option<u64>, default: u64) -> Duration {
+    let secs = value.unwrap_or(default);
+    if secs == 0 {
+        problems.push(format!("{} must be greater than 0", name));
+    }
+    Duration::from_secs(secs)
+}
+
+impl Config {
+    /// Reads flags, env vars and the optional TOML file, in that priority.
+    pub fn load() -> Result<Self, ConfigError> {
+        let cli = Cli::parse();
+        let file = match &cli.config {
+            Some(path) => read_file(path)?,
+            None => FileConfig::default(),
+        };
+        Config::from_sources(cli, file)
+    }
+
+    pub fn from_sources(cli: Cli, file: FileConfig) -> Result<Self, ConfigError> {
+        let mut problems = Vec::new();
+
+        let port = cli.port.or(file.port).unwrap_or(DEFAULT_PORT);
+        if port == 0 {
+            problems.push("port must be between 1 and 65535".to_string());
+        }
+
+        let mongo_uri = cli.mongo_uri.or(file.mongo_uri).unwrap_or_default();
+        if mongo_uri.is_empty() {
+            problems.push(
+                "mongo_uri is required (set MONGO_DB_URI, --mongo-uri or mongo_uri in the config file)"
+                    .to_string(),
+            );
+        } else if !mongo_uri.starts_with("mongodb://") && !mongo_uri.starts_with("mongodb+srv://") {
+            problems.push("mongo_uri must start with mongodb:// or mongodb+srv://".to_string());
+        }
+
+        let database_name = cli
+            .database_name
+            .or(file.database_name)
+            .unwrap_or_else(|| constants::DEFAULT_DBNAME.to_string());
+        if database_name.is_empty()
+            || database_name
+                .chars()
+                .any(|ch| "/\\. \"$".contains(ch) || ch == '\0')
+        {
+            problems.push(format!(
+                "database_name {:?} is not a valid MongoDB database name",
+                database_name
+            ));
+        }
+
+        let mongo_min_pool_size = cli
+            .mongo_min_pool_size
+            .or(file.mongo_min_pool_size)
+            .unwrap_or(DEFAULT_MIN_POOL_SIZE);
+        let mongo_max_pool_size = cli
+            .mongo_max_pool_size
+            .or(file.mongo_max_pool_size)
+            .unwrap_or(DEFAULT_MAX_POOL_SIZE);
+        if mongo_max_pool_size == 0 {
+            problems.push("mongo_max_pool_size must be greater than 0".to_string());
+        }
+        if mongo_min_pool_size > mongo_max_pool_size {
+            problems.push(format!(
+                "mongo_min_pool_size ({}) cannot exceed mongo_max_pool_size ({})",
+                mongo_min_pool_size, mongo_max_pool_size
+            ));
+        }
+
+        let mongo_connect_timeout = seconds(
+            &mut problems,
+            "mongo_connect_timeout_secs",
+            cli.mongo_connect_timeout_secs
+                .or(file.mongo_connect_timeout_secs),
+            DEFAULT_CONNECT_TIMEOUT_SECS,
+        );
+        let mongo_server_selection_timeout = seconds(
+            &mut problems,
+            "mongo_server_selection_timeout_secs",
+            cli.mongo_server_selection_timeout_secs
+                .or(file.mongo_server_selection_timeout_secs),
+            DEFAULT_SERVER_SELECTION_TIMEOUT_SECS,
+        );
+        let request_timeout = seconds(
+            &mut problems,
+            "request_timeout_secs",
+            cli.request_timeout_secs.or(file.request_timeout_secs),
+            DEFAULT_REQUEST_TIMEOUT_SECS,
+        );
+
+        let mongo_connect_attempts = cli
+            .mongo_connect_attempts
+            .or(file.mongo_connect_attempts)
+            .unwrap_or(DEFAULT_CONNECT_ATTEMPTS);
+        if mongo_connect_attempts == 0 {
+            problems.push("mongo_connect_attempts must be at least 1".to_string());
+        }
+        let mongo_retry_delay = Duration::from_millis(
+            cli.mongo_retry_delay_ms
+                .or(file.mongo_retry_delay_ms)
+                .unwrap_or(DEFAULT_RETRY_DELAY_MS),
+        );
+
+        let jwt_secret = cli.jwt_secret.or(file.jwt_secret).unwrap_or_default();
+        if jwt_secret.is_empty() {
+            problems.push(
+                "jwt_secret is required (set JWT_SECRET, --jwt-secret or jwt_secret in the config file)"
+                    .to_string(),
+            );
+        }
+
+        let payment_provider = cli
+            .payment_provider
+            .or(file.payment_provider)
+            .unwrap_or_default();
+        let allow_fake_payments = cli
+            .allow_fake_payments
+            .or(file.allow_fake_payments)
+            .unwrap_or(false);
+        let payment_webhook_secret = cli
+            .payment_webhook_secret
+            .or(file.payment_webhook_secret)
+            .filter(|secret| !secret.is_empty());
+        if !allow_fake_payments {
+            if payment_provider == PaymentProviderKind::Fake {
+                problems.push(
+                    "payment_provider fake approves charges without taking money; set allow_fake_payments for development or tests"
+                        .to_string(),
+                );
+            }
+            if payment_webhook_secret.as_deref() == Some(DEV_WEBHOOK_SECRET) {
+                problems.push(
+                    "payment_webhook_secret is the public development secret; choose another"
+                        .to_string(),
+                );
+            }
+            if payment_provider != PaymentProviderKind::None && payment_webhook_secret.is_none() {
+                problems
+                    .push("payment_webhook_secret is required with a payment provider".to_string());
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+
+        Ok(Config {
+            bind_address: cli
+                .bind_address
+                .or(file.bind_address)
+                .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            port,
+            mongo_uri,
+            database_name,
+            mongo_min_pool_size,
+            mongo_max_pool_size,
+            mongo_connect_timeout,
+            mongo_server_selection_timeout,
+            request_timeout,
+            mongo_connect_attempts,
+            mongo_retry_delay,
+            drop_unknown_indexes: cli
+                .drop_unknown_indexes
+                .or(file.drop_unknown_indexes)
+                .unwrap_or(false),
+            user_store: cli.user_store.or(file.user_store).unwrap_or_default(),
+            legacy_routes: cli.legacy_routes.or(file.legacy_routes).unwrap_or(true),
+            jwt_secret,
+            payment_provider,
+            payment_webhook_secret: payment_webhook_secret
+                .unwrap_or_else(|| DEV_WEBHOOK_SECRET.to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minimal() -> FileConfig {
+        FileConfig {
+            mongo_uri: Some("mongodb://127.0.0.1:1".to_string()),
+            jwt_secret: Some("s3cret".to_string()),
+            ..FileConfig::default()
+        }
+    }
+
+    fn file(provider: PaymentProviderKind, secret: Option<&str>, allow_fake: bool) -> FileConfig {
+        FileConfig {
+            payment_provider: Some(provider),
+            payment_webhook_secret: secret.map(str::to_string),
+            allow_fake_payments: Some(allow_fake),
+            ..minimal()
+        }
+    }
+
+    #[test]
+    fn a_minimal_file_fills_in_defaults() {
+        let config = Config::from_sources(Cli::default(), minimal()).unwrap();
+
+        assert_eq!(config.port, DEFAULT_PORT);
+        assert_eq!(config.database_name, constants::DEFAULT_DBNAME);
+        assert_eq!(config.user_store, UserStore::Mongo);
+        assert_eq!(config.payment_provider, PaymentProviderKind::None);
+        assert_eq!(config.jwt_secret, "s3cret");
+    }
+
+    #[test]
+    fn flags_win_over_the_file() {
+        let cli = Cli {
+            port: Some(8080),
+            jwt_secret: Some("from-flag".to_string()),
+            ..Cli::default()
+        };
+        let file = FileConfig {
+            port: Some(9090),
+            ..minimal()
+        };
+
+        let config = Config::from_sources(cli, file).unwrap();
+
+        assert_eq!(config.port, 8080);
+        assert_eq!(config.jwt_secret, "from-flag");
+    }
+
+    #[test]
+    fn a_missing_or_empty_jwt_secret_is_refused() {
+        for jwt_secret in [None, Some(String::new())] {
+            let file = FileConfig {
+                jwt_secret,
+                ..minimal()
+            };
+
+            let error = Config::from_sources(Cli::default(), file).unwrap_err();
+
+            assert_eq!(error.problems.len(), 1);
+            assert!(error.problems[0].contains("jwt_secret"));
+        }
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let file = FileConfig {
+            mongo_uri: Some("http://example.com".to_string()),
+            port: Some(0),
+            mongo_min_pool_size: Some(20),
+            request_timeout_secs: Some(0),
+            jwt_secret: None,
+            ..FileConfig::default()
+        };
+
+        let error = Config::from_sources(Cli::default(), file).unwrap_err();
+
+        assert_eq!(error.problems.len(), 5, "{}", error);
+    }
+
+    #[test]
+    fn the_fake_provider_needs_the_development_flag() {
+        let refused = Config::from_sources(
+            Cli::default(),
+            file(PaymentProviderKind::Fake, Some("s3cret"), false),
+        );
+        let allowed =
+            Config::from_sources(Cli::default(), file(PaymentProviderKind::Fake, None, true));
+
+        assert!(refused.is_err());
+        assert_eq!(allowed.unwrap().payment_webhook_secret, DEV_WEBHOOK_SECRET);
+    }
+
+    #[test]
+    fn the_public_webhook_secret_is_refused_outside_development() {
+        let error = Config::from_sources(
+            Cli::default(),
+            file(PaymentProviderKind::None, Some(DEV_WEBHOOK_SECRET), false),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.problems.len(), 1);
+        assert!(error.problems[0].contains("payment_webhook_secret"));
+    }
+}
@@ -1 +1,15 @@
-pub const DBNAME: &str = "NodeJsPre";
+pub const DEFAULT_DBNAME: &str = "NodeJsPre";
+/// Access tokens carry the user's roles and are not re-checked against the
+/// store, so a role change or revoked session takes effect within this long,
+/// when the token is next refreshed. Keep it short.
+pub const ACCESS_TOKEN_TTL_SECS: i64 = 15 * 60;
+pub const REFRESH_TOKEN_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+pub const MAX_PAGE_SIZE: i64 = 100;
+/// How long a stock reservation holds its units before the sweeper returns them.
+pub const RESERVATION_TTL_SECS: i64 = 15 * 60;
+pub const RESERVATION_SWEEP_INTERVAL_SECS: u64 = 30;
+/// Carts untouched for this long are deleted by a TTL index.
+pub const CART_IDLE_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+/// The TTL index deletes reservation documents this long after they expire.
+pub const RESERVATION_PURGE_AFTER_SECS: u64 = 24 * 60 * 60;
@@ -0,0 +1,220 @@
+use crate::{
+    auth::{
+        extractor::AuthUser,
+        password::{self, PasswordCheck},
+        permissions::Role,
+        tokens,
+    },
+    common_struct::{success_response, ApiResult, AppError},
+    constants,
+    controllers::cart_controller::merge_anonymous_cart,
+    models::{
+        auth_module::{LoginRequest, RefreshRequest, RefreshToken, TokenResponse},
+        cart_module::CART_TOKEN_HEADER,
+        user_module::{User, UserResponse},
+    },
+    repositories::UserChanges,
+    state::AppState,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+async fn issue_tokens(
+    state: &AppState,
+    user: &User,
+    user_id: ObjectId,
+    family_id: ObjectId,
+) -> Result<TokenResponse, AppError> {
+    let email = user.email.as_deref().unwrap_or_default();
+    // Documents created before roles existed are treated as regular users.
+    let roles = user.roles.clone().unwrap_or_else(|| vec![Role::User]);
+    let access_token = tokens::issue_access_token(&state.jwt_keys, &user_id, email, &roles)?;
+
+    let refresh_token = tokens::generate_opaque_token();
+    let now = DateTime::now();
+    let record = RefreshToken {
+        id: None,
+        user_id,
+        token_hash: tokens::hash_opaque_token(&refresh_token),
+        family_id,
+        expires_at: DateTime::from_millis(
+            now.timestamp_millis() + constants::REFRESH_TOKEN_TTL_SECS * 1000,
+        ),
+        revoked_at: None,
+        created_at: now,
+    };
+
+    state.refresh_tokens.insert(record).await?;
+
+    Ok(TokenResponse {
+        access_token,
+        refresh_token,
+        token_type: "Bearer".to_string(),
+        expires_in: constants::ACCESS_TOKEN_TTL_SECS,
+    })
+}
+
+async fn revoke_family(state: &AppState, family_id: ObjectId) {
+    if let Err(error) = state.refresh_tokens.revoke_family(family_id).await {
+        println!("Failed to revoke token family {}: {}", family_id, error);
+    }
+}
+
+fn unauthorized(message: &str) -> AppError {
+    AppError::Unauthorized(message.to_string())
+}
+
+fn required_refresh_token(payload: RefreshRequest) -> Result<String, AppError> {
+    payload
+        .refresh_token
+        .ok_or_else(|| AppError::invalid("refreshToken", "required", "refreshToken is required"))
+}
+
+pub async fn login(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<LoginRequest>,
+) -> ApiResult {
+    let (email, plain) = match (payload.email, payload.password) {
+        (Some(email), Some(plain)) => (email, plain),
+        (email, _) => {
+            let field = if email.is_none() { "email" } else { "password" };
+            return Err(AppError::invalid(
+                field,
+                "required",
+                format!("{} is required", field),
+            ));
+        }
+    };
+
+    let user = state
+        .users
+        .find_by_email(&email)
+        .await?
+        .ok_or_else(|| unauthorized("Invalid email or password"))?;
+
+    let (user_id, stored) = match (user.id, &user.password) {
+        (Some(user_id), Some(stored)) => (user_id, stored.clone()),
+        _ => return Err(unauthorized("Invalid email or password")),
+    };
+
+    match password::verify_password(plain, stored).await? {
+        PasswordCheck::Match => {}
+        PasswordCheck::MatchNeedsRehash(hash) => {
+            // The login is valid either way; a failed upgrade is retried next time.
+            let changes = UserChanges {
+                password: Some(hash),
+                ..UserChanges::default()
+            };
+            if let Err(error) = state.users.update(user_id, changes).await {
+                println!("Failed to upgrade password hash for {}: {}", user_id, error);
+            }
+        }
+        PasswordCheck::Mismatch => return Err(unauthorized("Invalid email or password")),
+    }
+
+    let tokens = issue_tokens(&state, &user, user_id, ObjectId::new()).await?;
+    if let Some(cart_token) = headers
+        .get(CART_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+    {
+        // A failed merge leaves the anonymous cart in place; it is not worth failing the login.
+        match merge_anonymous_cart(&state, cart_token, user_id).await {
+            Ok(0) => {}
+            Ok(count) => println!("Merged {} cart line(s) into {}'s cart", count, user_id),
+            Err(error) => println!("Failed to merge cart for {}: {}", user_id, error),
+        }
+    }
+    println!("User Logged In With ID: {}", user_id);
+    Ok(success_response(
+        StatusCode::OK,
+        "Logged in successfully",
+        Some(tokens),
+    ))
+}
+
+pub async fn refresh(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshRequest>,
+) -> ApiResult {
+    let presented = required_refresh_token(payload)?;
+
+    let stored = state
+        .refresh_tokens
+        .find_by_hash(&tokens::hash_opaque_token(&presented))
+        .await?
+        .ok_or_else(|| unauthorized("Invalid refresh token"))?;
+
+    if stored.revoked_at.is_some() {
+        // A rotated token was presented again, so assume it leaked.
+        revoke_family(&state, stored.family_id).await;
+        return Err(unauthorized("Refresh token has been revoked"));
+    }
+    if stored.expires_at < DateTime::now() {
+        return Err(unauthorized("Refresh token has expired"));
+    }
+
+    // Claim the token atomically so two concurrent refreshes cannot both rotate it.
+    let claimed = match stored.id {
+        Some(id) => state.refresh_tokens.claim(id).await?,
+        None => false,
+    };
+    if !claimed {
+        revoke_family(&state, stored.family_id).await;
+        return Err(unauthorized("Refresh token has been revoked"));
+    }
+
+    let user = state
+        .users
+        .find_by_id(stored.user_id)
+        .await?
+        .ok_or_else(|| unauthorized("User no longer exists"))?;
+
+    let tokens = issue_tokens(&state, &user, stored.user_id, stored.family_id).await?;
+    Ok(success_response(
+        StatusCode::OK,
+        "Token refreshed successfully",
+        Some(tokens),
+    ))
+}
+
+pub async fn logout(
+    State(state): State<AppState>,
+    Json(payload): Json<RefreshRequest>,
+) -> ApiResult {
+    let presented = required_refresh_token(payload)?;
+
+    // Logging out with an unknown token is not an error worth reporting.
+    if let Some(stored) = state
+        .refresh_tokens
+        .find_by_hash(&tokens::hash_opaque_token(&presented))
+        .await?
+    {
+        revoke_family(&state, stored.family_id).await;
+    }
+
+    Ok(success_response::<()>(
+        StatusCode::OK,
+        "Logged out successfully",
+        None,
+    ))
+}
+
+pub async fn me(State(state): State<AppState>, auth: AuthUser) -> ApiResult {
+    let user = state
+        .users
+        .find_by_id(auth.id)
+        .await?
+        .ok_or_else(|| unauthorized("User no longer exists"))?;
+
+    println!("Current User: {}", auth.email);
+    Ok(success_response(
+        StatusCode::OK,
+        "User retrieved successfully",
+        Some(UserResponse::from(user)),
+    ))
+}
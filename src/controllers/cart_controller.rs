@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+
+use crate::{
+    auth::{extractor::AuthUser, tokens},
+    common_struct::{app_error::is_duplicate_key, success_response, ApiResult, AppError},
+    controllers::promotion_controller,
+    db,
+    models::{
+        cart_module::{
+            price_items, Cart, CartItem, CartItemPayload, CartQuantityPayload, CartResponse,
+            CART_TOKEN_HEADER, MAX_LINE_QUANTITY,
+        },
+        product_module::Product,
+        promotion_module::CouponPayload,
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::bson::{doc, oid::ObjectId, DateTime, Document};
+
+/// Whose cart a request is about: the signed-in user's, or the anonymous
+/// cart named by the `X-Cart-Token` header.
+enum CartOwner {
+    User(ObjectId),
+    Anonymous { token: String, issued: bool },
+}
+
+impl CartOwner {
+    /// A bearer token wins over a cart token; anonymous carts are merged at login.
+    /// Cart tokens are only ever made here, so one that names no cart is
+    /// refused rather than starting a cart under a token the client picked.
+    async fn resolve(
+        state: &AppState,
+        auth: Option<AuthUser>,
+        headers: &HeaderMap,
+    ) -> Result<Option<CartOwner>, AppError> {
+        if let Some(auth) = auth {
+            return Ok(Some(CartOwner::User(auth.id)));
+        }
+        let Some(token) = headers
+            .get(CART_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|token| !token.is_empty())
+        else {
+            return Ok(None);
+        };
+        let owner = CartOwner::Anonymous {
+            token: token.to_string(),
+            issued: false,
+        };
+        if state.carts.count_documents(owner.filter()).limit(1).await? == 0 {
+            return Err(unknown_cart());
+        }
+        Ok(Some(owner))
+    }
+
+    /// Like `resolve`, but starts a new anonymous cart when there is no owner.
+    async fn resolve_or_issue(
+        state: &AppState,
+        auth: Option<AuthUser>,
+        headers: &HeaderMap,
+    ) -> Result<CartOwner, AppError> {
+        Ok(CartOwner::resolve(state, auth, headers)
+            .await?
+            .unwrap_or_else(|| CartOwner::Anonymous {
+                token: tokens::generate_opaque_token(),
+                issued: true,
+            }))
+    }
+
+    /// Whether adding to this owner's cart may create it: always for a user,
+    /// and for an anonymous owner only under a token this request issued.
+    fn may_create(&self) -> bool {
+        !matches!(self, CartOwner::Anonymous { issued: false, .. })
+    }
+
+    fn filter(&self) -> Document {
+        match self {
+            CartOwner::User(id) => doc! {"userId": id},
+            CartOwner::Anonymous { token, .. } => {
+                doc! {"anonymousTokenHash": tokens::hash_opaque_token(token)}
+            }
+        }
+    }
+
+    /// The token to hand back when this request created the anonymous cart.
+    fn issued_token(&self) -> Option<&str> {
+        match self {
+            CartOwner::Anonymous {
+                token,
+                issued: true,
+            } => Some(token),
+            _ => None,
+        }
+    }
+}
+
+fn unknown_cart() -> AppError {
+    AppError::invalid(
+        CART_TOKEN_HEADER,
+        "unknown_cart",
+        "No cart matches this cart token",
+    )
+}
+
+fn item_not_in_cart(product_id: &str) -> AppError {
+    AppError::NotFound(format!("Product {} is not in the cart", product_id))
+}
+
+fn parse_product_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid(
+            "productId",
+            "invalid_id",
+            format!("Invalid ID format: {}", params),
+        )
+    })
+}
+
+fn line_full() -> AppError {
+    AppError::invalid(
+        "quantity",
+        "out_of_range",
+        format!(
+            "A cart line can hold at most {} units of a product",
+            MAX_LINE_QUANTITY
+        ),
+    )
+}
+
+/// Adds `quantity` of a product to the cart matching `owner`, creating the
+/// cart if needed and allowed. An existing line is incremented rather than
+/// duplicated, and only while it stays within `MAX_LINE_QUANTITY`.
+async fn add_item(
+    state: &AppState,
+    cart_owner: &CartOwner,
+    product_id: ObjectId,
+    quantity: i64,
+) -> Result<(), AppError> {
+    let owner = &cart_owner.filter();
+    let increment = || async {
+        let mut filter = owner.clone();
+        filter.insert(
+            "items",
+            doc! {"$elemMatch": {
+                "productId": product_id,
+                "quantity": {"$lte": MAX_LINE_QUANTITY - quantity},
+            }},
+        );
+        state
+            .carts
+            .update_one(
+                filter,
+                doc! {
+                    "$inc": {"items.$.quantity": quantity},
+                    "$set": {"updatedAt": DateTime::now()},
+                },
+            )
+            .await
+    };
+    if increment().await?.matched_count > 0 {
+        return Ok(());
+    }
+
+    let mut filter = owner.clone();
+    filter.insert("items.productId", doc! {"$ne": product_id});
+    let item = mongodb::bson::to_bson(&CartItem {
+        product_id,
+        quantity,
+        added_at: DateTime::now(),
+    })
+    .map_err(|error| AppError::Internal(format!("Failed to encode cart item: {}", error)))?;
+    let pushed = state
+        .carts
+        .update_one(
+            filter,
+            doc! {
+                "$push": {"items": item},
+                "$set": {"updatedAt": DateTime::now()},
+                // The owner field is copied from the filter on insert.
+                "$setOnInsert": {"createdAt": DateTime::now()},
+            },
+        )
+        .upsert(cart_owner.may_create())
+        .await;
+    match pushed {
+        Ok(pushed) if pushed.matched_count > 0 || pushed.upserted_id.is_some() => Ok(()),
+        // The line exists, or someone added it in between; add to it instead,
+        // unless that takes it over the limit.
+        Ok(_) => {
+            if increment().await?.matched_count > 0 {
+                return Ok(());
+            }
+            // Without an upsert, no match may also mean the cart went away.
+            if state.carts.count_documents(owner.clone()).limit(1).await? == 0 {
+                return Err(unknown_cart());
+            }
+            Err(line_full())
+        }
+        Err(error) if is_duplicate_key(&error) => {
+            if increment().await?.matched_count == 0 {
+                return Err(line_full());
+            }
+            Ok(())
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// The catalogue entries for `items`, by id.
+async fn load_products(
+    state: &AppState,
+    items: &[CartItem],
+) -> Result<HashMap<ObjectId, Product>, AppError> {
+    let ids: Vec<ObjectId> = items.iter().map(|item| item.product_id).collect();
+    let mut products = HashMap::new();
+    if !ids.is_empty() {
+        let mut cursor = state.products.find(doc! {"_id": {"$in": ids}}).await?;
+        while cursor.advance().await? {
+            let product = cursor.deserialize_current()?;
+            if let Some(id) = product.id {
+                products.insert(id, product);
+            }
+        }
+    }
+    Ok(products)
+}
+
+async fn view(state: &AppState, cart: Option<Cart>) -> Result<CartResponse, AppError> {
+    let items = cart
+        .as_ref()
+        .map(|cart| cart.items.as_slice())
+        .unwrap_or_default();
+    let coupon_codes = cart
+        .as_ref()
+        .map(|cart| cart.coupon_codes.clone())
+        .unwrap_or_default();
+
+    let products = load_products(state, items).await?;
+    let priced = price_items(items, &products)?;
+    let coupons = promotion_controller::evaluate(
+        state,
+        None,
+        &coupon_codes,
+        &priced,
+        &products,
+        cart.as_ref().and_then(|cart| cart.user_id),
+    )
+    .await?;
+
+    Ok(CartResponse {
+        id: cart.as_ref().and_then(|cart| cart.id).map(|id| id.to_hex()),
+        priced,
+        coupon_codes,
+        coupons,
+        updated_at: cart.and_then(|cart| cart.updated_at.try_to_rfc3339_string().ok()),
+    })
+}
+
+/// Reads the cart back after a change and reports it, handing out the cart
+/// token when this request created an anonymous cart.
+async fn respond(state: &AppState, owner: &CartOwner, message: &str) -> Result<Response, AppError> {
+    let cart = state.carts.find_one(owner.filter()).await?;
+    let (status, body) = success_response(StatusCode::OK, message, Some(view(state, cart).await?));
+    let mut response = (status, body).into_response();
+    if let Some(token) = owner.issued_token() {
+        if let Ok(value) = token.parse() {
+            response.headers_mut().insert(CART_TOKEN_HEADER, value);
+        }
+    }
+    Ok(response)
+}
+
+pub async fn get_cart(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    headers: HeaderMap,
+) -> ApiResult {
+    let cart = match CartOwner::resolve(&state, auth, &headers).await? {
+        Some(owner) => state.carts.find_one(owner.filter()).await?,
+        None => None,
+    };
+    Ok(success_response(
+        StatusCode::OK,
+        "Cart retrieved successfully",
+        Some(view(&state, cart).await?),
+    ))
+}
+
+pub async fn add_cart_item(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    headers: HeaderMap,
+    Json(payload): Json<CartItemPayload>,
+) -> Result<Response, AppError> {
+    payload.validate_new()?;
+    let product_id = parse_product_id(payload.product_id.as_deref().unwrap_or_default())?;
+    if state
+        .products
+        .find_one(doc! {"_id": product_id})
+        .await?
+        .is_none()
+    {
+        return Err(AppError::invalid(
+            "productId",
+            "unknown_product",
+            format!("No product with ID: {}", product_id),
+        ));
+    }
+
+    let owner = CartOwner::resolve_or_issue(&state, auth, &headers).await?;
+    add_item(
+        &state,
+        &owner,
+        product_id,
+        payload.quantity.unwrap_or_default(),
+    )
+    .await?;
+    respond(&state, &owner, "Item added to cart").await
+}
+
+pub async fn update_cart_item(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    headers: HeaderMap,
+    Path(params): Path<String>,
+    Json(payload): Json<CartQuantityPayload>,
+) -> Result<Response, AppError> {
+    let product_id = parse_product_id(&params)?;
+    payload.validate_new()?;
+    let owner = CartOwner::resolve(&state, auth, &headers)
+        .await?
+        .ok_or_else(|| item_not_in_cart(&params))?;
+
+    let mut filter = owner.filter();
+    filter.insert("items.productId", product_id);
+    let updated = state
+        .carts
+        .update_one(
+            filter,
+            doc! {"$set": {
+                "items.$.quantity": payload.quantity.unwrap_or_default(),
+                "updatedAt": DateTime::now(),
+            }},
+        )
+        .await?;
+    if updated.matched_count == 0 {
+        return Err(item_not_in_cart(&params));
+    }
+    respond(&state, &owner, "Cart item updated").await
+}
+
+pub async fn remove_cart_item(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    headers: HeaderMap,
+    Path(params): Path<String>,
+) -> Result<Response, AppError> {
+    let product_id = parse_product_id(&params)?;
+    let owner = CartOwner::resolve(&state, auth, &headers)
+        .await?
+        .ok_or_else(|| item_not_in_cart(&params))?;
+
+    let removed = state
+        .carts
+        .update_one(
+            owner.filter(),
+            doc! {
+                "$pull": {"items": {"productId": product_id}},
+                "$set": {"updatedAt": DateTime::now()},
+            },
+        )
+        .await?;
+    if removed.modified_count == 0 {
+        return Err(item_not_in_cart(&params));
+    }
+    respond(&state, &owner, "Cart item removed").await
+}
+
+pub async fn clear_cart(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(owner) = CartOwner::resolve(&state, auth, &headers).await? {
+        state
+            .carts
+            .update_one(
+                owner.filter(),
+                doc! {"$set": {"items": [], "couponCodes": [], "updatedAt": DateTime::now()}},
+            )
+            .await?;
+        return respond(&state, &owner, "Cart cleared").await;
+    }
+    let data = view(&state, None).await?;
+    Ok(success_response(StatusCode::OK, "Cart cleared", Some(data)).into_response())
+}
+
+fn cart_empty() -> AppError {
+    AppError::Conflict {
+        code: "cart_empty",
+        message: "The cart is empty".to_string(),
+    }
+}
+
+fn coupon_not_in_cart(code: &str) -> AppError {
+    AppError::NotFound(format!("Coupon {} is not on the cart", code))
+}
+
+/// Adds a coupon to the cart if it can be used with what is in it and with
+/// the coupons already there, and answers with the itemized discounts.
+pub async fn apply_coupon(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    headers: HeaderMap,
+    Json(payload): Json<CouponPayload>,
+) -> Result<Response, AppError> {
+    payload.validate_new()?;
+    let code = payload.code.unwrap_or_default().to_uppercase();
+    let owner = CartOwner::resolve(&state, auth, &headers)
+        .await?
+        .ok_or_else(cart_empty)?;
+    let cart = state
+        .carts
+        .find_one(owner.filter())
+        .await?
+        .filter(|cart| !cart.items.is_empty())
+        .ok_or_else(cart_empty)?;
+    if cart.coupon_codes.contains(&code) {
+        return Err(AppError::Conflict {
+            code: "coupon_already_applied",
+            message: format!("Coupon {} is already on the cart", code),
+        });
+    }
+
+    let products = load_products(&state, &cart.items).await?;
+    let priced = price_items(&cart.items, &products)?;
+    let mut codes = cart.coupon_codes.clone();
+    codes.push(code.clone());
+    let coupons =
+        promotion_controller::evaluate(&state, None, &codes, &priced, &products, cart.user_id)
+            .await?;
+    if let Some(rejected) = coupons
+        .rejected
+        .into_iter()
+        .find(|rejected| rejected.code == code)
+    {
+        return Err(promotion_controller::rejection(rejected));
+    }
+
+    // Matching on updatedAt stops two coupons that do not stack being added at once.
+    let mut filter = owner.filter();
+    filter.insert("updatedAt", cart.updated_at);
+    let updated = state
+        .carts
+        .update_one(
+            filter,
+            doc! {
+                "$push": {"couponCodes": &code},
+                "$set": {"updatedAt": DateTime::now()},
+            },
+        )
+        .await?;
+    if updated.matched_count == 0 {
+        return Err(AppError::Conflict {
+            code: "cart_changed",
+            message: "The cart changed in the meantime; try again".to_string(),
+        });
+    }
+    respond(&state, &owner, "Coupon applied").await
+}
+
+pub async fn remove_coupon(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    headers: HeaderMap,
+    Path(params): Path<String>,
+) -> Result<Response, AppError> {
+    let code = params.to_uppercase();
+    let owner = CartOwner::resolve(&state, auth, &headers)
+        .await?
+        .ok_or_else(|| coupon_not_in_cart(&code))?;
+
+    let removed = state
+        .carts
+        .update_one(
+            owner.filter(),
+            doc! {
+                "$pull": {"couponCodes": &code},
+                "$set": {"updatedAt": DateTime::now()},
+            },
+        )
+        .await?;
+    if removed.modified_count == 0 {
+        return Err(coupon_not_in_cart(&code));
+    }
+    respond(&state, &owner, "Coupon removed").await
+}
+
+/// Folds the anonymous cart behind `token` into `user_id`'s cart and deletes
+/// it, in one transaction so a failed merge loses nothing. Quantities of
+/// products in both carts are added together, up to `MAX_LINE_QUANTITY`.
+/// The user's coupons stay; the anonymous cart's are kept only if they still
+/// apply to the merged cart, stack with the rest and are within the user's
+/// own limits.
+pub async fn merge_anonymous_cart(
+    state: &AppState,
+    token: &str,
+    user_id: ObjectId,
+) -> Result<usize, AppError> {
+    let anonymous = CartOwner::Anonymous {
+        token: token.to_string(),
+        issued: false,
+    }
+    .filter();
+    let owner = CartOwner::User(user_id).filter();
+    let client = state.db.client().clone();
+    db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let anonymous = anonymous.clone();
+        let owner = owner.clone();
+        Box::pin(async move {
+            let Some(cart) = state
+                .carts
+                .find_one_and_delete(anonymous)
+                .session(&mut *session)
+                .await?
+            else {
+                return Ok(0);
+            };
+
+            let (mut items, mut coupon_codes) = state
+                .carts
+                .find_one(owner.clone())
+                .session(&mut *session)
+                .await?
+                .map(|existing| (existing.items, existing.coupon_codes))
+                .unwrap_or_default();
+            for item in &cart.items {
+                match items
+                    .iter_mut()
+                    .find(|line| line.product_id == item.product_id)
+                {
+                    Some(line) => {
+                        line.quantity = (line.quantity + item.quantity).min(MAX_LINE_QUANTITY)
+                    }
+                    None => items.push(item.clone()),
+                }
+            }
+            let own_coupons = coupon_codes.len();
+            for code in &cart.coupon_codes {
+                if !coupon_codes.contains(code) {
+                    coupon_codes.push(code.clone());
+                }
+            }
+            if coupon_codes.len() > own_coupons {
+                let products = load_products(&state, &items).await?;
+                let priced = price_items(&items, &products)?;
+                let coupons = promotion_controller::evaluate(
+                    &state,
+                    Some(&mut *session),
+                    &coupon_codes,
+                    &priced,
+                    &products,
+                    Some(user_id),
+                )
+                .await?;
+                let rejected: Vec<String> = coupons
+                    .rejected
+                    .into_iter()
+                    .map(|rejected| rejected.code)
+                    .collect();
+                let mut position = 0;
+                coupon_codes.retain(|code| {
+                    position += 1;
+                    position <= own_coupons || !rejected.contains(code)
+                });
+            }
+
+            let items = mongodb::bson::to_bson(&items).map_err(|error| {
+                AppError::Internal(format!("Failed to encode cart items: {}", error))
+            })?;
+            let now = DateTime::now();
+            state
+                .carts
+                .update_one(
+                    owner,
+                    doc! {
+                        "$set": {"items": items, "couponCodes": &coupon_codes, "updatedAt": now},
+                        // The owner field is copied from the filter on insert.
+                        "$setOnInsert": {"createdAt": now},
+                    },
+                )
+                .upsert(true)
+                .session(&mut *session)
+                .await?;
+            Ok(cart.items.len())
+        })
+    })
+    .await
+}
@@ -0,0 +1,338 @@
+use crate::{
+    auth::{extractor::AuthUser, permissions::Permission},
+    common_struct::{app_error::is_duplicate_key, success_response, ApiResult, AppError},
+    db,
+    models::category_module::{Category, CategoryPayload, CategoryResponse, MoveCategoryPayload},
+    state::AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime},
+    options::ReturnDocument,
+    ClientSession,
+};
+use validator::Validate;
+
+fn parse_category_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid("id", "invalid_id", format!("Invalid ID format: {}", params))
+    })
+}
+
+fn category_not_found(params: &str) -> AppError {
+    AppError::NotFound(format!("Category not found with ID: {}", params))
+}
+
+fn slug_conflict(error: mongodb::error::Error) -> AppError {
+    if is_duplicate_key(&error) {
+        AppError::Conflict {
+            code: "slug_taken",
+            message: "A category with this slug already exists".to_string(),
+        }
+    } else {
+        AppError::from(error)
+    }
+}
+
+/// Looks up a category another document refers to; `field` (`parentId`,
+/// `categoryId`) is where a missing one is reported.
+pub async fn find_referenced_category(
+    state: &AppState,
+    field: &str,
+    id: ObjectId,
+) -> Result<Category, AppError> {
+    state
+        .categories
+        .find_one(doc! {"_id": id})
+        .await?
+        .ok_or_else(|| unknown_category(field, id))
+}
+
+/// `find_referenced_category` inside `session`'s transaction. It also bumps
+/// the category's `revision`, so a concurrent transaction that deletes or
+/// moves the category conflicts with this one instead of missing the new
+/// reference.
+pub async fn lock_referenced_category(
+    state: &AppState,
+    session: &mut ClientSession,
+    field: &str,
+    id: ObjectId,
+) -> Result<Category, AppError> {
+    state
+        .categories
+        .find_one_and_update(doc! {"_id": id}, doc! {"$inc": {"revision": 1}})
+        .session(&mut *session)
+        .await?
+        .ok_or_else(|| unknown_category(field, id))
+}
+
+fn unknown_category(field: &str, id: ObjectId) -> AppError {
+    AppError::invalid(
+        field,
+        "unknown_category",
+        format!("No category with ID: {}", id),
+    )
+}
+
+/// The category itself and every category below it, read through `session`
+/// when there is one.
+pub async fn subtree_ids(
+    state: &AppState,
+    session: Option<&mut ClientSession>,
+    id: ObjectId,
+) -> Result<Vec<ObjectId>, AppError> {
+    let distinct = state
+        .categories
+        .distinct("_id", doc! {"$or": [{"_id": id}, {"ancestors": id}]});
+    let ids = match session {
+        Some(session) => distinct.session(session).await?,
+        None => distinct.await?,
+    };
+    Ok(ids.iter().filter_map(Bson::as_object_id).collect())
+}
+
+pub async fn create_category(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<CategoryPayload>,
+) -> Result<Response, AppError> {
+    auth.require(Permission::ProductsAdmin, "Creating categories")?;
+    payload.validate_new()?;
+
+    let parent_id = payload
+        .parent_id
+        .as_deref()
+        .map(|parent_id| ObjectId::parse_str(parent_id).expect("validated above"));
+    let name = payload.name.unwrap_or_default();
+    let slug = payload.slug.unwrap_or_default();
+    // Locking the parent keeps a concurrent delete from orphaning the child.
+    let client = state.db.client().clone();
+    let category = db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let (name, slug) = (name.clone(), slug.clone());
+        Box::pin(async move {
+            let ancestors = match parent_id {
+                Some(parent_id) => lock_referenced_category(&state, session, "parentId", parent_id)
+                    .await?
+                    .child_ancestors(),
+                None => Vec::new(),
+            };
+            let mut category = Category {
+                id: None,
+                name,
+                slug,
+                parent_id: ancestors.last().copied(),
+                ancestors,
+                created_at: Some(DateTime::now()),
+                updated_at: Some(DateTime::now()),
+            };
+            let res = state
+                .categories
+                .insert_one(&category)
+                .session(&mut *session)
+                .await
+                .map_err(slug_conflict)?;
+            category.id = res.inserted_id.as_object_id();
+            Ok(category)
+        })
+    })
+    .await?;
+
+    let data = CategoryResponse::from(category);
+    println!("Category Added With ID: {}", data.id);
+    let location = format!("/api/v1/categories/{}", data.id);
+    let (status, body) = success_response(
+        StatusCode::CREATED,
+        "Category added successfully",
+        Some(data),
+    );
+    Ok((status, [(header::LOCATION, location)], body).into_response())
+}
+
+pub async fn get_category(State(state): State<AppState>, Path(params): Path<String>) -> ApiResult {
+    let oid = parse_category_id(&params)?;
+
+    let category = state
+        .categories
+        .find_one(doc! {"_id": oid})
+        .await?
+        .ok_or_else(|| category_not_found(&params))?;
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Category retrieved successfully",
+        Some(CategoryResponse::from(category)),
+    ))
+}
+
+/// The whole tree, flat; clients nest it by `parentId`.
+pub async fn list_categories(State(state): State<AppState>) -> ApiResult {
+    let mut cursor = state
+        .categories
+        .find(doc! {})
+        .sort(doc! {"slug": 1})
+        .await?;
+    let mut categories = Vec::new();
+    while cursor.advance().await? {
+        categories.push(CategoryResponse::from(cursor.deserialize_current()?));
+    }
+
+    println!("Listed {} category(ies)", categories.len());
+    Ok(success_response(
+        StatusCode::OK,
+        "Categories retrieved successfully",
+        Some(categories),
+    ))
+}
+
+/// Re-parents a category and rewrites the ancestors of its whole subtree, in
+/// one transaction. The new parent is locked, so two moves that would put
+/// each category below the other conflict and the retried one sees the cycle.
+pub async fn move_category(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<MoveCategoryPayload>,
+) -> ApiResult {
+    let oid = parse_category_id(&params)?;
+    auth.require(Permission::ProductsAdmin, "Moving categories")?;
+    payload.validate()?;
+    let parent_id = payload
+        .parent_id
+        .as_deref()
+        .map(|parent_id| ObjectId::parse_str(parent_id).expect("validated above"));
+
+    let client = state.db.client().clone();
+    let (category, moved) = db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let params = params.clone();
+        Box::pin(async move {
+            let ancestors = match parent_id {
+                Some(parent_id) => {
+                    let parent =
+                        lock_referenced_category(&state, session, "parentId", parent_id).await?;
+                    if parent_id == oid || parent.ancestors.contains(&oid) {
+                        return Err(AppError::invalid(
+                            "parentId",
+                            "cyclic_parent",
+                            "A category cannot move below itself",
+                        ));
+                    }
+                    parent.child_ancestors()
+                }
+                None => Vec::new(),
+            };
+
+            let category = state
+                .categories
+                .find_one_and_update(
+                    doc! {"_id": oid},
+                    doc! {
+                        "$set": {
+                            "parentId": ancestors.last().copied(),
+                            "ancestors": &ancestors,
+                            "updatedAt": DateTime::now(),
+                        },
+                        "$inc": {"revision": 1},
+                    },
+                )
+                .return_document(ReturnDocument::After)
+                .session(&mut *session)
+                .await?
+                .ok_or_else(|| category_not_found(&params))?;
+
+            // Descendants keep their path below this category and take its new prefix.
+            let mut prefix = ancestors;
+            prefix.push(oid);
+            let moved = state
+                .categories
+                .update_many(
+                    doc! {"ancestors": oid},
+                    vec![doc! {"$set": {
+                        "ancestors": {"$concatArrays": [
+                            &prefix,
+                            {"$slice": [
+                                "$ancestors",
+                                {"$add": [{"$indexOfArray": ["$ancestors", oid]}, 1]},
+                                {"$size": "$ancestors"},
+                            ]},
+                        ]},
+                        "updatedAt": DateTime::now(),
+                    }}],
+                )
+                .session(&mut *session)
+                .await?;
+            Ok((category, moved.modified_count))
+        })
+    })
+    .await?;
+
+    println!(
+        "Category Moved with ID: {} ({} descendant(s))",
+        params, moved
+    );
+    Ok(success_response(
+        StatusCode::OK,
+        "Category moved successfully",
+        Some(CategoryResponse::from(category)),
+    ))
+}
+
+/// Only leaf categories with no products can be deleted. The check and the
+/// delete share a transaction; anything that adds a child or a product locks
+/// the category, so it cannot slip in between.
+pub async fn delete_category(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_category_id(&params)?;
+    auth.require(Permission::ProductsAdmin, "Deleting categories")?;
+
+    let client = state.db.client().clone();
+    let category = db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let params = params.clone();
+        Box::pin(async move {
+            let children = state
+                .categories
+                .count_documents(doc! {"parentId": oid})
+                .session(&mut *session)
+                .await?;
+            let products = state
+                .products
+                .count_documents(doc! {"categoryId": oid})
+                .session(&mut *session)
+                .await?;
+            if children > 0 || products > 0 {
+                return Err(AppError::Conflict {
+                    code: "category_not_empty",
+                    message: format!(
+                        "Category {} still has {} subcategory(ies) and {} product(s)",
+                        params, children, products
+                    ),
+                });
+            }
+
+            state
+                .categories
+                .find_one_and_delete(doc! {"_id": oid})
+                .session(&mut *session)
+                .await?
+                .ok_or_else(|| category_not_found(&params))
+        })
+    })
+    .await?;
+
+    println!("Category Deleted with ID: {}", params);
+    Ok(success_response(
+        StatusCode::OK,
+        "Category deleted successfully",
+        Some(CategoryResponse::from(category)),
+    ))
+}
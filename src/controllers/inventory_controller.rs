@@ -0,0 +1,178 @@
+use crate::{
+    auth::{extractor::AuthUser, permissions::Permission},
+    common_struct::{
+        pagination::{self, PageRequest},
+        success_response, ApiResult, AppError,
+    },
+    inventory,
+    models::inventory_module::{
+        AdjustmentPayload, MovementListQuery, MovementResponse, ReservationPayload,
+        ReservationResponse,
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+use serde_json::json;
+
+fn parse_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid("id", "invalid_id", format!("Invalid ID format: {}", params))
+    })
+}
+
+/// Holds stock for the caller. Any signed-in user may reserve, with one
+/// active hold per product at a time.
+pub async fn create_reservation(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<ReservationPayload>,
+) -> Result<Response, AppError> {
+    payload.validate_new()?;
+    let product_id = ObjectId::parse_str(payload.product_id.as_deref().unwrap_or_default())
+        .expect("validated above");
+
+    let reservation = inventory::reserve(
+        &state,
+        auth.id,
+        product_id,
+        payload.quantity.unwrap_or_default(),
+        payload.reference,
+    )
+    .await?;
+
+    let data = ReservationResponse::from(reservation);
+    println!("Reserved {} of product {}", data.quantity, data.product_id);
+    let location = format!("/api/v1/inventory/reservations/{}", data.id);
+    let (status, body) = success_response(
+        StatusCode::CREATED,
+        "Stock reserved successfully",
+        Some(data),
+    );
+    Ok((status, [(header::LOCATION, location)], body).into_response())
+}
+
+pub async fn get_reservation(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+
+    // Other users' reservations look missing rather than forbidden.
+    let reservation = state
+        .reservations
+        .find_one(doc! {"_id": oid})
+        .await?
+        .filter(|reservation| auth.can_act_on(&reservation.user_id, Permission::InventoryAdmin))
+        .ok_or_else(|| AppError::NotFound(format!("Reservation not found with ID: {}", params)))?;
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Reservation retrieved successfully",
+        Some(ReservationResponse::from(reservation)),
+    ))
+}
+
+/// Owners may give back their own holds; inventory admins anyone's.
+pub async fn release_reservation(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+    let owner = (!auth.has_permission(Permission::InventoryAdmin)).then_some(auth.id);
+
+    let reservation = inventory::release(&state, oid, owner, Some(auth.id)).await?;
+
+    println!("Reservation Released with ID: {}", params);
+    Ok(success_response(
+        StatusCode::OK,
+        "Reservation released successfully",
+        Some(ReservationResponse::from(reservation)),
+    ))
+}
+
+pub async fn commit_reservation(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+    auth.require(Permission::InventoryAdmin, "Committing reservations")?;
+
+    let reservation = inventory::commit(&state, oid, Some(auth.id)).await?;
+
+    println!("Reservation Committed with ID: {}", params);
+    Ok(success_response(
+        StatusCode::OK,
+        "Reservation committed successfully",
+        Some(ReservationResponse::from(reservation)),
+    ))
+}
+
+pub async fn adjust_stock(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<AdjustmentPayload>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+    auth.require(Permission::InventoryAdmin, "Adjusting stock")?;
+    payload.validate_new()?;
+
+    let stock = inventory::adjust(
+        &state,
+        oid,
+        payload.delta.unwrap_or_default(),
+        payload.reason.unwrap_or_default(),
+        Some(auth.id),
+    )
+    .await?;
+
+    println!("Stock Adjusted for product {}: now {}", params, stock);
+    Ok(success_response(
+        StatusCode::OK,
+        "Stock adjusted successfully",
+        Some(json!({"productId": params, "stockQuantity": stock})),
+    ))
+}
+
+/// The ledger for one product, oldest first.
+pub async fn list_movements(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Query(query): Query<MovementListQuery>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+    auth.require(Permission::InventoryAdmin, "Reading the stock ledger")?;
+    let request = PageRequest::from_params(
+        query.limit,
+        query.offset,
+        query.cursor.as_deref(),
+        None,
+        &["_id"],
+    )?;
+
+    let page = pagination::find_page(
+        &state.inventory_movements,
+        doc! {"productId": oid},
+        None,
+        &request,
+        |movement| movement.id,
+    )
+    .await?
+    .map(MovementResponse::from);
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Stock movements retrieved successfully",
+        Some(page),
+    ))
+}
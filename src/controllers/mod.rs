@@ -1 +1,9 @@
-pub mod user_controller;
\ No newline at end of file
+pub mod auth_controller;
+pub mod cart_controller;
+pub mod category_controller;
+pub mod inventory_controller;
+pub mod order_controller;
+pub mod payment_controller;
+pub mod product_controller;
+pub mod promotion_controller;
+pub mod user_controller;
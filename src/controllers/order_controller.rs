@@ -0,0 +1,413 @@
+use std::collections::HashMap;
+
+use crate::{
+    auth::{extractor::AuthUser, permissions::Permission},
+    common_struct::{
+        pagination::{self, PageRequest},
+        success_response, ApiResult, AppError,
+    },
+    controllers::{payment_controller, promotion_controller},
+    db, inventory,
+    models::{
+        cart_module::price_items,
+        order_module::{
+            Order, OrderDiscount, OrderLine, OrderListQuery, OrderResponse, OrderStatus,
+            OrderStatusPayload, StatusChange,
+        },
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    options::ReturnDocument,
+    ClientSession,
+};
+
+fn parse_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid("id", "invalid_id", format!("Invalid ID format: {}", params))
+    })
+}
+
+fn cart_not_ready() -> AppError {
+    AppError::Conflict {
+        code: "cart_not_ready",
+        message: "Some cart lines cannot be bought as they stand; review the cart".to_string(),
+    }
+}
+
+/// Turns `user_id`'s cart into an order. Every read and write goes through
+/// `session`, so stock, coupon redemptions, the order and the cart change
+/// together or not at all.
+async fn checkout(
+    state: &AppState,
+    session: &mut ClientSession,
+    user_id: ObjectId,
+) -> Result<Order, AppError> {
+    let (items, coupon_codes) = state
+        .carts
+        .find_one(doc! {"userId": user_id})
+        .session(&mut *session)
+        .await?
+        .map(|cart| (cart.items, cart.coupon_codes))
+        .unwrap_or_default();
+    if items.is_empty() {
+        return Err(AppError::Conflict {
+            code: "cart_empty",
+            message: "The cart is empty".to_string(),
+        });
+    }
+
+    let ids: Vec<ObjectId> = items.iter().map(|item| item.product_id).collect();
+    let mut products = HashMap::new();
+    let mut cursor = state
+        .products
+        .find(doc! {"_id": {"$in": ids}})
+        .session(&mut *session)
+        .await?;
+    while cursor.advance(&mut *session).await? {
+        let product = cursor.deserialize_current()?;
+        if let Some(id) = product.id {
+            products.insert(id, product);
+        }
+    }
+
+    let priced = price_items(&items, &products)?;
+    if !priced.checkout_ready {
+        return Err(cart_not_ready());
+    }
+    let coupons = promotion_controller::evaluate(
+        state,
+        Some(&mut *session),
+        &coupon_codes,
+        &priced,
+        &products,
+        Some(user_id),
+    )
+    .await?;
+    if let Some(rejected) = coupons.rejected.into_iter().next() {
+        return Err(promotion_controller::rejection(rejected));
+    }
+    let total = coupons.total.ok_or_else(cart_not_ready)?;
+    let mut discounts = Vec::with_capacity(coupons.discounts.len());
+    for discount in coupons.discounts {
+        let promotion_id = ObjectId::parse_str(&discount.promotion_id)
+            .map_err(|_| AppError::Internal("Promotion has no ID".to_string()))?;
+        promotion_controller::redeem(state, session, promotion_id, &discount.code, user_id).await?;
+        discounts.push(OrderDiscount {
+            code: discount.code,
+            promotion_id,
+            kind: discount.kind.to_string(),
+            amount: discount.amount,
+            free_shipping: discount.free_shipping,
+        });
+    }
+    let lines = items
+        .iter()
+        .zip(priced.lines)
+        .map(|(item, line)| match (line.unit_price, line.line_total) {
+            (Some(unit_price), Some(line_total)) => Ok(OrderLine {
+                product_id: item.product_id,
+                name: line.name.unwrap_or_default(),
+                sku: line.sku.unwrap_or_default(),
+                quantity: item.quantity,
+                unit_price,
+                line_total,
+            }),
+            _ => Err(cart_not_ready()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let order_id = ObjectId::new();
+    for line in &lines {
+        inventory::take_for_order(
+            state,
+            session,
+            order_id,
+            user_id,
+            line.product_id,
+            line.quantity,
+        )
+        .await?;
+    }
+
+    let now = DateTime::now();
+    let mut status_history = vec![StatusChange {
+        from: None,
+        status: OrderStatus::Pending,
+        actor_id: Some(user_id),
+        reason: None,
+        at: now,
+    }];
+    // With nothing to charge, e.g. only free items, no payment will come.
+    let status = if total.is_zero() {
+        status_history.push(StatusChange {
+            from: Some(OrderStatus::Pending),
+            status: OrderStatus::Paid,
+            actor_id: None,
+            reason: Some("Nothing to pay".to_string()),
+            at: now,
+        });
+        OrderStatus::Paid
+    } else {
+        OrderStatus::Pending
+    };
+    let order = Order {
+        id: Some(order_id),
+        user_id,
+        status,
+        status_history,
+        lines,
+        discounts,
+        total,
+        created_at: now,
+        updated_at: now,
+    };
+    state
+        .orders
+        .insert_one(&order)
+        .session(&mut *session)
+        .await?;
+    state
+        .carts
+        .update_one(
+            doc! {"userId": user_id},
+            doc! {"$set": {"items": [], "couponCodes": [], "updatedAt": now}},
+        )
+        .session(&mut *session)
+        .await?;
+    Ok(order)
+}
+
+/// Places an order for everything in the caller's cart at today's prices.
+pub async fn place_order(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> Result<Response, AppError> {
+    let client = state.db.client().clone();
+    let order = db::with_transaction(&client, |session| {
+        let state = state.clone();
+        Box::pin(async move { checkout(&state, session, auth.id).await })
+    })
+    .await?;
+
+    let data = OrderResponse::from(order);
+    println!("Order Placed with ID: {}", data.id);
+    let location = format!("/api/v1/orders/{}", data.id);
+    let (status, body) =
+        success_response(StatusCode::CREATED, "Order placed successfully", Some(data));
+    Ok((status, [(header::LOCATION, location)], body).into_response())
+}
+
+fn order_not_found(params: &str) -> AppError {
+    AppError::NotFound(format!("Order not found with ID: {}", params))
+}
+
+fn invalid_transition(from: OrderStatus, to: OrderStatus) -> AppError {
+    let allowed: Vec<&str> = from.next().iter().map(|status| status.as_str()).collect();
+    AppError::Conflict {
+        code: "invalid_transition",
+        message: format!(
+            "An order cannot go from {} to {}; allowed next states: {}",
+            from.as_str(),
+            to.as_str(),
+            if allowed.is_empty() {
+                "none".to_string()
+            } else {
+                allowed.join(", ")
+            }
+        ),
+    }
+}
+
+/// Moves `order` to `to` if the transition table allows it, appending to its
+/// status history. Cancelling, or refunding before the order shipped, puts
+/// its stock back in the same transaction. Cancelling also gives back the
+/// coupon uses the order redeemed and claims the refund of whatever was
+/// captured; `payment_controller::settle_order_refunds` pays it out
+/// afterwards. Callers check that `actor_id` may make the change.
+pub async fn change_status(
+    state: &AppState,
+    order: &Order,
+    to: OrderStatus,
+    actor_id: Option<ObjectId>,
+    reason: Option<String>,
+) -> Result<Order, AppError> {
+    let from = order.status;
+    if !from.can_become(to) {
+        return Err(invalid_transition(from, to));
+    }
+    let order_id = order
+        .id
+        .ok_or_else(|| AppError::Internal("Order has no ID".to_string()))?;
+    let now = DateTime::now();
+    let change = mongodb::bson::to_bson(&StatusChange {
+        from: Some(from),
+        status: to,
+        actor_id,
+        reason: reason.clone(),
+        at: now,
+    })
+    .map_err(|error| AppError::Internal(format!("Failed to encode status change: {}", error)))?;
+
+    let client = state.db.client().clone();
+    db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let change = change.clone();
+        let reason = reason.clone();
+        let lines = order.lines.clone();
+        let discounts = order.discounts.clone();
+        let user_id = order.user_id;
+        Box::pin(async move {
+            // Matching on the old status makes a concurrent change lose cleanly.
+            let updated = state
+                .orders
+                .find_one_and_update(
+                    doc! {"_id": order_id, "status": from.as_str()},
+                    doc! {
+                        "$set": {"status": to.as_str(), "updatedAt": now},
+                        "$push": {"statusHistory": change},
+                    },
+                )
+                .return_document(ReturnDocument::After)
+                .session(&mut *session)
+                .await?
+                .ok_or_else(|| AppError::Conflict {
+                    code: "order_changed",
+                    message: "The order changed in the meantime; reload it and try again"
+                        .to_string(),
+                })?;
+            let called_off = matches!(to, OrderStatus::Cancelled | OrderStatus::Refunded);
+            if called_off && from.is_unshipped() {
+                for line in &lines {
+                    inventory::return_for_order(
+                        &state,
+                        session,
+                        order_id,
+                        actor_id,
+                        line.product_id,
+                        line.quantity,
+                    )
+                    .await?;
+                }
+            }
+            if to == OrderStatus::Cancelled {
+                for discount in &discounts {
+                    promotion_controller::release(&state, session, discount.promotion_id, user_id)
+                        .await?;
+                }
+                payment_controller::claim_order_refunds(
+                    &state, session, order_id, actor_id, reason,
+                )
+                .await?;
+            }
+            Ok(updated)
+        })
+    })
+    .await
+}
+
+pub async fn get_order(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+
+    // Other users' orders look missing rather than forbidden.
+    let order = state
+        .orders
+        .find_one(doc! {"_id": oid})
+        .await?
+        .filter(|order| auth.can_act_on(&order.user_id, Permission::OrdersAdmin))
+        .ok_or_else(|| order_not_found(&params))?;
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Order retrieved successfully",
+        Some(OrderResponse::from(order)),
+    ))
+}
+
+/// The caller's orders, newest first; order admins see everyone's.
+pub async fn list_orders(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<OrderListQuery>,
+) -> ApiResult {
+    let request = PageRequest::from_params(
+        query.limit,
+        query.offset,
+        query.cursor.as_deref(),
+        Some("-_id"),
+        &["_id"],
+    )?;
+    let filter = if auth.has_permission(Permission::OrdersAdmin) {
+        doc! {}
+    } else {
+        doc! {"userId": auth.id}
+    };
+
+    let page = pagination::find_page(&state.orders, filter, None, &request, |order| order.id)
+        .await?
+        .map(OrderResponse::from);
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Orders retrieved successfully",
+        Some(page),
+    ))
+}
+
+/// Owners may cancel their own pending orders; every other change needs
+/// `orders:admin`. Cancelling a paid order refunds what was captured; an
+/// order is only marked refunded by refunding its payment.
+pub async fn update_order_status(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<OrderStatusPayload>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+    payload.validate_new()?;
+    let to = payload.status.expect("validated above");
+    if to == OrderStatus::Refunded {
+        return Err(AppError::invalid(
+            "status",
+            "refund_only",
+            "An order becomes refunded when its payment is refunded in full",
+        ));
+    }
+    if to != OrderStatus::Cancelled {
+        auth.require(Permission::OrdersAdmin, "Changing order status")?;
+    }
+
+    let order = state
+        .orders
+        .find_one(doc! {"_id": oid})
+        .await?
+        .filter(|order| auth.can_act_on(&order.user_id, Permission::OrdersAdmin))
+        .ok_or_else(|| order_not_found(&params))?;
+    if to == OrderStatus::Cancelled && order.status != OrderStatus::Pending {
+        auth.require(Permission::OrdersAdmin, "Cancelling a paid order")?;
+    }
+    let order = change_status(&state, &order, to, Some(auth.id), payload.reason).await?;
+    if to == OrderStatus::Cancelled {
+        // The cancellation stands even if this fails; the refund stays pending
+        // and the refund endpoint retries it under the same key.
+        payment_controller::settle_order_refunds(&state, oid).await?;
+    }
+
+    println!("Order {} moved to {}", params, to.as_str());
+    Ok(success_response(
+        StatusCode::OK,
+        "Order status updated successfully",
+        Some(OrderResponse::from(order)),
+    ))
+}
@@ -0,0 +1,716 @@
+use crate::{
+    auth::{extractor::AuthUser, permissions::Permission},
+    common_struct::{
+        app_error::is_duplicate_key, money::Money, success_response, ApiResult, AppError,
+    },
+    controllers::order_controller::change_status,
+    db,
+    models::{
+        order_module::{Order, OrderStatus},
+        payment_module::{
+            Payment, PaymentPayload, PaymentRefund, PaymentResponse, PaymentStatus, RefundPayload,
+            RefundStatus, PAYMENT_SIGNATURE_HEADER,
+        },
+    },
+    payments::{ProviderOutcome, WebhookKind},
+    state::AppState,
+};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    options::ReturnDocument,
+    ClientSession,
+};
+use serde_json::json;
+use validator::Validate;
+
+fn parse_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid("id", "invalid_id", format!("Invalid ID format: {}", params))
+    })
+}
+
+fn payment_not_found(id: impl std::fmt::Display) -> AppError {
+    AppError::NotFound(format!("Payment not found with ID: {}", id))
+}
+
+/// The order `oid`, if the caller may see it.
+async fn visible_order(
+    state: &AppState,
+    auth: &AuthUser,
+    oid: ObjectId,
+) -> Result<Order, AppError> {
+    state
+        .orders
+        .find_one(doc! {"_id": oid})
+        .await?
+        .filter(|order| auth.can_act_on(&order.user_id, Permission::OrdersAdmin))
+        .ok_or_else(|| AppError::NotFound(format!("Order not found with ID: {}", oid)))
+}
+
+/// Records what the provider answered and returns the updated attempt.
+async fn settle(state: &AppState, id: ObjectId, mut fields: Document) -> Result<Payment, AppError> {
+    fields.insert("updatedAt", DateTime::now());
+    state
+        .payments
+        .find_one_and_update(doc! {"_id": id}, doc! {"$set": fields})
+        .return_document(ReturnDocument::After)
+        .await?
+        .ok_or_else(|| payment_not_found(id))
+}
+
+async fn order_status(
+    state: &AppState,
+    order_id: ObjectId,
+) -> Result<Option<OrderStatus>, AppError> {
+    Ok(state
+        .orders
+        .find_one(doc! {"_id": order_id})
+        .await?
+        .map(|order| order.status))
+}
+
+/// Only these can be refunded; a payment is marked refunded once nothing
+/// is left on it.
+const REFUNDABLE: [PaymentStatus; 2] = [PaymentStatus::Captured, PaymentStatus::PartiallyRefunded];
+
+fn refundable() -> Vec<&'static str> {
+    REFUNDABLE.iter().map(PaymentStatus::as_str).collect()
+}
+
+/// Claims `amount` of payment `id` for a refund inside `session`. The amount
+/// is counted as refunded and recorded as pending under a new idempotency
+/// key before the provider is asked, so concurrent refunds never exceed what
+/// was captured. `settle_refunds` asks the provider.
+async fn claim_refund(
+    state: &AppState,
+    session: &mut ClientSession,
+    id: ObjectId,
+    amount: Money,
+    actor_id: Option<ObjectId>,
+    reason: Option<String>,
+) -> Result<(), AppError> {
+    let entry = mongodb::bson::to_bson(&PaymentRefund {
+        idempotency_key: ObjectId::new().to_hex(),
+        status: RefundStatus::Pending,
+        reference: None,
+        decline_code: None,
+        amount,
+        actor_id,
+        reason,
+        at: DateTime::now(),
+    })
+    .map_err(|error| AppError::Internal(format!("Failed to encode refund: {}", error)))?;
+    let claimed = state
+        .payments
+        .update_one(
+            doc! {
+                "_id": id,
+                "status": {"$in": refundable()},
+                "amount.currency": amount.currency().code(),
+                "$expr": {"$lte": [
+                    {"$add": ["$refunded.amount", amount.minor_units()]},
+                    "$amount.amount",
+                ]},
+            },
+            doc! {
+                "$inc": {"refunded.amount": amount.minor_units()},
+                "$push": {"refunds": entry},
+                "$set": {"updatedAt": DateTime::now()},
+            },
+        )
+        .session(&mut *session)
+        .await?;
+    if claimed.matched_count > 0 {
+        return Ok(());
+    }
+
+    let payment = state
+        .payments
+        .find_one(doc! {"_id": id})
+        .session(&mut *session)
+        .await?
+        .ok_or_else(|| payment_not_found(id))?;
+    if !REFUNDABLE.contains(&payment.status) {
+        return Err(AppError::Conflict {
+            code: "payment_not_refundable",
+            message: format!(
+                "Only captured payments can be refunded; this one is {}",
+                payment.status.as_str()
+            ),
+        });
+    }
+    if payment.amount.currency() != amount.currency() {
+        return Err(AppError::invalid(
+            "amount",
+            "currency_mismatch",
+            format!("The payment was made in {}", payment.amount.currency()),
+        ));
+    }
+    Err(AppError::Conflict {
+        code: "refund_exceeds_payment",
+        message: format!(
+            "At most {} is left to refund",
+            payment.amount.checked_sub(&payment.refunded)?
+        ),
+    })
+}
+
+/// Claims whatever is left on `payment` for a refund inside `session`.
+async fn claim_rest(
+    state: &AppState,
+    session: &mut ClientSession,
+    payment: &Payment,
+    actor_id: Option<ObjectId>,
+    reason: Option<String>,
+) -> Result<(), AppError> {
+    let left = payment.amount.checked_sub(&payment.refunded)?;
+    match payment.id {
+        Some(id) if !left.is_zero() => {
+            claim_refund(state, session, id, left, actor_id, reason).await
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The payment's status worked out from its refunds: refunded once they
+/// cover it and none is pending, partially refunded once one has gone through.
+fn status_after_refunds() -> Document {
+    let statuses = doc! {"$ifNull": ["$refunds.status", []]};
+    doc! {"$switch": {
+        "branches": [
+            {
+                "case": {"$and": [
+                    {"$gte": ["$refunded.amount", "$amount.amount"]},
+                    {"$not": [{"$in": [RefundStatus::Pending.as_str(), &statuses]}]},
+                ]},
+                "then": PaymentStatus::Refunded.as_str(),
+            },
+            {
+                "case": {"$in": [RefundStatus::Succeeded.as_str(), &statuses]},
+                "then": PaymentStatus::PartiallyRefunded.as_str(),
+            },
+        ],
+        "default": "$status",
+    }}
+}
+
+/// Settles the pending refund `key` of payment `id` with `fields`, taking
+/// `given_back` minor units off the refunded total, in one update. A refund
+/// someone else settled in the meantime is left as it is.
+async fn resolve_refund(
+    state: &AppState,
+    id: ObjectId,
+    key: &str,
+    fields: Document,
+    given_back: i64,
+) -> Result<Payment, AppError> {
+    let pending = RefundStatus::Pending.as_str();
+    let resolved = state
+        .payments
+        .find_one_and_update(
+            doc! {"_id": id, "refunds": {"$elemMatch": {"idempotencyKey": key, "status": pending}}},
+            vec![
+                doc! {"$set": {
+                    "refunds": {"$map": {
+                        "input": "$refunds",
+                        "in": {"$cond": [
+                            {"$and": [
+                                {"$eq": ["$$this.idempotencyKey", key]},
+                                {"$eq": ["$$this.status", pending]},
+                            ]},
+                            {"$mergeObjects": ["$$this", {"$literal": fields}]},
+                            "$$this",
+                        ]},
+                    }},
+                    "refunded.amount": {"$subtract": ["$refunded.amount", given_back]},
+                    "updatedAt": DateTime::now(),
+                }},
+                doc! {"$set": {"status": status_after_refunds()}},
+            ],
+        )
+        .return_document(ReturnDocument::After)
+        .await?;
+    match resolved {
+        Some(payment) => Ok(payment),
+        None => state
+            .payments
+            .find_one(doc! {"_id": id})
+            .await?
+            .ok_or_else(|| payment_not_found(id)),
+    }
+}
+
+/// Asks the provider for every pending refund of payment `id`, each under
+/// the key it was claimed with, and returns the payment with how many it
+/// settled. An error leaves the refund pending for the next try; a decline
+/// gives its amount back to the payment.
+async fn settle_refunds(state: &AppState, id: ObjectId) -> Result<(Payment, usize), AppError> {
+    let mut payment = state
+        .payments
+        .find_one(doc! {"_id": id})
+        .await?
+        .ok_or_else(|| payment_not_found(id))?;
+    let charge = payment.provider_reference.clone().unwrap_or_default();
+    let pending: Vec<PaymentRefund> = payment
+        .refunds
+        .iter()
+        .filter(|refund| refund.status == RefundStatus::Pending)
+        .cloned()
+        .collect();
+    for refund in &pending {
+        let key = &refund.idempotency_key;
+        let outcome = state
+            .provider()?
+            .refund(&charge, refund.amount, key)
+            .await?;
+        match outcome {
+            ProviderOutcome::Approved { reference } => {
+                let fields = doc! {
+                    "status": RefundStatus::Succeeded.as_str(),
+                    "reference": reference,
+                };
+                payment = resolve_refund(state, id, key, fields, 0).await?;
+            }
+            ProviderOutcome::Declined { code, message } => {
+                let fields = doc! {
+                    "status": RefundStatus::Declined.as_str(),
+                    "declineCode": code,
+                };
+                resolve_refund(state, id, key, fields, refund.amount.minor_units()).await?;
+                return Err(AppError::Conflict {
+                    code: "refund_declined",
+                    message: format!("The provider declined the refund: {}", message),
+                });
+            }
+        }
+    }
+    Ok((payment, pending.len()))
+}
+
+/// Claims everything still captured on `order_id`'s payments inside
+/// `session`, e.g. when a paid order is cancelled. `settle_order_refunds`
+/// asks the provider once the transaction has committed.
+pub async fn claim_order_refunds(
+    state: &AppState,
+    session: &mut ClientSession,
+    order_id: ObjectId,
+    actor_id: Option<ObjectId>,
+    reason: Option<String>,
+) -> Result<(), AppError> {
+    let mut cursor = state
+        .payments
+        .find(doc! {"orderId": order_id, "status": {"$in": refundable()}})
+        .session(&mut *session)
+        .await?;
+    let mut payments = Vec::new();
+    while cursor.advance(&mut *session).await? {
+        payments.push(cursor.deserialize_current()?);
+    }
+    for payment in &payments {
+        claim_rest(state, session, payment, actor_id, reason.clone()).await?;
+    }
+    Ok(())
+}
+
+/// Asks the provider for the refunds pending on `order_id`'s payments.
+pub async fn settle_order_refunds(state: &AppState, order_id: ObjectId) -> Result<(), AppError> {
+    let ids = state
+        .payments
+        .distinct(
+            "_id",
+            doc! {"orderId": order_id, "refunds.status": RefundStatus::Pending.as_str()},
+        )
+        .await?;
+    for id in ids.iter().filter_map(Bson::as_object_id) {
+        settle_refunds(state, id).await?;
+    }
+    Ok(())
+}
+
+/// Lets another attempt charge the order, once this one can no longer.
+async fn release(state: &AppState, id: ObjectId) -> Result<(), AppError> {
+    state
+        .payments
+        .update_one(doc! {"_id": id}, doc! {"$unset": {"activeOrderId": ""}})
+        .await?;
+    Ok(())
+}
+
+/// Charges the order's total: authorizes, then captures straight away. A
+/// decline is recorded and reported with the attempt, not as an error.
+pub async fn pay_order(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<PaymentPayload>,
+) -> Result<Response, AppError> {
+    let oid = parse_id(&params)?;
+    payload.validate_new()?;
+    let order = visible_order(&state, &auth, oid).await?;
+    if order.status != OrderStatus::Pending {
+        return Err(AppError::Conflict {
+            code: "order_not_payable",
+            message: format!(
+                "Only pending orders can be paid; this one is {}",
+                order.status.as_str()
+            ),
+        });
+    }
+
+    let provider = state.provider()?;
+    // Written first, so an attempt whose provider call never returns still
+    // shows up. It claims the order before any money moves: a second attempt
+    // trips the unique index on activeOrderId instead of charging again.
+    let id = ObjectId::new();
+    let now = DateTime::now();
+    let inserted = state
+        .payments
+        .insert_one(Payment {
+            id: Some(id),
+            order_id: oid,
+            active_order_id: Some(oid),
+            user_id: order.user_id,
+            provider: provider.name().to_string(),
+            status: PaymentStatus::Pending,
+            amount: order.total,
+            refunded: Money::zero(order.total.currency()),
+            provider_reference: None,
+            decline_code: None,
+            refunds: Vec::new(),
+            webhook_events: Vec::new(),
+            created_at: now,
+            updated_at: now,
+        })
+        .await;
+    match inserted {
+        Ok(_) => {}
+        Err(error) if is_duplicate_key(&error) => {
+            return Err(AppError::Conflict {
+                code: "payment_in_progress",
+                message: "The order is already being paid or has been paid".to_string(),
+            });
+        }
+        Err(error) => return Err(error.into()),
+    }
+
+    let method = payload.payment_method.unwrap_or_default();
+    let authorized = match provider.authorize(order.total, &method, &id.to_hex()).await {
+        Ok(outcome) => outcome,
+        // Nothing was captured, so another attempt may try.
+        Err(error) => {
+            release(&state, id).await?;
+            return Err(error);
+        }
+    };
+    let (payment, message) = match authorized {
+        ProviderOutcome::Declined { code, .. } => {
+            release(&state, id).await?;
+            (
+                settle(
+                    &state,
+                    id,
+                    doc! {"status": PaymentStatus::Declined.as_str(), "declineCode": code},
+                )
+                .await?,
+                "Payment declined",
+            )
+        }
+        ProviderOutcome::Approved { reference } => {
+            settle(
+                &state,
+                id,
+                doc! {
+                    "status": PaymentStatus::Authorized.as_str(),
+                    "providerReference": &reference,
+                },
+            )
+            .await?;
+            // A capture that errors may still have gone through, so the claim
+            // is kept and the order stays blocked until someone looks at it.
+            match provider.capture(&reference, order.total).await? {
+                ProviderOutcome::Declined { code, .. } => {
+                    release(&state, id).await?;
+                    (
+                        settle(
+                            &state,
+                            id,
+                            doc! {"status": PaymentStatus::Failed.as_str(), "declineCode": code},
+                        )
+                        .await?,
+                        "Payment could not be captured",
+                    )
+                }
+                ProviderOutcome::Approved { .. } => {
+                    let payment = settle(
+                        &state,
+                        id,
+                        doc! {"status": PaymentStatus::Captured.as_str()},
+                    )
+                    .await?;
+                    let paid = change_status(
+                        &state,
+                        &order,
+                        OrderStatus::Paid,
+                        Some(auth.id),
+                        Some("Payment captured".to_string()),
+                    )
+                    .await;
+                    match paid {
+                        Ok(_) => {}
+                        // Paid already by the capture webhook for this charge;
+                        // the claim means no other attempt can have captured.
+                        Err(_) if order_status(&state, oid).await? == Some(OrderStatus::Paid) => {}
+                        // The order moved on (e.g. was cancelled) while it was being
+                        // charged. Cancelling may have claimed the refund already.
+                        Err(error) => {
+                            let client = state.db.client().clone();
+                            db::with_transaction(&client, |session| {
+                                let state = state.clone();
+                                Box::pin(async move {
+                                    let payment = state
+                                        .payments
+                                        .find_one(doc! {"_id": id})
+                                        .session(&mut *session)
+                                        .await?
+                                        .ok_or_else(|| payment_not_found(id))?;
+                                    let reason = "Order could not be marked paid".to_string();
+                                    claim_rest(&state, session, &payment, None, Some(reason)).await
+                                })
+                            })
+                            .await?;
+                            settle_refunds(&state, id).await?;
+                            release(&state, id).await?;
+                            return Err(error);
+                        }
+                    }
+                    (payment, "Payment captured")
+                }
+            }
+        }
+    };
+
+    let data = PaymentResponse::from(payment);
+    println!(
+        "Payment {} for order {}: {:?}",
+        data.id, data.order_id, data.status
+    );
+    let location = format!("/api/v1/payments/{}", data.id);
+    let (status, body) = success_response(StatusCode::CREATED, message, Some(data));
+    Ok((status, [(header::LOCATION, location)], body).into_response())
+}
+
+/// Every attempt to pay for an order, oldest first.
+pub async fn list_order_payments(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let order = visible_order(&state, &auth, parse_id(&params)?).await?;
+
+    let mut cursor = state
+        .payments
+        .find(doc! {"orderId": order.id})
+        .sort(doc! {"_id": 1})
+        .await?;
+    let mut payments = Vec::new();
+    while cursor.advance().await? {
+        payments.push(PaymentResponse::from(cursor.deserialize_current()?));
+    }
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Payments retrieved successfully",
+        Some(payments),
+    ))
+}
+
+pub async fn get_payment(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+
+    let payment = state
+        .payments
+        .find_one(doc! {"_id": oid})
+        .await?
+        .filter(|payment| auth.can_act_on(&payment.user_id, Permission::OrdersAdmin))
+        .ok_or_else(|| payment_not_found(&params))?;
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Payment retrieved successfully",
+        Some(PaymentResponse::from(payment)),
+    ))
+}
+
+/// Refunds part or, by default, the rest of a payment. Refunds an earlier
+/// attempt left pending are retried first, under the keys they were claimed
+/// with. A payment refunded in full marks its order refunded.
+pub async fn refund_payment(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<RefundPayload>,
+) -> ApiResult {
+    let oid = parse_id(&params)?;
+    auth.require(Permission::OrdersAdmin, "Refunding payments")?;
+    payload.validate()?;
+
+    let (mut payment, retried) = settle_refunds(&state, oid).await?;
+    let amount = match payload.amount {
+        Some(amount) => amount.to_money()?,
+        None => payment.amount.checked_sub(&payment.refunded)?,
+    };
+    if !amount.is_zero() {
+        let client = state.db.client().clone();
+        db::with_transaction(&client, |session| {
+            let state = state.clone();
+            let reason = payload.reason.clone();
+            Box::pin(async move {
+                claim_refund(&state, session, oid, amount, Some(auth.id), reason).await
+            })
+        })
+        .await?;
+        payment = settle_refunds(&state, oid).await?.0;
+    } else if retried == 0 {
+        return Err(AppError::Conflict {
+            code: "payment_not_refundable",
+            message: "The payment has already been refunded in full".to_string(),
+        });
+    }
+
+    if payment.status == PaymentStatus::Refunded {
+        let order = state
+            .orders
+            .find_one(doc! {"_id": payment.order_id})
+            .await?;
+        if let Some(order) = order.filter(|order| order.status.can_become(OrderStatus::Refunded)) {
+            change_status(
+                &state,
+                &order,
+                OrderStatus::Refunded,
+                Some(auth.id),
+                payload.reason,
+            )
+            .await?;
+        }
+    }
+
+    println!("Refunded {} of payment {}", amount, params);
+    Ok(success_response(
+        StatusCode::OK,
+        "Payment refunded successfully",
+        Some(PaymentResponse::from(payment)),
+    ))
+}
+
+/// Provider callbacks. Each event is applied once; resends of an event id
+/// that was already applied are acknowledged and otherwise ignored.
+pub async fn payment_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult {
+    let signature = headers
+        .get(PAYMENT_SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let provider = state.provider()?;
+    let event = provider.verify_webhook(signature, &body)?;
+
+    let payment = state
+        .payments
+        .find_one(doc! {
+            "provider": provider.name(),
+            "providerReference": &event.reference,
+        })
+        .await?
+        .ok_or_else(|| {
+            AppError::NotFound(format!("No payment with reference: {}", event.reference))
+        })?;
+    let id = payment.id.unwrap_or_default();
+    if payment.webhook_events.contains(&event.id) {
+        return Ok(success_response(
+            StatusCode::OK,
+            "Webhook already processed",
+            Some(json!({"eventId": event.id, "duplicate": true})),
+        ));
+    }
+
+    // Each step only fires from the expected state, so replaying one is harmless.
+    let from_authorized = doc! {"_id": id, "status": PaymentStatus::Authorized.as_str()};
+    match event.kind {
+        WebhookKind::Captured => {
+            let became_captured = state
+                .payments
+                .update_one(
+                    from_authorized,
+                    doc! {"$set": {"status": PaymentStatus::Captured.as_str(), "updatedAt": DateTime::now()}},
+                )
+                .await?
+                .modified_count
+                > 0;
+            // A declined, failed or refunded attempt never pays for the order.
+            let captured = became_captured || payment.status == PaymentStatus::Captured;
+            let order = if captured {
+                state
+                    .orders
+                    .find_one(doc! {"_id": payment.order_id})
+                    .await?
+            } else {
+                None
+            };
+            if let Some(order) = order.filter(|order| order.status == OrderStatus::Pending) {
+                change_status(
+                    &state,
+                    &order,
+                    OrderStatus::Paid,
+                    None,
+                    Some("Payment captured".to_string()),
+                )
+                .await?;
+            }
+        }
+        // The order's claim goes with it, so the customer can pay again.
+        WebhookKind::Failed => {
+            state
+                .payments
+                .update_one(
+                    from_authorized,
+                    doc! {
+                        "$set": {"status": PaymentStatus::Failed.as_str(), "updatedAt": DateTime::now()},
+                        "$unset": {"activeOrderId": ""},
+                    },
+                )
+                .await?;
+        }
+        // Refunds are recorded when they are asked for; this only confirms one.
+        WebhookKind::Refunded => {}
+    }
+    state
+        .payments
+        .update_one(
+            doc! {"_id": id},
+            doc! {
+                "$addToSet": {"webhookEvents": &event.id},
+                "$set": {"updatedAt": DateTime::now()},
+            },
+        )
+        .await?;
+
+    println!("Webhook {} applied to payment {}", event.id, id);
+    Ok(success_response(
+        StatusCode::OK,
+        "Webhook processed",
+        Some(json!({"eventId": event.id, "duplicate": false})),
+    ))
+}
@@ -0,0 +1,519 @@
+use crate::{
+    auth::{extractor::AuthUser, permissions::Permission},
+    common_struct::money::{Currency, Money},
+    common_struct::{
+        app_error::is_duplicate_key,
+        pagination::{self, Page, PageRequest},
+        success_response, ApiResult, AppError,
+    },
+    controllers::category_controller::{lock_referenced_category, subtree_ids},
+    db, inventory,
+    models::product_module::{
+        CategoryFacet, PriceFacet, Product, ProductListQuery, ProductPayload, ProductResponse,
+        ProductSearchQuery, SearchFacets, SearchHit, SearchResults, TagFacet,
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Bson, DateTime, Document},
+    options::ReturnDocument,
+    ClientSession,
+};
+use validator::Validate;
+
+fn parse_product_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid("id", "invalid_id", format!("Invalid ID format: {}", params))
+    })
+}
+
+fn product_not_found(params: &str) -> AppError {
+    AppError::NotFound(format!("Product not found with ID: {}", params))
+}
+
+/// The unique SKU index is the only one on `products` besides `_id`.
+fn sku_conflict(error: mongodb::error::Error) -> AppError {
+    if is_duplicate_key(&error) {
+        AppError::Conflict {
+            code: "sku_taken",
+            message: "A product with this SKU already exists".to_string(),
+        }
+    } else {
+        AppError::from(error)
+    }
+}
+
+/// Checks the product's category inside `session`'s transaction, locking it
+/// so the category cannot be deleted while the product is written.
+async fn check_category(
+    state: &AppState,
+    session: &mut ClientSession,
+    product: &Product,
+) -> Result<(), AppError> {
+    if let Some(category_id) = product.category_id {
+        lock_referenced_category(state, session, "categoryId", category_id).await?;
+    }
+    Ok(())
+}
+
+pub async fn create_product(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<ProductPayload>,
+) -> Result<Response, AppError> {
+    auth.require(Permission::ProductsAdmin, "Creating products")?;
+    payload.validate_new()?;
+
+    let mut product = payload.into_product()?;
+    product.stock_quantity.get_or_insert(0);
+    product.created_at = Some(DateTime::now());
+    product.updated_at = Some(DateTime::now());
+
+    let client = state.db.client().clone();
+    product.id = db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let product = product.clone();
+        Box::pin(async move {
+            check_category(&state, session, &product).await?;
+            let res = state
+                .products
+                .insert_one(&product)
+                .session(&mut *session)
+                .await
+                .map_err(sku_conflict)?;
+            let id = res.inserted_id.as_object_id();
+            let initial_stock = product.stock_quantity.unwrap_or_default();
+            if let (Some(id), true) = (id, initial_stock > 0) {
+                inventory::record_initial_stock(&state, session, id, initial_stock, auth.id)
+                    .await?;
+            }
+            Ok(id)
+        })
+    })
+    .await?;
+
+    let data = ProductResponse::from(product);
+    println!("Product Added With ID: {}", data.id);
+    let location = format!("/api/v1/products/{}", data.id);
+    let (status, body) = success_response(
+        StatusCode::CREATED,
+        "Product added successfully",
+        Some(data),
+    );
+    Ok((status, [(header::LOCATION, location)], body).into_response())
+}
+
+pub async fn get_product(State(state): State<AppState>, Path(params): Path<String>) -> ApiResult {
+    let oid = parse_product_id(&params)?;
+
+    let product = state
+        .products
+        .find_one(doc! {"_id": oid})
+        .await?
+        .ok_or_else(|| product_not_found(&params))?;
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Product retrieved successfully",
+        Some(ProductResponse::from(product)),
+    ))
+}
+
+const SORTABLE_FIELDS: [&str; 6] = [
+    "_id",
+    "name",
+    "price",
+    "stockQuantity",
+    "createdAt",
+    "updatedAt",
+];
+
+async fn product_filter(state: &AppState, query: &ProductListQuery) -> Result<Document, AppError> {
+    let mut filter = doc! {};
+    if let Some(code) = &query.currency {
+        let currency = Currency::from_code(code).ok_or_else(|| {
+            AppError::invalid(
+                "currency",
+                "unsupported_currency",
+                format!("Unsupported currency: {}", code),
+            )
+        })?;
+        filter.insert("price.currency", currency.code());
+    }
+    if let Some(category) = &query.category {
+        let category_id = ObjectId::parse_str(category).map_err(|_| {
+            AppError::invalid(
+                "category",
+                "invalid_id",
+                format!("Invalid ID format: {}", category),
+            )
+        })?;
+        filter.insert(
+            "categoryId",
+            doc! {"$in": subtree_ids(state, None, category_id).await?},
+        );
+    }
+    if let Some(tag) = &query.tag {
+        filter.insert("tags", tag);
+    }
+    Ok(filter)
+}
+
+/// Prices only order within one currency, so sorting by price needs `currency`.
+fn require_currency_for_price(
+    request: &PageRequest,
+    currency: Option<&str>,
+) -> Result<(), AppError> {
+    if request.sort_field == "price" && currency.is_none() {
+        return Err(AppError::invalid(
+            "currency",
+            "required",
+            "currency is required to sort by price",
+        ));
+    }
+    Ok(())
+}
+
+pub async fn list_products(
+    State(state): State<AppState>,
+    Query(query): Query<ProductListQuery>,
+) -> ApiResult {
+    let mut request = PageRequest::from_params(
+        query.limit,
+        query.offset,
+        query.cursor.as_deref(),
+        query.sort.as_deref(),
+        &SORTABLE_FIELDS,
+    )?;
+    require_currency_for_price(&request, query.currency.as_deref())?;
+    // Prices are stored as `{amount, currency}`; order by the minor units.
+    if request.sort_field == "price" {
+        request.sort_field = "price.amount".to_string();
+    }
+
+    let page = pagination::find_page(
+        &state.products,
+        product_filter(&state, &query).await?,
+        None,
+        &request,
+        |product| product.id,
+    )
+    .await?
+    .map(ProductResponse::from);
+
+    println!("Listed {} of {} product(s)", page.items.len(), page.total);
+    Ok(success_response(
+        StatusCode::OK,
+        "Products retrieved successfully",
+        Some(page),
+    ))
+}
+
+const SEARCH_SORTS: [&str; 5] = ["relevance", "name", "price", "createdAt", "updatedAt"];
+const MAX_QUERY_LENGTH: usize = 200;
+const MAX_TAG_FACETS: i64 = 20;
+/// Lower bounds of the price facet buckets, in major units of each currency.
+const PRICE_BUCKETS: [i64; 8] = [0, 10, 25, 50, 100, 250, 500, 1000];
+
+/// `price.amount` in major units, using each currency's exponent.
+fn major_price() -> Bson {
+    let branches: Vec<Document> = Currency::all()
+        .iter()
+        .map(|currency| {
+            doc! {
+                "case": {"$eq": ["$price.currency", currency.code()]},
+                "then": 10_i64.pow(currency.exponent()),
+            }
+        })
+        .collect();
+    bson::bson!({"$divide": [
+        "$price.amount",
+        {"$switch": {"branches": branches, "default": 100_i64}},
+    ]})
+}
+
+/// The lower bound of the `PRICE_BUCKETS` entry `$majorPrice` falls in.
+fn price_bucket() -> Bson {
+    let branches: Vec<Document> = PRICE_BUCKETS
+        .windows(2)
+        .map(|bounds| doc! {"case": {"$lt": ["$majorPrice", bounds[1]]}, "then": bounds[0]})
+        .collect();
+    bson::bson!({"$switch": {"branches": branches, "default": PRICE_BUCKETS[PRICE_BUCKETS.len() - 1]}})
+}
+
+/// One aggregation: the text match, then the requested page and every facet
+/// computed side by side over the same matches with `$facet`.
+fn search_pipeline(filter: Document, request: &PageRequest) -> Vec<Document> {
+    let sort = match request.sort_field.as_str() {
+        "relevance" => doc! {"score": -request.sort_order, "_id": 1},
+        "price" => doc! {"price.amount": request.sort_order, "_id": 1},
+        field => doc! {field: request.sort_order, "_id": 1},
+    };
+    vec![
+        doc! {"$match": filter},
+        doc! {"$addFields": {"score": {"$meta": "textScore"}}},
+        doc! {"$facet": {
+            "results": [
+                {"$sort": sort},
+                {"$skip": request.offset as i64},
+                {"$limit": request.limit},
+            ],
+            "total": [{"$count": "count"}],
+            "categories": [
+                {"$match": {"categoryId": {"$ne": null}}},
+                {"$sortByCount": "$categoryId"},
+            ],
+            "tags": [
+                {"$unwind": "$tags"},
+                {"$sortByCount": "$tags"},
+                {"$limit": MAX_TAG_FACETS},
+            ],
+            "prices": [
+                {"$match": {"price.amount": {"$type": "number"}}},
+                {"$addFields": {"majorPrice": major_price()}},
+                {"$group": {
+                    "_id": {"currency": "$price.currency", "min": price_bucket()},
+                    "count": {"$sum": 1},
+                }},
+                {"$sort": {"_id.currency": 1, "_id.min": 1}},
+            ],
+        }},
+    ]
+}
+
+fn facet_count(doc: &Document) -> u64 {
+    match doc.get("count") {
+        Some(Bson::Int32(n)) => *n as u64,
+        Some(Bson::Int64(n)) => *n as u64,
+        _ => 0,
+    }
+}
+
+fn facet_docs<'a>(facets: &'a Document, name: &str) -> impl Iterator<Item = &'a Document> {
+    facets
+        .get_array(name)
+        .map(|entries| entries.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(Bson::as_document)
+}
+
+fn price_facet(entry: &Document) -> Option<PriceFacet> {
+    let key = entry.get_document("_id").ok()?;
+    let currency = Currency::from_code(key.get_str("currency").ok()?)?;
+    let min = match key.get("min")? {
+        Bson::Int32(n) => i64::from(*n),
+        Bson::Int64(n) => *n,
+        _ => return None,
+    };
+    let scale = 10_i64.pow(currency.exponent());
+    let amount = |major: i64| Money::from_minor(major * scale, currency).amount_string();
+    Some(PriceFacet {
+        currency: currency.code().to_string(),
+        min: amount(min),
+        max: PRICE_BUCKETS
+            .iter()
+            .find(|bound| **bound > min)
+            .map(|bound| amount(*bound)),
+        count: facet_count(entry),
+    })
+}
+
+fn search_facets(facets: &Document) -> SearchFacets {
+    SearchFacets {
+        categories: facet_docs(facets, "categories")
+            .filter_map(|entry| {
+                Some(CategoryFacet {
+                    category_id: entry.get_object_id("_id").ok()?.to_hex(),
+                    count: facet_count(entry),
+                })
+            })
+            .collect(),
+        tags: facet_docs(facets, "tags")
+            .filter_map(|entry| {
+                Some(TagFacet {
+                    tag: entry.get_str("_id").ok()?.to_string(),
+                    count: facet_count(entry),
+                })
+            })
+            .collect(),
+        prices: facet_docs(facets, "prices")
+            .filter_map(price_facet)
+            .collect(),
+    }
+}
+
+pub async fn search_products(
+    State(state): State<AppState>,
+    Query(query): Query<ProductSearchQuery>,
+) -> ApiResult {
+    let text = query.q.as_deref().map(str::trim).unwrap_or_default();
+    if text.is_empty() {
+        return Err(AppError::invalid("q", "required", "q is required"));
+    }
+    if text.chars().count() > MAX_QUERY_LENGTH {
+        return Err(AppError::invalid(
+            "q",
+            "invalid_length",
+            format!("q must be at most {} characters", MAX_QUERY_LENGTH),
+        ));
+    }
+    let request = PageRequest::from_params(
+        query.limit,
+        query.offset,
+        None,
+        Some(query.sort.as_deref().unwrap_or("relevance")),
+        &SEARCH_SORTS,
+    )?;
+    require_currency_for_price(&request, query.currency.as_deref())?;
+
+    let mut filter = product_filter(
+        &state,
+        &ProductListQuery {
+            limit: None,
+            offset: None,
+            cursor: None,
+            sort: None,
+            category: query.category,
+            tag: query.tag,
+            currency: query.currency,
+        },
+    )
+    .await?;
+    filter.insert("$text", doc! {"$search": text});
+
+    let mut cursor = state
+        .products
+        .aggregate(search_pipeline(filter, &request))
+        .await?;
+    let facets = if cursor.advance().await? {
+        cursor.deserialize_current()?
+    } else {
+        Document::new()
+    };
+
+    let mut items = Vec::new();
+    for hit in facet_docs(&facets, "results") {
+        let score = hit.get_f64("score").unwrap_or_default();
+        let product: Product = bson::from_document(hit.clone())
+            .map_err(|error| AppError::Internal(format!("Failed to decode product: {}", error)))?;
+        items.push(SearchHit {
+            product: ProductResponse::from(product),
+            score,
+        });
+    }
+    let total = facet_docs(&facets, "total")
+        .next()
+        .map(facet_count)
+        .unwrap_or(0);
+
+    println!("Search {:?} matched {} product(s)", text, total);
+    let results = SearchResults {
+        page: Page {
+            items,
+            total,
+            limit: request.limit,
+            offset: Some(request.offset),
+            next_cursor: None,
+            prev_cursor: None,
+        },
+        facets: search_facets(&facets),
+    };
+    Ok(success_response(
+        StatusCode::OK,
+        "Search completed successfully",
+        Some(results),
+    ))
+}
+
+pub async fn update_product(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<ProductPayload>,
+) -> ApiResult {
+    let oid = parse_product_id(&params)?;
+    auth.require(Permission::ProductsAdmin, "Updating products")?;
+    payload.validate()?;
+    if payload.stock_quantity.is_some() {
+        // Stock only moves through the ledger so every change is recorded.
+        return Err(AppError::invalid(
+            "stockQuantity",
+            "read_only",
+            format!(
+                "Change stock with POST /api/v1/products/{}/inventory/adjustments",
+                params
+            ),
+        ));
+    }
+
+    let changes = payload.into_product()?;
+    let mut update_doc = bson::to_document(&changes)
+        .map_err(|error| AppError::Internal(format!("Failed to encode product: {}", error)))?;
+    // Identity and timestamps are managed here, never taken from the client.
+    for field in ["_id", "createdAt", "updatedAt"] {
+        update_doc.remove(field);
+    }
+
+    if update_doc.is_empty() {
+        return Err(AppError::invalid(
+            "body",
+            "empty_update",
+            format!("No fields to update for product with ID: {}", params),
+        ));
+    }
+
+    update_doc.insert("updatedAt", DateTime::now());
+    let client = state.db.client().clone();
+    let product = db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let (changes, update_doc, params) = (changes.clone(), update_doc.clone(), params.clone());
+        Box::pin(async move {
+            check_category(&state, session, &changes).await?;
+            state
+                .products
+                .find_one_and_update(doc! {"_id": oid}, doc! {"$set": update_doc})
+                .return_document(ReturnDocument::After)
+                .session(&mut *session)
+                .await
+                .map_err(sku_conflict)?
+                .ok_or_else(|| product_not_found(&params))
+        })
+    })
+    .await?;
+
+    println!("Product Updated with ID: {}", params);
+    Ok(success_response(
+        StatusCode::OK,
+        "Product updated successfully",
+        Some(ProductResponse::from(product)),
+    ))
+}
+
+pub async fn delete_product(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_product_id(&params)?;
+    auth.require(Permission::ProductsAdmin, "Deleting products")?;
+
+    let product = state
+        .products
+        .find_one_and_delete(doc! {"_id": oid})
+        .await?
+        .ok_or_else(|| product_not_found(&params))?;
+
+    println!("Product Deleted with ID: {}", params);
+    Ok(success_response(
+        StatusCode::OK,
+        "Product deleted successfully",
+        Some(ProductResponse::from(product)),
+    ))
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use crate::{
+    auth::{extractor::AuthUser, permissions::Permission},
+    common_struct::{
+        app_error::is_duplicate_key,
+        pagination::{self, PageRequest},
+        success_response, ApiResult, AppError,
+    },
+    controllers::category_controller::{find_referenced_category, subtree_ids},
+    models::{
+        cart_module::{CartLine, LineStatus, PricedCart},
+        product_module::Product,
+        promotion_module::{
+            can_stack, compute_discount, total_discount, CouponSummary, Promotion,
+            PromotionListQuery, PromotionPayload, PromotionResponse, PromotionRule, RejectedCoupon,
+        },
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime},
+    ClientSession,
+};
+
+fn parse_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid("id", "invalid_id", format!("Invalid ID format: {}", params))
+    })
+}
+
+fn promotion_not_found(params: &str) -> AppError {
+    AppError::NotFound(format!("Promotion not found with ID: {}", params))
+}
+
+fn code_conflict(error: mongodb::error::Error) -> AppError {
+    if is_duplicate_key(&error) {
+        AppError::Conflict {
+            code: "code_taken",
+            message: "A promotion with this code already exists".to_string(),
+        }
+    } else {
+        AppError::from(error)
+    }
+}
+
+fn coupon_exhausted(code: &str) -> AppError {
+    AppError::Conflict {
+        code: "coupon_exhausted",
+        message: format!("Coupon {} has been used up", code),
+    }
+}
+
+/// The error to answer with when a coupon cannot be used.
+pub fn rejection(rejected: RejectedCoupon) -> AppError {
+    match rejected.reason {
+        "coupon_not_found" => AppError::NotFound(rejected.message),
+        reason => AppError::Conflict {
+            code: reason,
+            message: rejected.message,
+        },
+    }
+}
+
+/// Prices `codes` against a priced cart. Coupons are taken in order, so a
+/// coupon that does not stack with the ones before it is the one rejected.
+/// Per-user limits are only checked when `user_id` is known; checkout
+/// enforces them again when redeeming. Checkout passes its transaction's
+/// `session` so every read sees the same snapshot as its writes; a cart
+/// view passes none and reads without one.
+pub async fn evaluate(
+    state: &AppState,
+    mut session: Option<&mut ClientSession>,
+    codes: &[String],
+    priced: &PricedCart,
+    products: &HashMap<ObjectId, Product>,
+    user_id: Option<ObjectId>,
+) -> Result<CouponSummary, AppError> {
+    let mut summary = CouponSummary {
+        discounts: Vec::new(),
+        rejected: Vec::new(),
+        discount_total: None,
+        total: priced.subtotal,
+        free_shipping: false,
+    };
+    if codes.is_empty() {
+        return Ok(summary);
+    }
+
+    let mut promotions = HashMap::new();
+    let find = state
+        .promotions
+        .find(doc! {"code": {"$in": codes.to_vec()}});
+    match session.as_deref_mut() {
+        Some(session) => {
+            let mut cursor = find.session(&mut *session).await?;
+            while cursor.advance(&mut *session).await? {
+                let promotion: Promotion = cursor.deserialize_current()?;
+                promotions.insert(promotion.code.clone(), promotion);
+            }
+        }
+        None => {
+            let mut cursor = find.await?;
+            while cursor.advance().await? {
+                let promotion = cursor.deserialize_current()?;
+                promotions.insert(promotion.code.clone(), promotion);
+            }
+        }
+    }
+
+    let now = DateTime::now();
+    let mut accepted: Vec<&Promotion> = Vec::new();
+    for code in codes {
+        let reject = |reason: &'static str, message: String| RejectedCoupon {
+            code: code.clone(),
+            reason,
+            message,
+        };
+        let Some(promotion) = promotions.get(code) else {
+            summary.rejected.push(reject(
+                "coupon_not_found",
+                format!("No coupon with code: {}", code),
+            ));
+            continue;
+        };
+        if let Some((reason, message)) = promotion.unavailable(now) {
+            summary.rejected.push(reject(reason, message));
+            continue;
+        }
+        if let (Some(user_id), Some(limit)) = (user_id, promotion.per_user_limit) {
+            let find = state
+                .promotion_redemptions
+                .find_one(doc! {"promotionId": promotion.id, "userId": user_id});
+            let redemption = match session.as_deref_mut() {
+                Some(session) => find.session(session).await?,
+                None => find.await?,
+            };
+            let used = redemption
+                .map(|redemption| redemption.count)
+                .unwrap_or_default();
+            if used >= limit {
+                summary.rejected.push(reject(
+                    "coupon_limit_reached",
+                    format!("You have already used coupon {} as often as allowed", code),
+                ));
+                continue;
+            }
+        }
+        if !can_stack(&accepted, promotion) {
+            summary.rejected.push(reject(
+                "coupon_not_stackable",
+                format!("Coupon {} cannot be combined with the other coupons", code),
+            ));
+            continue;
+        }
+
+        let scope = match promotion.category_id {
+            Some(category_id) => {
+                Some(subtree_ids(state, session.as_deref_mut(), category_id).await?)
+            }
+            None => None,
+        };
+        let lines: Vec<&CartLine> = priced
+            .lines
+            .iter()
+            .filter(|line| line.status == LineStatus::Available)
+            .filter(|line| {
+                scope.as_ref().is_none_or(|scope| {
+                    ObjectId::parse_str(&line.product_id)
+                        .ok()
+                        .and_then(|id| products.get(&id)?.category_id)
+                        .is_some_and(|category_id| scope.contains(&category_id))
+                })
+            })
+            .collect();
+        let Some(subtotal) = priced.subtotal else {
+            accepted.push(promotion);
+            continue;
+        };
+        if let PromotionRule::FixedAmount { amount } = &promotion.rule {
+            if amount.currency() != subtotal.currency() {
+                summary.rejected.push(reject(
+                    "currency_mismatch",
+                    format!(
+                        "Coupon {} is in {}, but the cart is in {}",
+                        code,
+                        amount.currency(),
+                        subtotal.currency()
+                    ),
+                ));
+                continue;
+            }
+        }
+        let discount = compute_discount(promotion, &lines, subtotal.currency())?;
+        if discount.amount.is_zero() && !discount.free_shipping {
+            summary.rejected.push(reject(
+                "coupon_not_applicable",
+                format!("Coupon {} does not apply to anything in the cart", code),
+            ));
+            continue;
+        }
+        accepted.push(promotion);
+        summary.free_shipping |= discount.free_shipping;
+        summary.discounts.push(discount);
+    }
+
+    if let Some(subtotal) = priced.subtotal {
+        let discount_total = total_discount(subtotal, &summary.discounts)?;
+        summary.total = Some(subtotal.checked_sub(&discount_total)?);
+        summary.discount_total = Some(discount_total);
+    }
+    Ok(summary)
+}
+
+/// Counts one use of a promotion by `user_id` inside `session`. Both counters
+/// only move through conditional updates, so concurrent checkouts cannot take
+/// a promotion past its total or per-user limit.
+pub async fn redeem(
+    state: &AppState,
+    session: &mut ClientSession,
+    promotion_id: ObjectId,
+    code: &str,
+    user_id: ObjectId,
+) -> Result<(), AppError> {
+    let promotion = state
+        .promotions
+        .find_one_and_update(
+            doc! {
+                "_id": promotion_id,
+                "$or": [
+                    {"usageLimit": {"$exists": false}},
+                    {"$expr": {"$lt": ["$redemptions", "$usageLimit"]}},
+                ],
+            },
+            doc! {"$inc": {"redemptions": 1}, "$set": {"updatedAt": DateTime::now()}},
+        )
+        .session(&mut *session)
+        .await?
+        .ok_or_else(|| coupon_exhausted(code))?;
+
+    let Some(limit) = promotion.per_user_limit else {
+        return Ok(());
+    };
+    // A user at the limit misses the filter, and the upsert then trips the
+    // unique index instead of starting a second counter.
+    let counted = state
+        .promotion_redemptions
+        .update_one(
+            doc! {"promotionId": promotion_id, "userId": user_id, "count": {"$lt": limit}},
+            doc! {"$inc": {"count": 1}},
+        )
+        .upsert(true)
+        .session(&mut *session)
+        .await;
+    match counted {
+        Ok(_) => Ok(()),
+        Err(error) if is_duplicate_key(&error) => Err(AppError::Conflict {
+            code: "coupon_limit_reached",
+            message: format!("You have already used coupon {} as often as allowed", code),
+        }),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Gives back one use of a promotion that `redeem` counted for `user_id`,
+/// inside `session`, e.g. when the order that used it is cancelled.
+pub async fn release(
+    state: &AppState,
+    session: &mut ClientSession,
+    promotion_id: ObjectId,
+    user_id: ObjectId,
+) -> Result<(), AppError> {
+    state
+        .promotions
+        .update_one(
+            doc! {"_id": promotion_id, "redemptions": {"$gt": 0}},
+            doc! {"$inc": {"redemptions": -1}, "$set": {"updatedAt": DateTime::now()}},
+        )
+        .session(&mut *session)
+        .await?;
+    // Counters start at one, so the last use goes with its document.
+    let counter = doc! {"promotionId": promotion_id, "userId": user_id};
+    let mut last = counter.clone();
+    last.insert("count", doc! {"$lte": 1});
+    let removed = state
+        .promotion_redemptions
+        .delete_one(last)
+        .session(&mut *session)
+        .await?;
+    if removed.deleted_count == 0 {
+        state
+            .promotion_redemptions
+            .update_one(counter, doc! {"$inc": {"count": -1}})
+            .session(&mut *session)
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn create_promotion(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<PromotionPayload>,
+) -> Result<Response, AppError> {
+    auth.require(Permission::PromotionsAdmin, "Creating promotions")?;
+    payload.validate_new()?;
+
+    let mut promotion = payload.into_promotion()?;
+    if let Some(category_id) = promotion.category_id {
+        find_referenced_category(&state, "categoryId", category_id).await?;
+    }
+    let res = state
+        .promotions
+        .insert_one(&promotion)
+        .await
+        .map_err(code_conflict)?;
+    promotion.id = res.inserted_id.as_object_id();
+
+    let data = PromotionResponse::from(promotion);
+    println!("Promotion Added With ID: {}", data.id);
+    let location = format!("/api/v1/promotions/{}", data.id);
+    let (status, body) = success_response(
+        StatusCode::CREATED,
+        "Promotion added successfully",
+        Some(data),
+    );
+    Ok((status, [(header::LOCATION, location)], body).into_response())
+}
+
+pub async fn get_promotion(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    auth.require(Permission::PromotionsAdmin, "Reading promotions")?;
+    let oid = parse_id(&params)?;
+
+    let promotion = state
+        .promotions
+        .find_one(doc! {"_id": oid})
+        .await?
+        .ok_or_else(|| promotion_not_found(&params))?;
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Promotion retrieved successfully",
+        Some(PromotionResponse::from(promotion)),
+    ))
+}
+
+/// Every promotion, newest first.
+pub async fn list_promotions(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<PromotionListQuery>,
+) -> ApiResult {
+    auth.require(Permission::PromotionsAdmin, "Reading promotions")?;
+    let request = PageRequest::from_params(
+        query.limit,
+        query.offset,
+        query.cursor.as_deref(),
+        Some("-_id"),
+        &["_id"],
+    )?;
+
+    let page = pagination::find_page(&state.promotions, doc! {}, None, &request, |promotion| {
+        promotion.id
+    })
+    .await?
+    .map(PromotionResponse::from);
+
+    Ok(success_response(
+        StatusCode::OK,
+        "Promotions retrieved successfully",
+        Some(page),
+    ))
+}
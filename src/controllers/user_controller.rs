@@ -1,219 +1,321 @@
 use crate::{
-    common_struct::{handle_db_error, handle_invalid_id_error, ApiResponse},
-    db,
-    models::user_module::User,
+    auth::{
+        extractor::AuthUser,
+        password,
+        permissions::{Permission, Role},
+    },
+    common_struct::{pagination::PageRequest, success_response, ApiResult, AppError},
+    models::user_module::{User, UserListQuery, UserResponse},
+    repositories::{TextMatch, UserChanges, UserFilter},
+    state::AppState,
 };
-use axum::{extract::Path, http::StatusCode, Json};
-use mongodb::bson::{doc, oid::ObjectId, DateTime, Document};
-use serde_json::{json, Value};
-
-pub async fn add_user(Json(mut payload): Json<User>) -> (StatusCode, Json<Value>) {
-    let db = match db::connect_db().await {
-        Ok(db) => db,
-        Err(error) => return handle_db_error(error).await,
-    };
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use mongodb::bson::{oid::ObjectId, DateTime};
+use validator::Validate;
 
-    let coll = db.collection::<User>("users");
-
-    if payload.first_name.is_none()
-        || payload.last_name.is_none()
-        || payload.email.is_none()
-        || payload.password.is_none()
-    {
-        println!("Missing fields in payload: {:?}", payload);
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!(ApiResponse {
-                status: "error".to_string(),
-                code: 400,
-                message: "Missing fields".to_string(),
-                data: None,
-                errors: Some(format!("Missing fields: {:?}", payload)),
-            })),
-        );
-    }
+fn parse_user_id(params: &str) -> Result<ObjectId, AppError> {
+    ObjectId::parse_str(params).map_err(|_| {
+        AppError::invalid("id", "invalid_id", format!("Invalid ID format: {}", params))
+    })
+}
 
-    payload.created_at = Some(DateTime::now());
-    payload.updated_at = Some(DateTime::now());
+fn user_not_found(params: &str) -> AppError {
+    AppError::NotFound(format!("User not found with ID: {}", params))
+}
 
-    match coll.insert_one(payload).await {
-        Ok(res) => {
-            println!("User Added With ID: {}", res.inserted_id);
-            (
-                StatusCode::OK,
-                Json(json!(ApiResponse {
-                    status: "Success".to_string(),
-                    code: 200,
-                    message: "User added successfully".to_string(),
-                    data: Some(format!("id:{}", res.inserted_id)),
-                    errors: None,
-                })),
-            )
+async fn insert_user(
+    state: &AppState,
+    auth: Option<AuthUser>,
+    mut payload: User,
+) -> Result<UserResponse, AppError> {
+    payload.validate_new()?;
+
+    // Self-registration always yields a regular user; only admins pick roles.
+    if payload.roles.is_some() {
+        match &auth {
+            Some(auth) => auth.require(Permission::UsersWrite, "Assigning roles")?,
+            None => {
+                return Err(AppError::Forbidden(format!(
+                    "Assigning roles requires {}",
+                    Permission::UsersWrite.as_str()
+                )))
+            }
         }
-        Err(error) => handle_db_error(error).await,
+    } else {
+        payload.roles = Some(vec![Role::User]);
     }
-}
 
-pub async fn get_user(Path(params): Path<String>) -> (StatusCode, Json<Value>) {
-    let db = match db::connect_db().await {
-        Ok(db) => db,
-        Err(error) => return handle_db_error(error).await,
-    };
+    if let Some(plain) = payload.password.take() {
+        payload.password = Some(password::hash_password(plain).await?);
+    }
+    payload.created_at = Some(DateTime::now());
+    payload.updated_at = Some(DateTime::now());
 
-    let coll = db.collection::<Document>("users");
+    let data = UserResponse::from(state.users.insert(payload).await?);
+    println!("User Added With ID: {}", data.id);
+    Ok(data)
+}
 
-    let oid = match ObjectId::parse_str(&params) {
-        Ok(oid) => oid,
-        Err(_) => return handle_invalid_id_error::<String>(params).await,
-    };
+/// Legacy `POST /addUser`, which answers 200 rather than 201.
+pub async fn add_user(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    Json(payload): Json<User>,
+) -> ApiResult {
+    let data = insert_user(&state, auth, payload).await?;
+    Ok(success_response(
+        StatusCode::OK,
+        "User added successfully",
+        Some(data),
+    ))
+}
 
-    match coll.find_one(doc! {"_id": oid}).await {
-        Ok(Some(data)) => {
-            println!("User Details: {:?}", data);
-            (
-                StatusCode::OK,
-                Json(json!(ApiResponse {
-                    status: "Success".to_string(),
-                    code: 200,
-                    message: "User retrieved successfully".to_string(),
-                    data: Some(data),
-                    errors: None,
-                })),
-            )
-        }
-        Ok(None) => {
-            println!("User Not Found with ID: {}", params);
-            (
-                StatusCode::NOT_FOUND,
-                Json(json!(ApiResponse {
-                    status: "error".to_string(),
-                    code: 404,
-                    message: "User not found".to_string(),
-                    data: None,
-                    errors: Some(format!("User not found with ID: {}", params)),
-                })),
-            )
-        }
-        Err(error) => handle_db_error(error).await,
-    }
+pub async fn create_user(
+    State(state): State<AppState>,
+    auth: Option<AuthUser>,
+    Json(payload): Json<User>,
+) -> Result<Response, AppError> {
+    let data = insert_user(&state, auth, payload).await?;
+    let location = format!("/api/v1/users/{}", data.id);
+    let (status, body) =
+        success_response(StatusCode::CREATED, "User added successfully", Some(data));
+    Ok((status, [(header::LOCATION, location)], body).into_response())
 }
 
-pub async fn update_user(
+pub async fn get_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
     Path(params): Path<String>,
-    Json(payload): Json<User>,
-) -> (StatusCode, Json<Value>) {
-    let db = match db::connect_db().await {
-        Ok(db) => db,
-        Err(error) => return handle_db_error(error).await,
-    };
+) -> ApiResult {
+    let oid = parse_user_id(&params)?;
+    auth.require_owner_or(&oid, Permission::UsersRead, "Reading other users")?;
 
-    let coll = db.collection::<User>("users");
+    let user = state
+        .users
+        .find_by_id(oid)
+        .await?
+        .ok_or_else(|| user_not_found(&params))?;
 
-    let oid = match ObjectId::parse_str(&params) {
-        Ok(oid) => oid,
-        Err(_) => return handle_invalid_id_error::<String>(params).await,
-    };
+    let data = UserResponse::from(user);
+    println!("User Details: {:?}", data);
+    Ok(success_response(
+        StatusCode::OK,
+        "User retrieved successfully",
+        Some(data),
+    ))
+}
+
+const SORTABLE_FIELDS: [&str; 6] = [
+    "_id",
+    "firstName",
+    "lastName",
+    "email",
+    "createdAt",
+    "updatedAt",
+];
+
+fn parse_timestamp(field: &str, value: &str) -> Result<DateTime, AppError> {
+    DateTime::parse_rfc3339_str(value).map_err(|_| {
+        AppError::invalid(
+            field,
+            "invalid_timestamp",
+            format!("{} must be an RFC 3339 timestamp", field),
+        )
+    })
+}
 
-    let mut update_doc = doc! {};
+fn user_filter(query: &UserListQuery) -> Result<UserFilter, AppError> {
+    let mut filter = UserFilter::default();
 
-    if let Some(first_name) = &payload.first_name {
-        update_doc.insert("firstName", first_name);
+    let text_filters = [
+        (
+            "firstName",
+            &query.first_name,
+            &query.first_name_prefix,
+            &mut filter.first_name,
+        ),
+        (
+            "lastName",
+            &query.last_name,
+            &query.last_name_prefix,
+            &mut filter.last_name,
+        ),
+        (
+            "email",
+            &query.email,
+            &query.email_prefix,
+            &mut filter.email,
+        ),
+    ];
+    for (field, exact, prefix, slot) in text_filters {
+        *slot = match (exact, prefix) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::invalid(
+                    field,
+                    "conflicting_filters",
+                    format!("Use either {} or {}Prefix, not both", field, field),
+                ))
+            }
+            (Some(exact), None) => Some(TextMatch::Exact(exact.clone())),
+            (None, Some(prefix)) => Some(TextMatch::Prefix(prefix.clone())),
+            (None, None) => None,
+        };
     }
-    if let Some(last_name) = &payload.last_name {
-        update_doc.insert("lastName", last_name);
+
+    if let Some(from) = &query.created_from {
+        filter.created_from = Some(parse_timestamp("createdFrom", from)?);
     }
-    if let Some(email) = &payload.email {
-        update_doc.insert("email", email);
+    if let Some(to) = &query.created_to {
+        filter.created_to = Some(parse_timestamp("createdTo", to)?);
     }
-    if let Some(password) = &payload.password {
-        update_doc.insert("password", password);
+
+    Ok(filter)
+}
+
+pub async fn list_users(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<UserListQuery>,
+) -> ApiResult {
+    auth.require(Permission::UsersRead, "Listing users")?;
+
+    let request = PageRequest::from_params(
+        query.limit,
+        query.offset,
+        query.cursor.as_deref(),
+        query.sort.as_deref(),
+        &SORTABLE_FIELDS,
+    )?;
+    let filter = user_filter(&query)?;
+
+    let page = state
+        .users
+        .list(&filter, &request)
+        .await?
+        .map(UserResponse::from);
+
+    println!("Listed {} of {} user(s)", page.items.len(), page.total);
+    Ok(success_response(
+        StatusCode::OK,
+        "Users retrieved successfully",
+        Some(page),
+    ))
+}
+
+/// Changing the password revokes the user's refresh tokens, so every session
+/// ends once its access token expires.
+pub async fn update_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<User>,
+) -> ApiResult {
+    let oid = parse_user_id(&params)?;
+    auth.require_owner_or(&oid, Permission::UsersWrite, "Updating other users")?;
+    if payload.roles.is_some() {
+        auth.require(Permission::UsersWrite, "Assigning roles")?;
     }
+    payload.validate()?;
 
-    if !update_doc.is_empty() {
-        update_doc.insert("updatedAt", DateTime::now());
-        match coll
-            .update_one(doc! {"_id": oid}, doc! { "$set": update_doc })
-            .await
-        {
-            Ok(res) => {
-                println!(
-                    "Matched {} document(s) and modified {} document(s)",
-                    res.matched_count, res.modified_count
-                );
-                (
-                    StatusCode::OK,
-                    Json(json!(ApiResponse {
-                        status: "Success".to_string(),
-                        code: 200,
-                        message: "User updated successfully".to_string(),
-                        data: Some(format!(
-                            "Matched {} document(s) and modified {} document(s)",
-                            res.matched_count, res.modified_count
-                        )),
-                        errors: None,
-                    })),
-                )
-            }
-            Err(error) => handle_db_error(error).await,
-        }
-    } else {
-        println!("No fields to update for user with ID: {}", params);
-        (
-            StatusCode::BAD_REQUEST,
-            Json(json!(ApiResponse {
-                status: "error".to_string(),
-                code: 400,
-                message: "No fields to update".to_string(),
-                data: None,
-                errors: Some(format!("No fields to update for user with ID: {}", params)),
-            })),
-        )
+    let changes = user_changes(payload).await?;
+    if changes.is_empty() {
+        return Err(AppError::invalid(
+            "body",
+            "empty_update",
+            format!("No fields to update for user with ID: {}", params),
+        ));
     }
+
+    save_changes(&state, oid, &params, changes).await
 }
 
-pub async fn delete_user(Path(params): Path<String>) -> (StatusCode, Json<Value>) {
-    let db = match db::connect_db().await {
-        Ok(db) => db,
-        Err(error) => return handle_db_error(error).await,
-    };
+/// `PUT` replaces the profile fields wholesale. Roles and password are only
+/// changed when supplied, since neither is part of the public representation.
+/// A new password revokes refresh tokens as in `update_user`.
+pub async fn replace_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+    Json(payload): Json<User>,
+) -> ApiResult {
+    let oid = parse_user_id(&params)?;
+    auth.require_owner_or(&oid, Permission::UsersWrite, "Updating other users")?;
+    if payload.roles.is_some() {
+        auth.require(Permission::UsersWrite, "Assigning roles")?;
+    }
+    payload.validate_replacement()?;
 
-    let coll = db.collection::<User>("users");
+    let changes = user_changes(payload).await?;
+    save_changes(&state, oid, &params, changes).await
+}
 
-    let oid = match ObjectId::parse_str(&params) {
-        Ok(oid) => oid,
-        Err(_) => return handle_invalid_id_error::<String>(params).await,
+async fn user_changes(payload: User) -> Result<UserChanges, AppError> {
+    let mut changes = UserChanges {
+        first_name: payload.first_name,
+        last_name: payload.last_name,
+        email: payload.email,
+        roles: payload.roles,
+        ..UserChanges::default()
     };
+    if let Some(plain) = payload.password {
+        changes.password = Some(password::hash_password(plain).await?);
+    }
+    Ok(changes)
+}
 
-    match coll.delete_one(doc! {"_id": oid}).await {
-        Ok(res) => {
-            if res.deleted_count > 0 {
-                println!("User Deleted with ID: {}", params);
-                (
-                    StatusCode::OK,
-                    Json(json!(ApiResponse {
-                        status: "Success".to_string(),
-                        code: 200,
-                        message: "User deleted successfully".to_string(),
-                        data: Some(format!("Deleted {} user(s)", res.deleted_count)),
-                        errors: None,
-                    })),
-                )
-            } else {
-                println!("User Not Found with ID: {}", params);
-                (
-                    StatusCode::NOT_FOUND,
-                    Json(json!(ApiResponse {
-                        status: "error".to_string(),
-                        code: 404,
-                        message: "User not found".to_string(),
-                        data: None,
-                        errors: Some(format!("User not found with ID: {}", params)),
-                    })),
-                )
-            }
-        }
-        Err(error) => handle_db_error(error).await,
+async fn save_changes(
+    state: &AppState,
+    oid: ObjectId,
+    params: &str,
+    mut changes: UserChanges,
+) -> ApiResult {
+    changes.updated_at = Some(DateTime::now());
+    let password_changed = changes.password.is_some();
+    let user = state
+        .users
+        .update(oid, changes)
+        .await?
+        .ok_or_else(|| user_not_found(params))?;
+    if password_changed {
+        // Sessions opened with the old password must not outlive it.
+        state.refresh_tokens.revoke_user(oid).await?;
     }
+
+    println!("User Updated with ID: {}", params);
+    Ok(success_response(
+        StatusCode::OK,
+        "User updated successfully",
+        Some(UserResponse::from(user)),
+    ))
+}
+
+/// Deletes the user and revokes their refresh tokens.
+pub async fn delete_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(params): Path<String>,
+) -> ApiResult {
+    let oid = parse_user_id(&params)?;
+    auth.require_owner_or(&oid, Permission::UsersWrite, "Deleting other users")?;
+
+    let user = state
+        .users
+        .delete(oid)
+        .await?
+        .ok_or_else(|| user_not_found(&params))?;
+    state.refresh_tokens.revoke_user(oid).await?;
+
+    println!("User Deleted with ID: {}", params);
+    Ok(success_response(
+        StatusCode::OK,
+        "User deleted successfully",
+        Some(UserResponse::from(user)),
+    ))
 }
 
 // pub async fn add_user(Json(mut payload): Json<User>) -> (StatusCode, Json<Value>) {
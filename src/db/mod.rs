@@ -1,58 +1,124 @@
-use lazy_static::lazy_static;
+pub mod schema;
+
+use std::{future::Future, pin::Pin, time::Duration};
+
 use mongodb::{
-    options::{ClientOptions, ServerApi, ServerApiVersion},
-    Client, Database,
+    bson::doc,
+    error::{TRANSIENT_TRANSACTION_ERROR, UNKNOWN_TRANSACTION_COMMIT_RESULT},
+    options::{ClientOptions, Collation, CollationStrength, ServerApi, ServerApiVersion},
+    Client, ClientSession,
 };
-use tokio::sync::OnceCell;
 
+use crate::{common_struct::AppError, config::Config};
 
-use crate::constants;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_TRANSACTION_ATTEMPTS: u32 = 5;
 
-lazy_static! {
-    pub static ref MONGO_CLIENT: OnceCell<Client> = OnceCell::new();
+/// Builds a client and pings the server, so an unreachable Mongo fails here
+/// rather than on the first request.
+async fn connect_once(config: &Config) -> mongodb::error::Result<Client> {
+    let mut client_options = ClientOptions::parse(&config.mongo_uri).await?;
+    let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
+    client_options.server_api = Some(server_api);
+    client_options.min_pool_size = Some(config.mongo_min_pool_size);
+    client_options.max_pool_size = Some(config.mongo_max_pool_size);
+    client_options.connect_timeout = Some(config.mongo_connect_timeout);
+    client_options.server_selection_timeout = Some(config.mongo_server_selection_timeout);
+
+    let client = Client::with_options(client_options)?;
+    client
+        .database(&config.database_name)
+        .run_command(doc! {"ping": 1})
+        .await?;
+    Ok(client)
 }
 
-pub async fn mongo_client() {
-    let uri = dotenv::var("MONGO_DB_URI").unwrap();
-    let client_options = ClientOptions::parse(uri.clone()).await;
-    match client_options {
-        Ok(mut client_options) => {
-            let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
-            client_options.server_api = Some(server_api);
-            let client = Client::with_uri_str(uri);
-            match client.await {
-                Ok(client) => {
-                    let _ = MONGO_CLIENT.set(client);
-                }
-                Err(error) => {
-                    println!("Error::>>{}", error);
-                }
+/// Tries `mongo_connect_attempts` times, doubling the delay between attempts.
+pub async fn connect(config: &Config) -> mongodb::error::Result<Client> {
+    let mut delay = config.mongo_retry_delay;
+    let mut attempt = 1;
+    loop {
+        match connect_once(config).await {
+            Ok(client) => return Ok(client),
+            Err(error) if attempt < config.mongo_connect_attempts => {
+                println!(
+                    "MongoDB connection attempt {}/{} failed: {}; retrying in {:?}",
+                    attempt, config.mongo_connect_attempts, error, delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+                attempt += 1;
             }
+            Err(error) => return Err(error),
         }
-        Err(error) => {
-            println!("40 Error::>>{}", error);
+    }
+}
+
+/// Case-insensitive comparison for emails. Queries must pass the same collation
+/// as the unique index in `schema` for Mongo to use it.
+pub fn email_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+/// The body of a transaction. It gets the session to pass to every operation.
+pub type TransactionFuture<'s, T> = Pin<Box<dyn Future<Output = Result<T, AppError>> + Send + 's>>;
+
+fn has_label(error: &AppError, label: &str) -> bool {
+    matches!(error, AppError::Database(error) if error.contains_label(label))
+}
+
+async fn commit(session: &mut ClientSession) -> Result<(), AppError> {
+    let mut attempt = 1;
+    loop {
+        match session.commit_transaction().await {
+            Ok(()) => return Ok(()),
+            // Committing again is safe; the server applies a transaction once.
+            Err(error)
+                if error.contains_label(UNKNOWN_TRANSACTION_COMMIT_RESULT)
+                    && attempt < MAX_TRANSACTION_ATTEMPTS =>
+            {
+                attempt += 1;
+            }
+            Err(error) => return Err(error.into()),
         }
     }
 }
 
-pub async fn connect_db() -> Result<Database, String> {
-    let client = MONGO_CLIENT.get();
-    match client {
-        Some(client) => {
-            let db = client.database(constants::DBNAME);
-            Ok(db)
+/// Runs `body` in a multi-document transaction. Failures labelled
+/// `TransientTransactionError` (write conflicts, elections) start the whole
+/// transaction over; an `UnknownTransactionCommitResult` retries the commit.
+/// Needs a replica set or sharded cluster.
+pub async fn with_transaction<T, F>(client: &Client, mut body: F) -> Result<T, AppError>
+where
+    F: for<'s> FnMut(&'s mut ClientSession) -> TransactionFuture<'s, T>,
+{
+    let mut session = client.start_session().await?;
+    let mut attempt = 1;
+    loop {
+        session.start_transaction().await?;
+        let result = match body(&mut session).await {
+            Ok(value) => commit(&mut session).await.map(|()| value),
+            Err(error) => {
+                // The server may already have aborted it; either way it is over.
+                let _ = session.abort_transaction().await;
+                Err(error)
+            }
+        };
+        match result {
+            Err(error)
+                if has_label(&error, TRANSIENT_TRANSACTION_ERROR)
+                    && attempt < MAX_TRANSACTION_ATTEMPTS =>
+            {
+                println!(
+                    "Transaction attempt {}/{} failed: {}; retrying",
+                    attempt, MAX_TRANSACTION_ATTEMPTS, error
+                );
+                attempt += 1;
+            }
+            result => return result,
         }
-        None => Err(format!("none 35")),
     }
-    // match client {
-    //     Ok(client) => {
-    //         println!("{:?}", constants::DBNAME);
-    //         let db = client.database(constants::DBNAME);
-    //         Ok(db)
-    //     }
-    //     Err(error) => {
-    //         println!("{}", error);
-    //         Err(error.to_owned())
-    //     }
-    // }
 }
@@ -0,0 +1,717 @@
+use std::time::Duration;
+
+use mongodb::{
+    bson::{self, doc, Bson, Document},
+    options::{Collation, IndexOptions},
+    Database, IndexModel,
+};
+
+use crate::{common_struct::AppError, constants};
+
+use super::email_collation;
+
+/// An index we expect to find on a collection, matched against the server by name.
+pub struct IndexSpec {
+    pub name: &'static str,
+    pub keys: Document,
+    pub unique: bool,
+    /// Leave documents without the key out of the index, so a unique index
+    /// only applies to documents that have the field.
+    pub sparse: bool,
+    /// Only index documents matching this filter, e.g. to make a unique
+    /// index apply to active documents alone.
+    pub partial_filter: Option<Document>,
+    pub collation: Option<Collation>,
+    pub expire_after: Option<Duration>,
+    /// Field weights of a text index.
+    pub weights: Option<Document>,
+}
+
+impl IndexSpec {
+    fn new(name: &'static str, keys: Document) -> Self {
+        IndexSpec {
+            name,
+            keys,
+            unique: false,
+            sparse: false,
+            partial_filter: None,
+            collation: None,
+            expire_after: None,
+            weights: None,
+        }
+    }
+
+    /// A text index over the weighted fields.
+    fn text(name: &'static str, weights: Document) -> Self {
+        let keys = weights.keys().map(|field| (field.clone(), "text".into()));
+        IndexSpec {
+            weights: Some(weights.clone()),
+            ..IndexSpec::new(name, keys.collect())
+        }
+    }
+
+    fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    fn sparse(mut self) -> Self {
+        self.sparse = true;
+        self
+    }
+
+    fn partial(mut self, filter: Document) -> Self {
+        self.partial_filter = Some(filter);
+        self
+    }
+
+    fn collation(mut self, collation: Collation) -> Self {
+        self.collation = Some(collation);
+        self
+    }
+
+    fn expire_after(mut self, ttl: Duration) -> Self {
+        self.expire_after = Some(ttl);
+        self
+    }
+
+    fn to_model(&self) -> IndexModel {
+        let options = IndexOptions::builder()
+            .name(self.name.to_string())
+            .unique(self.unique.then_some(true))
+            .sparse(self.sparse.then_some(true))
+            .partial_filter_expression(self.partial_filter.clone())
+            .collation(self.collation.clone())
+            .expire_after(self.expire_after)
+            .weights(self.weights.clone())
+            .build();
+        IndexModel::builder()
+            .keys(self.keys.clone())
+            .options(options)
+            .build()
+    }
+}
+
+/// Everything the app expects of one collection.
+pub struct CollectionSpec {
+    pub name: &'static str,
+    pub indexes: Vec<IndexSpec>,
+    pub validator: Option<Document>,
+}
+
+fn users() -> CollectionSpec {
+    CollectionSpec {
+        name: "users",
+        indexes: vec![IndexSpec::new("email_unique_ci", doc! {"email": 1})
+            .unique()
+            .collation(email_collation())],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["email", "password"],
+                "properties": {
+                    "firstName": {"bsonType": "string"},
+                    "lastName": {"bsonType": "string"},
+                    "email": {"bsonType": "string"},
+                    "password": {"bsonType": "string"},
+                    "roles": {"bsonType": "array", "items": {"enum": ["user", "admin"]}},
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn refresh_tokens() -> CollectionSpec {
+    CollectionSpec {
+        name: "refresh_tokens",
+        indexes: vec![
+            IndexSpec::new("token_hash_unique", doc! {"tokenHash": 1}).unique(),
+            IndexSpec::new("family_id", doc! {"familyId": 1}),
+            // Mongo removes each token once its own expiresAt has passed.
+            IndexSpec::new("expires_at_ttl", doc! {"expiresAt": 1})
+                .expire_after(Duration::from_secs(0)),
+        ],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["userId", "tokenHash", "familyId", "expiresAt", "createdAt"],
+                "properties": {
+                    "userId": {"bsonType": "objectId"},
+                    "tokenHash": {"bsonType": "string"},
+                    "familyId": {"bsonType": "objectId"},
+                    "expiresAt": {"bsonType": "date"},
+                    "revokedAt": {"bsonType": "date"},
+                    "createdAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+/// Money in minor units, never a double.
+fn money_schema() -> Document {
+    doc! {
+        "bsonType": "object",
+        "required": ["amount", "currency"],
+        "properties": {
+            "amount": {"bsonType": ["int", "long"], "minimum": 0},
+            "currency": {"bsonType": "string", "pattern": "^[A-Z]{3}$"},
+        },
+    }
+}
+
+fn products() -> CollectionSpec {
+    CollectionSpec {
+        name: "products",
+        indexes: vec![
+            IndexSpec::new("sku_unique", doc! {"sku": 1}).unique(),
+            IndexSpec::new("category_id", doc! {"categoryId": 1}),
+            IndexSpec::new("tags", doc! {"tags": 1}),
+            IndexSpec::text(
+                "text_search",
+                doc! {"name": 10, "tags": 5, "description": 1},
+            ),
+        ],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["name", "sku", "price"],
+                "properties": {
+                    "name": {"bsonType": "string"},
+                    "sku": {"bsonType": "string"},
+                    "description": {"bsonType": "string"},
+                    "price": money_schema(),
+                    "stockQuantity": {"bsonType": ["int", "long"], "minimum": 0},
+                    "categoryId": {"bsonType": "objectId"},
+                    "tags": {"bsonType": "array", "items": {"bsonType": "string"}},
+                    "images": {"bsonType": "array", "items": {"bsonType": "string"}},
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn categories() -> CollectionSpec {
+    CollectionSpec {
+        name: "categories",
+        indexes: vec![
+            IndexSpec::new("slug_unique", doc! {"slug": 1}).unique(),
+            IndexSpec::new("parent_id", doc! {"parentId": 1}),
+            IndexSpec::new("ancestors", doc! {"ancestors": 1}),
+        ],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["name", "slug", "ancestors"],
+                "properties": {
+                    "name": {"bsonType": "string"},
+                    "slug": {"bsonType": "string"},
+                    "parentId": {"bsonType": ["objectId", "null"]},
+                    "ancestors": {"bsonType": "array", "items": {"bsonType": "objectId"}},
+                    "revision": {"bsonType": ["int", "long"]},
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn reservations() -> CollectionSpec {
+    CollectionSpec {
+        name: "reservations",
+        indexes: vec![
+            IndexSpec::new("status_expires_at", doc! {"status": 1, "expiresAt": 1}),
+            IndexSpec::new("user_id", doc! {"userId": 1}),
+            // One active hold per user and product; more means releasing first.
+            IndexSpec::new("active_hold_unique", doc! {"userId": 1, "productId": 1})
+                .unique()
+                .partial(doc! {"status": "active"}),
+            // The sweeper restocks expired holds first; this only clears them out.
+            IndexSpec::new("expires_at_ttl", doc! {"expiresAt": 1})
+                .expire_after(Duration::from_secs(constants::RESERVATION_PURGE_AFTER_SECS)),
+        ],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["productId", "userId", "quantity", "status", "expiresAt"],
+                "properties": {
+                    "productId": {"bsonType": "objectId"},
+                    "userId": {"bsonType": "objectId"},
+                    "quantity": {"bsonType": ["int", "long"], "minimum": 1},
+                    "status": {"enum": ["active", "committed", "released", "expired"]},
+                    "reference": {"bsonType": "string"},
+                    "expiresAt": {"bsonType": "date"},
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn inventory_movements() -> CollectionSpec {
+    CollectionSpec {
+        name: "inventory_movements",
+        indexes: vec![IndexSpec::new(
+            "product_id",
+            doc! {"productId": 1, "_id": 1},
+        )],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["productId", "kind", "delta", "createdAt"],
+                "properties": {
+                    "productId": {"bsonType": "objectId"},
+                    "kind": {"enum": ["reserve", "release", "expire", "commit", "adjust"]},
+                    "delta": {"bsonType": ["int", "long"]},
+                    "reservationId": {"bsonType": "objectId"},
+                    "orderId": {"bsonType": "objectId"},
+                    "actorId": {"bsonType": "objectId"},
+                    "reason": {"bsonType": "string"},
+                    "createdAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn carts() -> CollectionSpec {
+    CollectionSpec {
+        name: "carts",
+        indexes: vec![
+            IndexSpec::new("user_id_unique", doc! {"userId": 1})
+                .unique()
+                .sparse(),
+            IndexSpec::new("anonymous_token_unique", doc! {"anonymousTokenHash": 1})
+                .unique()
+                .sparse(),
+            IndexSpec::new("updated_at_ttl", doc! {"updatedAt": 1})
+                .expire_after(Duration::from_secs(constants::CART_IDLE_TTL_SECS)),
+        ],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["items", "updatedAt"],
+                "properties": {
+                    "userId": {"bsonType": "objectId"},
+                    "anonymousTokenHash": {"bsonType": "string"},
+                    "items": {
+                        "bsonType": "array",
+                        "items": {
+                            "bsonType": "object",
+                            "required": ["productId", "quantity"],
+                            "properties": {
+                                "productId": {"bsonType": "objectId"},
+                                "quantity": {"bsonType": ["int", "long"], "minimum": 1},
+                                "addedAt": {"bsonType": "date"},
+                            },
+                        },
+                    },
+                    "couponCodes": {"bsonType": "array", "items": {"bsonType": "string"}},
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn order_statuses() -> Vec<&'static str> {
+    vec![
+        "pending",
+        "paid",
+        "fulfilled",
+        "shipped",
+        "delivered",
+        "cancelled",
+        "refunded",
+    ]
+}
+
+fn orders() -> CollectionSpec {
+    CollectionSpec {
+        name: "orders",
+        indexes: vec![
+            IndexSpec::new("user_id", doc! {"userId": 1, "_id": -1}),
+            IndexSpec::new("status", doc! {"status": 1}),
+        ],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["userId", "status", "lines", "total", "createdAt"],
+                "properties": {
+                    "userId": {"bsonType": "objectId"},
+                    "status": {"enum": order_statuses()},
+                    "statusHistory": {
+                        "bsonType": "array",
+                        "items": {
+                            "bsonType": "object",
+                            "required": ["status", "at"],
+                            "properties": {
+                                "from": {"enum": order_statuses()},
+                                "status": {"enum": order_statuses()},
+                                "actorId": {"bsonType": "objectId"},
+                                "reason": {"bsonType": "string"},
+                                "at": {"bsonType": "date"},
+                            },
+                        },
+                    },
+                    "lines": {
+                        "bsonType": "array",
+                        "minItems": 1,
+                        "items": {
+                            "bsonType": "object",
+                            "required": ["productId", "name", "sku", "quantity", "unitPrice", "lineTotal"],
+                            "properties": {
+                                "productId": {"bsonType": "objectId"},
+                                "name": {"bsonType": "string"},
+                                "sku": {"bsonType": "string"},
+                                "quantity": {"bsonType": ["int", "long"], "minimum": 1},
+                                "unitPrice": money_schema(),
+                                "lineTotal": money_schema(),
+                            },
+                        },
+                    },
+                    "discounts": {
+                        "bsonType": "array",
+                        "items": {
+                            "bsonType": "object",
+                            "required": ["code", "promotionId", "kind", "amount", "freeShipping"],
+                            "properties": {
+                                "code": {"bsonType": "string"},
+                                "promotionId": {"bsonType": "objectId"},
+                                "kind": {"bsonType": "string"},
+                                "amount": money_schema(),
+                                "freeShipping": {"bsonType": "bool"},
+                            },
+                        },
+                    },
+                    "total": money_schema(),
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn payments() -> CollectionSpec {
+    CollectionSpec {
+        name: "payments",
+        indexes: vec![
+            IndexSpec::new("order_id", doc! {"orderId": 1, "_id": 1}),
+            IndexSpec::new("active_order_unique", doc! {"activeOrderId": 1})
+                .unique()
+                .sparse(),
+            IndexSpec::new(
+                "provider_reference",
+                doc! {"provider": 1, "providerReference": 1},
+            ),
+        ],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["orderId", "userId", "provider", "status", "amount", "refunded"],
+                "properties": {
+                    "orderId": {"bsonType": "objectId"},
+                    "activeOrderId": {"bsonType": "objectId"},
+                    "userId": {"bsonType": "objectId"},
+                    "provider": {"bsonType": "string"},
+                    "status": {"enum": [
+                        "pending",
+                        "declined",
+                        "authorized",
+                        "captured",
+                        "failed",
+                        "partially_refunded",
+                        "refunded",
+                    ]},
+                    "amount": money_schema(),
+                    "refunded": money_schema(),
+                    "providerReference": {"bsonType": "string"},
+                    "declineCode": {"bsonType": "string"},
+                    "refunds": {
+                        "bsonType": "array",
+                        "items": {
+                            "bsonType": "object",
+                            "required": ["idempotencyKey", "status", "amount", "at"],
+                            "properties": {
+                                "idempotencyKey": {"bsonType": "string"},
+                                "status": {"enum": ["pending", "succeeded", "declined"]},
+                                "reference": {"bsonType": "string"},
+                                "declineCode": {"bsonType": "string"},
+                                "amount": money_schema(),
+                                "actorId": {"bsonType": "objectId"},
+                                "reason": {"bsonType": "string"},
+                                "at": {"bsonType": "date"},
+                            },
+                        },
+                    },
+                    "webhookEvents": {"bsonType": "array", "items": {"bsonType": "string"}},
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn promotions() -> CollectionSpec {
+    CollectionSpec {
+        name: "promotions",
+        indexes: vec![IndexSpec::new("code_unique", doc! {"code": 1}).unique()],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["code", "rule", "stackable", "redemptions", "createdAt"],
+                "properties": {
+                    "code": {"bsonType": "string", "pattern": "^[A-Z0-9_-]{3,32}$"},
+                    "description": {"bsonType": "string", "maxLength": 500},
+                    "rule": {
+                        "bsonType": "object",
+                        "required": ["type"],
+                        "properties": {
+                            "type": {"enum": [
+                                "percentage_off",
+                                "fixed_amount",
+                                "buy_x_get_y",
+                                "free_shipping",
+                            ]},
+                            "percent": {"bsonType": ["int", "long"], "minimum": 1, "maximum": 100},
+                            "amount": money_schema(),
+                            "buy": {"bsonType": ["int", "long"], "minimum": 1},
+                            "get": {"bsonType": ["int", "long"], "minimum": 1},
+                        },
+                    },
+                    "categoryId": {"bsonType": "objectId"},
+                    "stackable": {"bsonType": "bool"},
+                    "startsAt": {"bsonType": "date"},
+                    "endsAt": {"bsonType": "date"},
+                    "usageLimit": {"bsonType": ["int", "long"], "minimum": 1},
+                    "perUserLimit": {"bsonType": ["int", "long"], "minimum": 1},
+                    "redemptions": {"bsonType": ["int", "long"], "minimum": 0},
+                    "createdAt": {"bsonType": "date"},
+                    "updatedAt": {"bsonType": "date"},
+                },
+            }
+        }),
+    }
+}
+
+fn promotion_redemptions() -> CollectionSpec {
+    CollectionSpec {
+        name: "promotion_redemptions",
+        // Also what stops two first redemptions by one user from both inserting.
+        indexes: vec![IndexSpec::new(
+            "promotion_user_unique",
+            doc! {"promotionId": 1, "userId": 1},
+        )
+        .unique()],
+        validator: Some(doc! {
+            "$jsonSchema": {
+                "bsonType": "object",
+                "required": ["promotionId", "userId", "count"],
+                "properties": {
+                    "promotionId": {"bsonType": "objectId"},
+                    "userId": {"bsonType": "objectId"},
+                    "count": {"bsonType": ["int", "long"], "minimum": 1},
+                },
+            }
+        }),
+    }
+}
+
+/// The single source of truth for indexes and validators.
+pub fn collections() -> Vec<CollectionSpec> {
+    vec![
+        users(),
+        refresh_tokens(),
+        products(),
+        categories(),
+        reservations(),
+        inventory_movements(),
+        carts(),
+        orders(),
+        payments(),
+        promotions(),
+        promotion_redemptions(),
+    ]
+}
+
+fn collation_key(collation: &Option<Collation>) -> Option<(String, Bson)> {
+    collation.as_ref().map(|collation| {
+        (
+            collation.locale.clone(),
+            bson::to_bson(&collation.strength).unwrap_or(Bson::Null),
+        )
+    })
+}
+
+/// Weights as sorted `(field, weight)` pairs; the server may reorder them.
+fn weight_key(weights: &Option<Document>) -> Vec<(String, Option<i64>)> {
+    let mut pairs: Vec<_> = weights
+        .iter()
+        .flatten()
+        .map(|(field, weight)| (field.clone(), bson_number(weight)))
+        .collect();
+    pairs.sort();
+    pairs
+}
+
+fn bson_number(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(n) => Some(i64::from(*n)),
+        Bson::Int64(n) => Some(*n),
+        Bson::Double(n) => Some(*n as i64),
+        _ => None,
+    }
+}
+
+/// Describes how an existing index differs from its spec, if at all.
+fn index_drift(spec: &IndexSpec, existing: &IndexModel) -> Option<String> {
+    let options = existing.options.as_ref();
+    let unique = options.and_then(|o| o.unique).unwrap_or(false);
+    let sparse = options.and_then(|o| o.sparse).unwrap_or(false);
+    let partial_filter = options.and_then(|o| o.partial_filter_expression.clone());
+    let expire_after = options.and_then(|o| o.expire_after);
+    let collation = options.and_then(|o| o.collation.clone());
+    let weights = options.and_then(|o| o.weights.clone());
+
+    let mut drift = Vec::new();
+    if spec.weights.is_some() {
+        // The server reports text keys as `_fts`/`_ftsx`; the weights name the fields.
+        if weight_key(&weights) != weight_key(&spec.weights) {
+            drift.push("text weights differ".to_string());
+        }
+    } else if existing.keys != spec.keys {
+        drift.push(format!("keys {} != {}", existing.keys, spec.keys));
+    }
+    if unique != spec.unique {
+        drift.push(format!("unique {} != {}", unique, spec.unique));
+    }
+    if sparse != spec.sparse {
+        drift.push(format!("sparse {} != {}", sparse, spec.sparse));
+    }
+    if partial_filter != spec.partial_filter {
+        drift.push("partial filter differs".to_string());
+    }
+    if expire_after != spec.expire_after {
+        drift.push(format!(
+            "expireAfter {:?} != {:?}",
+            expire_after, spec.expire_after
+        ));
+    }
+    if collation_key(&collation) != collation_key(&spec.collation) {
+        drift.push("collation differs".to_string());
+    }
+
+    if drift.is_empty() {
+        None
+    } else {
+        Some(drift.join(", "))
+    }
+}
+
+async fn reconcile_validator(db: &Database, spec: &CollectionSpec) -> Result<(), AppError> {
+    let mut cursor = db
+        .list_collections()
+        .filter(doc! {"name": spec.name})
+        .await?;
+    if !cursor.advance().await? {
+        let mut create = db.create_collection(spec.name);
+        if let Some(validator) = &spec.validator {
+            create = create.validator(validator.clone());
+        }
+        create.await?;
+        println!("Created collection {}", spec.name);
+        return Ok(());
+    }
+    let current = cursor.deserialize_current()?.options.validator;
+
+    if let Some(validator) = &spec.validator {
+        if current.as_ref() != Some(validator) {
+            // Moderate leaves legacy documents editable until they are fixed up.
+            db.run_command(doc! {
+                "collMod": spec.name,
+                "validator": validator.clone(),
+                "validationLevel": "moderate",
+            })
+            .await?;
+            println!("Updated validator on {}", spec.name);
+        }
+    } else if current.is_some_and(|current| !current.is_empty()) {
+        println!(
+            "Warning: {} has a validator that is not declared",
+            spec.name
+        );
+    }
+    Ok(())
+}
+
+async fn reconcile_indexes(
+    db: &Database,
+    spec: &CollectionSpec,
+    drop_unknown: bool,
+) -> Result<(), AppError> {
+    let coll = db.collection::<Document>(spec.name);
+
+    let mut existing = Vec::new();
+    let mut cursor = coll.list_indexes().await?;
+    while cursor.advance().await? {
+        existing.push(cursor.deserialize_current()?);
+    }
+    let name_of = |index: &IndexModel| {
+        index
+            .options
+            .as_ref()
+            .and_then(|options| options.name.clone())
+            .unwrap_or_default()
+    };
+
+    for index in &spec.indexes {
+        match existing.iter().find(|model| name_of(model) == index.name) {
+            Some(model) => {
+                if let Some(drift) = index_drift(index, model) {
+                    println!(
+                        "Warning: index {}.{} has drifted: {}",
+                        spec.name, index.name, drift
+                    );
+                }
+            }
+            None => {
+                coll.create_index(index.to_model()).await?;
+                println!("Created index {}.{}", spec.name, index.name);
+            }
+        }
+    }
+
+    for model in &existing {
+        let name = name_of(model);
+        if name == "_id_" || spec.indexes.iter().any(|index| index.name == name) {
+            continue;
+        }
+        if drop_unknown {
+            coll.drop_index(name.as_str()).await?;
+            println!("Dropped unknown index {}.{}", spec.name, name);
+        } else {
+            println!("Warning: unknown index {}.{}", spec.name, name);
+        }
+    }
+    Ok(())
+}
+
+/// Brings the server in line with `collections()`: creates what is missing,
+/// updates validators, and reports indexes that drifted or are not declared.
+/// Unknown indexes are only dropped when `drop_unknown` is set.
+pub async fn reconcile(db: &Database, drop_unknown: bool) -> Result<(), AppError> {
+    for spec in collections() {
+        reconcile_validator(db, &spec).await?;
+        reconcile_indexes(db, &spec, drop_unknown).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,530 @@
+//! The stock ledger. `products.stockQuantity` is what can still be sold; every
+//! change to it goes through here and is recorded in `inventory_movements`.
+//! Holds, releases, commits, adjustments and expiries write the stock, the
+//! hold and the ledger entry in one transaction.
+//!
+//! Stock is only ever taken with a conditional `update_one` whose filter
+//! requires enough of it, so concurrent checkouts cannot oversell.
+
+use std::time::Duration;
+
+use mongodb::{
+    bson::{doc, oid::ObjectId, DateTime, Document},
+    ClientSession,
+};
+
+use crate::{
+    common_struct::{app_error::is_duplicate_key, AppError},
+    constants, db,
+    models::inventory_module::{InventoryMovement, MovementKind, Reservation, ReservationStatus},
+    state::AppState,
+};
+
+fn insufficient_stock(product_id: ObjectId) -> AppError {
+    AppError::Conflict {
+        code: "insufficient_stock",
+        message: format!("Not enough stock for product {}", product_id),
+    }
+}
+
+fn already_held(product_id: ObjectId) -> AppError {
+    AppError::Conflict {
+        code: "reservation_exists",
+        message: format!(
+            "You already hold product {}; release that reservation first",
+            product_id
+        ),
+    }
+}
+
+fn reservation_not_found(id: ObjectId) -> AppError {
+    AppError::NotFound(format!("Reservation not found with ID: {}", id))
+}
+
+/// A ledger entry for `delta` units of `product_id`, to be filled in further.
+fn movement(product_id: ObjectId, kind: MovementKind, delta: i64) -> InventoryMovement {
+    InventoryMovement {
+        id: None,
+        product_id,
+        kind,
+        delta,
+        reservation_id: None,
+        order_id: None,
+        actor_id: None,
+        reason: None,
+        created_at: DateTime::now(),
+    }
+}
+
+async fn record(
+    state: &AppState,
+    session: &mut ClientSession,
+    movement: InventoryMovement,
+) -> Result<(), AppError> {
+    state
+        .inventory_movements
+        .insert_one(movement)
+        .session(&mut *session)
+        .await?;
+    Ok(())
+}
+
+/// Moves an order's stock inside its transaction and records it against the order.
+async fn move_for_order(
+    state: &AppState,
+    session: &mut ClientSession,
+    order_id: ObjectId,
+    actor_id: Option<ObjectId>,
+    product_id: ObjectId,
+    kind: MovementKind,
+    delta: i64,
+) -> Result<(), AppError> {
+    let mut filter = doc! {"_id": product_id};
+    if delta < 0 {
+        filter.insert("stockQuantity", doc! {"$gte": -delta});
+    }
+    let moved = state
+        .products
+        .update_one(filter, doc! {"$inc": {"stockQuantity": delta}})
+        .session(&mut *session)
+        .await?;
+    if moved.matched_count == 0 && delta < 0 {
+        return Err(insufficient_stock(product_id));
+    }
+    record(
+        state,
+        session,
+        InventoryMovement {
+            order_id: Some(order_id),
+            actor_id,
+            ..movement(product_id, kind, delta)
+        },
+    )
+    .await
+}
+
+/// Holds `quantity` units for a new order. Fails with `insufficient_stock`,
+/// aborting the transaction, if there are not enough left.
+pub async fn take_for_order(
+    state: &AppState,
+    session: &mut ClientSession,
+    order_id: ObjectId,
+    actor_id: ObjectId,
+    product_id: ObjectId,
+    quantity: i64,
+) -> Result<(), AppError> {
+    move_for_order(
+        state,
+        session,
+        order_id,
+        Some(actor_id),
+        product_id,
+        MovementKind::Reserve,
+        -quantity,
+    )
+    .await
+}
+
+/// Puts back what `take_for_order` took, e.g. when the order is cancelled.
+pub async fn return_for_order(
+    state: &AppState,
+    session: &mut ClientSession,
+    order_id: ObjectId,
+    actor_id: Option<ObjectId>,
+    product_id: ObjectId,
+    quantity: i64,
+) -> Result<(), AppError> {
+    move_for_order(
+        state,
+        session,
+        order_id,
+        actor_id,
+        product_id,
+        MovementKind::Release,
+        quantity,
+    )
+    .await
+}
+
+/// Takes `quantity` units of `product_id` out of stock and holds them for
+/// `user_id` until the reservation is committed, released or expires. A user
+/// holds at most one active reservation per product. The stock, the hold and
+/// its ledger entry are written in one transaction.
+pub async fn reserve(
+    state: &AppState,
+    user_id: ObjectId,
+    product_id: ObjectId,
+    quantity: i64,
+    reference: Option<String>,
+) -> Result<Reservation, AppError> {
+    let client = state.db.client().clone();
+    db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let reference = reference.clone();
+        Box::pin(async move {
+            let taken = state
+                .products
+                .update_one(
+                    doc! {"_id": product_id, "stockQuantity": {"$gte": quantity}},
+                    doc! {"$inc": {"stockQuantity": -quantity}},
+                )
+                .session(&mut *session)
+                .await?;
+            if taken.matched_count == 0 {
+                let product = state
+                    .products
+                    .find_one(doc! {"_id": product_id})
+                    .session(&mut *session)
+                    .await?;
+                return Err(match product {
+                    Some(_) => insufficient_stock(product_id),
+                    None => AppError::invalid(
+                        "productId",
+                        "unknown_product",
+                        format!("No product with ID: {}", product_id),
+                    ),
+                });
+            }
+
+            let now = DateTime::now();
+            let mut reservation = Reservation {
+                id: None,
+                product_id,
+                user_id,
+                quantity,
+                status: ReservationStatus::Active,
+                reference,
+                expires_at: DateTime::from_millis(
+                    now.timestamp_millis() + constants::RESERVATION_TTL_SECS * 1000,
+                ),
+                created_at: now,
+                updated_at: now,
+            };
+            // The unique index on active holds turns a second one away.
+            let res = state
+                .reservations
+                .insert_one(&reservation)
+                .session(&mut *session)
+                .await
+                .map_err(|error| {
+                    if is_duplicate_key(&error) {
+                        already_held(product_id)
+                    } else {
+                        AppError::from(error)
+                    }
+                })?;
+            reservation.id = res.inserted_id.as_object_id();
+
+            record(
+                &state,
+                session,
+                InventoryMovement {
+                    reservation_id: reservation.id,
+                    actor_id: Some(user_id),
+                    ..movement(product_id, MovementKind::Reserve, -quantity)
+                },
+            )
+            .await?;
+            Ok(reservation)
+        })
+    })
+    .await
+}
+
+/// Moves an active reservation to `to`, or explains why it cannot move.
+/// `owner` limits the change to that user's reservations.
+async fn finish(
+    state: &AppState,
+    session: &mut ClientSession,
+    id: ObjectId,
+    owner: Option<ObjectId>,
+    extra_filter: Document,
+    to: ReservationStatus,
+) -> Result<Reservation, AppError> {
+    let mut filter = doc! {"_id": id, "status": ReservationStatus::Active.as_str()};
+    if let Some(owner) = owner {
+        filter.insert("userId", owner);
+    }
+    filter.extend(extra_filter);
+
+    let updated = state
+        .reservations
+        .find_one_and_update(
+            filter,
+            doc! {"$set": {
+                "status": to.as_str(),
+                "updatedAt": DateTime::now(),
+            }},
+        )
+        .session(&mut *session)
+        .await?;
+    if let Some(reservation) = updated {
+        return Ok(reservation);
+    }
+
+    let existing = state
+        .reservations
+        .find_one(doc! {"_id": id})
+        .session(&mut *session)
+        .await?
+        .filter(|reservation| owner.is_none_or(|owner| reservation.user_id == owner))
+        .ok_or_else(|| reservation_not_found(id))?;
+    Err(if existing.status != ReservationStatus::Active {
+        AppError::Conflict {
+            code: "reservation_not_active",
+            message: format!("Reservation {} is already {}", id, existing.status.as_str()),
+        }
+    } else {
+        AppError::Conflict {
+            code: "reservation_expired",
+            message: format!("Reservation {} has expired", id),
+        }
+    })
+}
+
+/// The value of `finish` for a reservation that was `find_one_and_update`d;
+/// the returned document still shows the old status.
+fn finished(mut reservation: Reservation, status: ReservationStatus) -> Reservation {
+    reservation.status = status;
+    reservation.updated_at = DateTime::now();
+    reservation
+}
+
+async fn restock(
+    state: &AppState,
+    session: &mut ClientSession,
+    reservation: &Reservation,
+) -> Result<(), AppError> {
+    state
+        .products
+        .update_one(
+            doc! {"_id": reservation.product_id},
+            doc! {"$inc": {"stockQuantity": reservation.quantity}},
+        )
+        .session(&mut *session)
+        .await?;
+    Ok(())
+}
+
+/// Returns a held quantity to stock, in one transaction with the hold's
+/// status change and its ledger entry.
+pub async fn release(
+    state: &AppState,
+    id: ObjectId,
+    owner: Option<ObjectId>,
+    actor_id: Option<ObjectId>,
+) -> Result<Reservation, AppError> {
+    let client = state.db.client().clone();
+    db::with_transaction(&client, |session| {
+        let state = state.clone();
+        Box::pin(async move {
+            let reservation = finish(
+                &state,
+                session,
+                id,
+                owner,
+                doc! {},
+                ReservationStatus::Released,
+            )
+            .await?;
+            restock(&state, session, &reservation).await?;
+            record(
+                &state,
+                session,
+                InventoryMovement {
+                    reservation_id: reservation.id,
+                    actor_id,
+                    ..movement(
+                        reservation.product_id,
+                        MovementKind::Release,
+                        reservation.quantity,
+                    )
+                },
+            )
+            .await?;
+            Ok(finished(reservation, ReservationStatus::Released))
+        })
+    })
+    .await
+}
+
+/// Turns a hold into a sale. Expired holds cannot be committed, even if the
+/// sweeper has not released them yet.
+pub async fn commit(
+    state: &AppState,
+    id: ObjectId,
+    actor_id: Option<ObjectId>,
+) -> Result<Reservation, AppError> {
+    let client = state.db.client().clone();
+    db::with_transaction(&client, |session| {
+        let state = state.clone();
+        Box::pin(async move {
+            let reservation = finish(
+                &state,
+                session,
+                id,
+                None,
+                doc! {"expiresAt": {"$gt": DateTime::now()}},
+                ReservationStatus::Committed,
+            )
+            .await?;
+            record(
+                &state,
+                session,
+                InventoryMovement {
+                    reservation_id: reservation.id,
+                    actor_id,
+                    ..movement(reservation.product_id, MovementKind::Commit, 0)
+                },
+            )
+            .await?;
+            Ok(finished(reservation, ReservationStatus::Committed))
+        })
+    })
+    .await
+}
+
+/// Restocks (positive `delta`) or writes off stock. A write-off never takes
+/// the stock below zero. Returns the new stock level.
+pub async fn adjust(
+    state: &AppState,
+    product_id: ObjectId,
+    delta: i64,
+    reason: String,
+    actor_id: Option<ObjectId>,
+) -> Result<i64, AppError> {
+    let client = state.db.client().clone();
+    db::with_transaction(&client, |session| {
+        let state = state.clone();
+        let reason = reason.clone();
+        Box::pin(async move {
+            let mut filter = doc! {"_id": product_id};
+            if delta < 0 {
+                filter.insert("stockQuantity", doc! {"$gte": -delta});
+            }
+            let product = state
+                .products
+                .find_one_and_update(filter, doc! {"$inc": {"stockQuantity": delta}})
+                .return_document(mongodb::options::ReturnDocument::After)
+                .session(&mut *session)
+                .await?;
+            let Some(product) = product else {
+                let existing = state
+                    .products
+                    .find_one(doc! {"_id": product_id})
+                    .session(&mut *session)
+                    .await?;
+                return Err(match existing {
+                    Some(_) => insufficient_stock(product_id),
+                    None => {
+                        AppError::NotFound(format!("Product not found with ID: {}", product_id))
+                    }
+                });
+            };
+
+            record(
+                &state,
+                session,
+                InventoryMovement {
+                    actor_id,
+                    reason: Some(reason),
+                    ..movement(product_id, MovementKind::Adjust, delta)
+                },
+            )
+            .await?;
+            Ok(product.stock_quantity.unwrap_or_default())
+        })
+    })
+    .await
+}
+
+/// Records the stock a product was created with, in the transaction that
+/// inserts the product.
+pub async fn record_initial_stock(
+    state: &AppState,
+    session: &mut ClientSession,
+    product_id: ObjectId,
+    quantity: i64,
+    actor_id: ObjectId,
+) -> Result<(), AppError> {
+    record(
+        state,
+        session,
+        InventoryMovement {
+            actor_id: Some(actor_id),
+            reason: Some("initial stock".to_string()),
+            ..movement(product_id, MovementKind::Adjust, quantity)
+        },
+    )
+    .await
+}
+
+/// Releases every active reservation whose hold has run out. Returns how many.
+pub async fn expire_due(state: &AppState) -> Result<u64, AppError> {
+    let client = state.db.client().clone();
+    let mut expired = 0;
+    loop {
+        // One hold per transaction, so two sweepers never restock the same
+        // hold twice and a failure never leaves one expired but not restocked.
+        let due = db::with_transaction(&client, |session| {
+            let state = state.clone();
+            Box::pin(async move {
+                let due = state
+                    .reservations
+                    .find_one_and_update(
+                        doc! {
+                            "status": ReservationStatus::Active.as_str(),
+                            "expiresAt": {"$lte": DateTime::now()},
+                        },
+                        doc! {"$set": {
+                            "status": ReservationStatus::Expired.as_str(),
+                            "updatedAt": DateTime::now(),
+                        }},
+                    )
+                    .session(&mut *session)
+                    .await?;
+                let Some(reservation) = due else {
+                    return Ok(false);
+                };
+                restock(&state, session, &reservation).await?;
+                record(
+                    &state,
+                    session,
+                    InventoryMovement {
+                        reservation_id: reservation.id,
+                        ..movement(
+                            reservation.product_id,
+                            MovementKind::Expire,
+                            reservation.quantity,
+                        )
+                    },
+                )
+                .await?;
+                Ok(true)
+            })
+        })
+        .await?;
+        if !due {
+            return Ok(expired);
+        }
+        expired += 1;
+    }
+}
+
+/// Runs `expire_due` in the background for the life of the process. The TTL
+/// index on `reservations` only purges holds well after this has restocked them.
+pub fn spawn_reservation_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            constants::RESERVATION_SWEEP_INTERVAL_SECS,
+        ));
+        loop {
+            interval.tick().await;
+            match expire_due(&state).await {
+                Ok(0) => {}
+                Ok(count) => println!("Expired {} stock reservation(s)", count),
+                Err(error) => println!("Failed to expire reservations: {}", error),
+            }
+        }
+    });
+}
@@ -1,24 +1,56 @@
+mod auth;
+mod config;
 mod constants;
 mod controllers;
 mod db;
+mod inventory;
 mod models;
+mod payments;
+mod repositories;
 mod routers;
 mod common_struct;
-use mongodb::bson::{doc, oid::ObjectId, Document};
+mod state;
+#[cfg(test)]
+mod test_support;
+use std::{net::SocketAddr, sync::Arc};
+
+use config::Config;
 use routers::router;
+use state::AppState;
 #[tokio::main]
 async fn main() {
     dotenv::from_filename(".env").ok();
-    println!("{}", dotenv::var("PORT").unwrap());
-    let port = dotenv::var("PORT").unwrap();
-    let addr = format!("localhost:{}", port);
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(error) => {
+            eprintln!("Invalid configuration:\n{}", error);
+            std::process::exit(1);
+        }
+    };
+    // Even with `user_store = memory` only users and their refresh tokens stay
+    // in process; products, carts, orders and the rest always need MongoDB.
+    let client = match db::connect(&config).await {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("Could not connect to MongoDB: {}", error);
+            std::process::exit(1);
+        }
+    };
+    let state = AppState::new(config.clone(), client);
+    // Serving without the unique indexes and validators would let bad data in.
+    if let Err(error) = db::schema::reconcile(&state.db, config.drop_unknown_indexes).await {
+        eprintln!("Failed to reconcile schema: {}", error);
+        std::process::exit(1);
+    }
+    inventory::spawn_reservation_sweeper(state.clone());
+
+    let addr = SocketAddr::new(config.bind_address, config.port);
     let listener = tokio::net::TcpListener::bind(addr).await;
     match listener {
         Ok(listener) => {
-            let _connection = db::mongo_client().await;
-            println!("Server Started on port:{}", port);
+            println!("Server Started on {}", addr);
             // controllers::user_controller::get_user().await;
-            let app = router().await;
+            let app = router(state).await;
             let serve = axum::serve(listener, app).await;
             match serve {
                 Ok(_serve) => {
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::{
+    category_module::validate_object_id, product_module::Product, promotion_module::CouponSummary,
+};
+use crate::common_struct::{
+    app_error::validate_required,
+    money::{Money, MoneyError},
+    AppError,
+};
+
+/// Identifies an anonymous cart. Issued on its first write, sent back on
+/// every cart request and on login, where the cart is merged into the user's.
+pub const CART_TOKEN_HEADER: &str = "x-cart-token";
+
+/// The most units of one product a cart line may hold, however they were added.
+pub const MAX_LINE_QUANTITY: i64 = 1000;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CartItem {
+    #[serde(rename = "productId")]
+    pub product_id: ObjectId,
+    pub quantity: i64,
+    #[serde(rename = "addedAt")]
+    pub added_at: DateTime,
+}
+
+/// Carts only store what was picked and how many; prices are looked up
+/// again every time the cart is read.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Cart {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "userId", skip_serializing_if = "Option::is_none")]
+    pub user_id: Option<ObjectId>,
+    #[serde(rename = "anonymousTokenHash", skip_serializing_if = "Option::is_none")]
+    pub anonymous_token_hash: Option<String>,
+    #[serde(default)]
+    pub items: Vec<CartItem>,
+    /// Upper-cased promotion codes, in the order they were applied.
+    #[serde(rename = "couponCodes", default)]
+    pub coupon_codes: Vec<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LineStatus {
+    Available,
+    /// The product was deleted or has no price.
+    Unavailable,
+    OutOfStock,
+    /// Some, but fewer than the requested quantity, are in stock.
+    InsufficientStock,
+    /// Priced in a different currency from the rest of the cart.
+    CurrencyMismatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CartLine {
+    #[serde(rename = "productId")]
+    pub product_id: String,
+    pub name: Option<String>,
+    pub sku: Option<String>,
+    pub quantity: i64,
+    #[serde(rename = "unitPrice")]
+    pub unit_price: Option<Money>,
+    #[serde(rename = "lineTotal")]
+    pub line_total: Option<Money>,
+    #[serde(rename = "availableQuantity")]
+    pub available_quantity: i64,
+    pub status: LineStatus,
+}
+
+/// Cart lines priced against the current catalogue.
+#[derive(Debug, Clone, Serialize)]
+pub struct PricedCart {
+    pub lines: Vec<CartLine>,
+    /// The total of the available lines, in the cart's currency.
+    pub subtotal: Option<Money>,
+    /// Whether every line can be bought as it stands.
+    #[serde(rename = "checkoutReady")]
+    pub checkout_ready: bool,
+}
+
+/// Prices `items` with `products`. The cart's currency is that of the first
+/// priced line; only available lines count toward the subtotal.
+pub fn price_items(
+    items: &[CartItem],
+    products: &HashMap<ObjectId, Product>,
+) -> Result<PricedCart, MoneyError> {
+    let currency = items
+        .iter()
+        .filter_map(|item| products.get(&item.product_id)?.price)
+        .map(|price| price.currency())
+        .next();
+
+    let mut lines = Vec::with_capacity(items.len());
+    for item in items {
+        let product = products.get(&item.product_id);
+        let price = product.and_then(|product| product.price);
+        let stock = product
+            .and_then(|product| product.stock_quantity)
+            .unwrap_or_default();
+        let status = match price {
+            None => LineStatus::Unavailable,
+            Some(_) if stock <= 0 => LineStatus::OutOfStock,
+            Some(_) if stock < item.quantity => LineStatus::InsufficientStock,
+            Some(price) if Some(price.currency()) != currency => LineStatus::CurrencyMismatch,
+            Some(_) => LineStatus::Available,
+        };
+        lines.push(CartLine {
+            product_id: item.product_id.to_hex(),
+            name: product.and_then(|product| product.name.clone()),
+            sku: product.and_then(|product| product.sku.clone()),
+            quantity: item.quantity,
+            unit_price: price,
+            line_total: price
+                .map(|price| price.checked_mul(item.quantity))
+                .transpose()?,
+            available_quantity: stock.max(0),
+            status,
+        });
+    }
+
+    let subtotal = currency
+        .map(|currency| {
+            Money::sum(
+                currency,
+                lines
+                    .iter()
+                    .filter(|line| line.status == LineStatus::Available)
+                    .filter_map(|line| line.line_total.as_ref()),
+            )
+        })
+        .transpose()?;
+    let checkout_ready = !lines.is_empty()
+        && lines
+            .iter()
+            .all(|line| line.status == LineStatus::Available);
+    Ok(PricedCart {
+        lines,
+        subtotal,
+        checkout_ready,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct CartResponse {
+    /// `None` until the cart has been written to.
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub priced: PricedCart,
+    #[serde(rename = "couponCodes")]
+    pub coupon_codes: Vec<String>,
+    /// What the coupons take off, line by line, and the total after them.
+    #[serde(flatten)]
+    pub coupons: CouponSummary,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CartItemPayload {
+    #[serde(rename = "productId")]
+    #[validate(custom(function = "validate_object_id"))]
+    pub product_id: Option<String>,
+    #[validate(range(
+        min = 1,
+        max = 1000,
+        code = "out_of_range",
+        message = "quantity must be between 1 and 1000"
+    ))]
+    pub quantity: Option<i64>,
+}
+
+impl CartItemPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[
+                ("productId", self.product_id.is_none()),
+                ("quantity", self.quantity.is_none()),
+            ],
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CartQuantityPayload {
+    #[validate(range(
+        min = 1,
+        max = 1000,
+        code = "out_of_range",
+        message = "quantity must be between 1 and 1000"
+    ))]
+    pub quantity: Option<i64>,
+}
+
+impl CartQuantityPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(self, &[("quantity", self.quantity.is_none())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_struct::money::Currency;
+
+    fn product(id: ObjectId, price: Option<(i64, &str)>, stock: i64) -> Product {
+        Product {
+            id: Some(id),
+            name: Some("Widget".to_string()),
+            sku: Some("W-1".to_string()),
+            description: None,
+            price: price
+                .map(|(units, code)| Money::from_minor(units, Currency::from_code(code).unwrap())),
+            stock_quantity: Some(stock),
+            category_id: None,
+            tags: None,
+            images: None,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn item(product_id: ObjectId, quantity: i64) -> CartItem {
+        CartItem {
+            product_id,
+            quantity,
+            added_at: DateTime::now(),
+        }
+    }
+
+    #[test]
+    fn prices_lines_and_flags_what_cannot_be_bought() {
+        let ids: Vec<ObjectId> = (0..6).map(|_| ObjectId::new()).collect();
+        let products: HashMap<_, _> = [
+            product(ids[0], Some((250, "USD")), 10),
+            product(ids[1], Some((100, "USD")), 0),
+            product(ids[2], Some((100, "USD")), 1),
+            product(ids[3], Some((100, "EUR")), 5),
+            product(ids[4], None, 5),
+        ]
+        .into_iter()
+        .map(|product| (product.id.unwrap(), product))
+        .collect();
+        let items: Vec<_> = ids.iter().map(|id| item(*id, 2)).collect();
+
+        let priced = price_items(&items, &products).unwrap();
+
+        let statuses: Vec<_> = priced.lines.iter().map(|line| line.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                LineStatus::Available,
+                LineStatus::OutOfStock,
+                LineStatus::InsufficientStock,
+                LineStatus::CurrencyMismatch,
+                LineStatus::Unavailable,
+                LineStatus::Unavailable,
+            ]
+        );
+        assert_eq!(priced.lines[0].line_total.unwrap().minor_units(), 500);
+        assert_eq!(priced.subtotal.unwrap().minor_units(), 500);
+        assert!(!priced.checkout_ready);
+    }
+
+    #[test]
+    fn an_empty_cart_has_no_subtotal_and_cannot_check_out() {
+        let priced = price_items(&[], &HashMap::new()).unwrap();
+
+        assert!(priced.subtotal.is_none());
+        assert!(!priced.checkout_ready);
+    }
+}
@@ -0,0 +1,122 @@
+use lazy_static::lazy_static;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::common_struct::{app_error::validate_required, AppError};
+
+lazy_static! {
+    static ref SLUG_PATTERN: Regex = Regex::new(r"^[a-z0-9]+(-[a-z0-9]+)*$").unwrap();
+}
+
+/// A node in the category tree. `ancestors` lists every category above this
+/// one, root first, so a subtree is a single `{ancestors: id}` query.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Category {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub slug: String,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<ObjectId>,
+    #[serde(default)]
+    pub ancestors: Vec<ObjectId>,
+    #[serde(rename = "createdAt", skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime>,
+    #[serde(rename = "updatedAt", skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime>,
+}
+
+impl Category {
+    /// The `ancestors` a direct child of this category gets.
+    pub fn child_ancestors(&self) -> Vec<ObjectId> {
+        let mut ancestors = self.ancestors.clone();
+        ancestors.extend(self.id);
+        ancestors
+    }
+}
+
+pub fn validate_object_id(value: &str) -> Result<(), ValidationError> {
+    ObjectId::parse_str(value).map(|_| ()).map_err(|_| {
+        ValidationError::new("invalid_id").with_message("must be a 24-character hex id".into())
+    })
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CategoryPayload {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        code = "invalid_length",
+        message = "name must be 1 to 100 characters"
+    ))]
+    pub name: Option<String>,
+    #[validate(
+        length(
+            max = 100,
+            code = "invalid_length",
+            message = "slug must be at most 100 characters"
+        ),
+        regex(
+            path = *SLUG_PATTERN,
+            code = "invalid_slug",
+            message = "slug must be lowercase words joined by '-'"
+        )
+    )]
+    pub slug: Option<String>,
+    /// Omitted or `null` for a root category.
+    #[serde(rename = "parentId")]
+    #[validate(custom(function = "validate_object_id"))]
+    pub parent_id: Option<String>,
+}
+
+impl CategoryPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[("name", self.name.is_none()), ("slug", self.slug.is_none())],
+        )
+    }
+}
+
+/// Body of `POST /api/v1/categories/:id/move`.
+#[derive(Debug, Deserialize, Validate)]
+pub struct MoveCategoryPayload {
+    /// The new parent, or `null` to make the category a root.
+    #[serde(rename = "parentId")]
+    #[validate(custom(function = "validate_object_id"))]
+    pub parent_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryResponse {
+    pub id: String,
+    pub name: String,
+    pub slug: String,
+    #[serde(rename = "parentId")]
+    pub parent_id: Option<String>,
+    pub ancestors: Vec<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+impl From<Category> for CategoryResponse {
+    fn from(category: Category) -> Self {
+        CategoryResponse {
+            id: category.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: category.name,
+            slug: category.slug,
+            parent_id: category.parent_id.map(|id| id.to_hex()),
+            ancestors: category.ancestors.iter().map(|id| id.to_hex()).collect(),
+            created_at: category
+                .created_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+            updated_at: category
+                .updated_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use super::category_module::validate_object_id;
+use crate::common_struct::{app_error::validate_required, AppError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReservationStatus {
+    /// Stock is held and will come back if the hold is not committed in time.
+    Active,
+    /// The hold became a sale; the stock is gone for good.
+    Committed,
+    Released,
+    Expired,
+}
+
+impl ReservationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReservationStatus::Active => "active",
+            ReservationStatus::Committed => "committed",
+            ReservationStatus::Released => "released",
+            ReservationStatus::Expired => "expired",
+        }
+    }
+}
+
+/// Stock taken out of `stockQuantity` on someone's behalf until it is
+/// committed, released or expires.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Reservation {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "productId")]
+    pub product_id: ObjectId,
+    #[serde(rename = "userId")]
+    pub user_id: ObjectId,
+    pub quantity: i64,
+    pub status: ReservationStatus,
+    /// Free-form link to whatever holds the stock, e.g. a cart or order id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: DateTime,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MovementKind {
+    Reserve,
+    Release,
+    Expire,
+    Commit,
+    Adjust,
+}
+
+/// One line of the append-only stock ledger. `delta` is the change to the
+/// product's `stockQuantity`; a commit moves no stock and records 0.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct InventoryMovement {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "productId")]
+    pub product_id: ObjectId,
+    pub kind: MovementKind,
+    pub delta: i64,
+    #[serde(rename = "reservationId", skip_serializing_if = "Option::is_none")]
+    pub reservation_id: Option<ObjectId>,
+    /// Set when the stock was held or given back by an order.
+    #[serde(rename = "orderId", skip_serializing_if = "Option::is_none")]
+    pub order_id: Option<ObjectId>,
+    /// Who caused the movement; `None` for the expiry sweeper.
+    #[serde(rename = "actorId", skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct ReservationPayload {
+    #[serde(rename = "productId")]
+    #[validate(custom(function = "validate_object_id"))]
+    pub product_id: Option<String>,
+    #[validate(range(
+        min = 1,
+        max = 1000,
+        code = "out_of_range",
+        message = "quantity must be between 1 and 1000"
+    ))]
+    pub quantity: Option<i64>,
+    #[validate(length(
+        max = 100,
+        code = "invalid_length",
+        message = "reference must be at most 100 characters"
+    ))]
+    pub reference: Option<String>,
+}
+
+impl ReservationPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[
+                ("productId", self.product_id.is_none()),
+                ("quantity", self.quantity.is_none()),
+            ],
+        )
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct AdjustmentPayload {
+    /// Units to add (positive) or write off (negative); never zero.
+    #[validate(range(
+        min = -1_000_000,
+        max = 1_000_000,
+        code = "out_of_range",
+        message = "delta must be between -1000000 and 1000000"
+    ))]
+    pub delta: Option<i64>,
+    #[validate(length(
+        min = 1,
+        max = 200,
+        code = "invalid_length",
+        message = "reason must be 1 to 200 characters"
+    ))]
+    pub reason: Option<String>,
+}
+
+impl AdjustmentPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[
+                ("delta", self.delta.is_none()),
+                ("reason", self.reason.is_none()),
+            ],
+        )?;
+        if self.delta == Some(0) {
+            return Err(AppError::invalid(
+                "delta",
+                "out_of_range",
+                "delta cannot be 0",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReservationResponse {
+    pub id: String,
+    #[serde(rename = "productId")]
+    pub product_id: String,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub quantity: i64,
+    pub status: ReservationStatus,
+    pub reference: Option<String>,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+impl From<Reservation> for ReservationResponse {
+    fn from(reservation: Reservation) -> Self {
+        ReservationResponse {
+            id: reservation.id.map(|id| id.to_hex()).unwrap_or_default(),
+            product_id: reservation.product_id.to_hex(),
+            user_id: reservation.user_id.to_hex(),
+            quantity: reservation.quantity,
+            status: reservation.status,
+            reference: reservation.reference,
+            expires_at: reservation.expires_at.try_to_rfc3339_string().ok(),
+            created_at: reservation.created_at.try_to_rfc3339_string().ok(),
+            updated_at: reservation.updated_at.try_to_rfc3339_string().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MovementResponse {
+    pub id: String,
+    #[serde(rename = "productId")]
+    pub product_id: String,
+    pub kind: MovementKind,
+    pub delta: i64,
+    #[serde(rename = "reservationId")]
+    pub reservation_id: Option<String>,
+    #[serde(rename = "orderId")]
+    pub order_id: Option<String>,
+    #[serde(rename = "actorId")]
+    pub actor_id: Option<String>,
+    pub reason: Option<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+}
+
+impl From<InventoryMovement> for MovementResponse {
+    fn from(movement: InventoryMovement) -> Self {
+        MovementResponse {
+            id: movement.id.map(|id| id.to_hex()).unwrap_or_default(),
+            product_id: movement.product_id.to_hex(),
+            kind: movement.kind,
+            delta: movement.delta,
+            reservation_id: movement.reservation_id.map(|id| id.to_hex()),
+            order_id: movement.order_id.map(|id| id.to_hex()),
+            actor_id: movement.actor_id.map(|id| id.to_hex()),
+            reason: movement.reason,
+            created_at: movement.created_at.try_to_rfc3339_string().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MovementListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<u64>,
+    pub cursor: Option<String>,
+}
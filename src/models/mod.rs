@@ -1 +1,9 @@
-pub mod user_module;
\ No newline at end of file
+pub mod auth_module;
+pub mod cart_module;
+pub mod category_module;
+pub mod inventory_module;
+pub mod order_module;
+pub mod payment_module;
+pub mod product_module;
+pub mod promotion_module;
+pub mod user_module;
@@ -0,0 +1,279 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::common_struct::{
+    app_error::validate_required,
+    money::{self, Money},
+    AppError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    /// Placed and holding its stock, waiting for payment.
+    Pending,
+    Paid,
+    /// Picked and packed.
+    Fulfilled,
+    Shipped,
+    Delivered,
+    /// Called off before fulfilment; its stock goes back on sale and any
+    /// captured payment is refunded.
+    Cancelled,
+    /// Paid back in full. Only a refund moves an order here; its stock goes
+    /// back on sale if it had not shipped.
+    Refunded,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Fulfilled => "fulfilled",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        }
+    }
+
+    /// The transition table: the statuses an order may move to from this one.
+    pub fn next(&self) -> &'static [OrderStatus] {
+        match self {
+            OrderStatus::Pending => &[OrderStatus::Paid, OrderStatus::Cancelled],
+            OrderStatus::Paid => &[
+                OrderStatus::Fulfilled,
+                OrderStatus::Cancelled,
+                OrderStatus::Refunded,
+            ],
+            OrderStatus::Fulfilled => &[OrderStatus::Shipped, OrderStatus::Refunded],
+            OrderStatus::Shipped => &[OrderStatus::Delivered, OrderStatus::Refunded],
+            OrderStatus::Delivered => &[OrderStatus::Refunded],
+            OrderStatus::Cancelled | OrderStatus::Refunded => &[],
+        }
+    }
+
+    pub fn can_become(&self, to: OrderStatus) -> bool {
+        self.next().contains(&to)
+    }
+
+    /// Whether the goods are still in the warehouse.
+    pub fn is_unshipped(&self) -> bool {
+        matches!(
+            self,
+            OrderStatus::Pending | OrderStatus::Paid | OrderStatus::Fulfilled
+        )
+    }
+}
+
+/// One entry in an order's status history.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StatusChange {
+    /// `None` for the entry written when the order was placed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<OrderStatus>,
+    pub status: OrderStatus,
+    /// Who made the change; `None` when the system did, e.g. a payment webhook.
+    #[serde(rename = "actorId", skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub at: DateTime,
+}
+
+/// A product as it was when the order was placed. Later catalogue edits
+/// leave it alone.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OrderLine {
+    #[serde(rename = "productId")]
+    pub product_id: ObjectId,
+    pub name: String,
+    pub sku: String,
+    pub quantity: i64,
+    #[serde(rename = "unitPrice", with = "money::stored")]
+    pub unit_price: Money,
+    #[serde(rename = "lineTotal", with = "money::stored")]
+    pub line_total: Money,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OrderDiscount {
+    pub code: String,
+    #[serde(rename = "promotionId")]
+    pub promotion_id: ObjectId,
+    pub kind: String,
+    #[serde(with = "money::stored")]
+    pub amount: Money,
+    #[serde(rename = "freeShipping")]
+    pub free_shipping: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Order {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "userId")]
+    pub user_id: ObjectId,
+    pub status: OrderStatus,
+    /// Every status the order has had, oldest first.
+    #[serde(rename = "statusHistory", default)]
+    pub status_history: Vec<StatusChange>,
+    pub lines: Vec<OrderLine>,
+    /// Coupons redeemed with the order; `total` is already net of them.
+    #[serde(default)]
+    pub discounts: Vec<OrderDiscount>,
+    #[serde(with = "money::stored")]
+    pub total: Money,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderLineResponse {
+    #[serde(rename = "productId")]
+    pub product_id: String,
+    pub name: String,
+    pub sku: String,
+    pub quantity: i64,
+    #[serde(rename = "unitPrice")]
+    pub unit_price: Money,
+    #[serde(rename = "lineTotal")]
+    pub line_total: Money,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderDiscountResponse {
+    pub code: String,
+    #[serde(rename = "promotionId")]
+    pub promotion_id: String,
+    pub kind: String,
+    pub amount: Money,
+    #[serde(rename = "freeShipping")]
+    pub free_shipping: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatusChangeResponse {
+    pub from: Option<OrderStatus>,
+    pub status: OrderStatus,
+    #[serde(rename = "actorId")]
+    pub actor_id: Option<String>,
+    pub reason: Option<String>,
+    pub at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderResponse {
+    pub id: String,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub status: OrderStatus,
+    #[serde(rename = "statusHistory")]
+    pub status_history: Vec<StatusChangeResponse>,
+    pub lines: Vec<OrderLineResponse>,
+    pub discounts: Vec<OrderDiscountResponse>,
+    pub total: Money,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+impl From<Order> for OrderResponse {
+    fn from(order: Order) -> Self {
+        OrderResponse {
+            id: order.id.map(|id| id.to_hex()).unwrap_or_default(),
+            user_id: order.user_id.to_hex(),
+            status: order.status,
+            status_history: order
+                .status_history
+                .into_iter()
+                .map(|change| StatusChangeResponse {
+                    from: change.from,
+                    status: change.status,
+                    actor_id: change.actor_id.map(|id| id.to_hex()),
+                    reason: change.reason,
+                    at: change.at.try_to_rfc3339_string().ok(),
+                })
+                .collect(),
+            lines: order
+                .lines
+                .into_iter()
+                .map(|line| OrderLineResponse {
+                    product_id: line.product_id.to_hex(),
+                    name: line.name,
+                    sku: line.sku,
+                    quantity: line.quantity,
+                    unit_price: line.unit_price,
+                    line_total: line.line_total,
+                })
+                .collect(),
+            discounts: order
+                .discounts
+                .into_iter()
+                .map(|discount| OrderDiscountResponse {
+                    code: discount.code,
+                    promotion_id: discount.promotion_id.to_hex(),
+                    kind: discount.kind,
+                    amount: discount.amount,
+                    free_shipping: discount.free_shipping,
+                })
+                .collect(),
+            total: order.total,
+            created_at: order.created_at.try_to_rfc3339_string().ok(),
+            updated_at: order.updated_at.try_to_rfc3339_string().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrderListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<u64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct OrderStatusPayload {
+    pub status: Option<OrderStatus>,
+    #[validate(length(
+        min = 1,
+        max = 200,
+        code = "invalid_length",
+        message = "reason must be 1 to 200 characters"
+    ))]
+    pub reason: Option<String>,
+}
+
+impl OrderStatusPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(self, &[("status", self.status.is_none())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_only_move_forward_along_the_table() {
+        assert!(OrderStatus::Pending.can_become(OrderStatus::Paid));
+        assert!(OrderStatus::Paid.can_become(OrderStatus::Cancelled));
+        assert!(OrderStatus::Delivered.can_become(OrderStatus::Refunded));
+        assert!(OrderStatus::Paid.can_become(OrderStatus::Refunded));
+        assert!(!OrderStatus::Pending.can_become(OrderStatus::Refunded));
+        assert!(!OrderStatus::Pending.can_become(OrderStatus::Shipped));
+        assert!(!OrderStatus::Shipped.can_become(OrderStatus::Cancelled));
+        assert!(!OrderStatus::Paid.can_become(OrderStatus::Pending));
+    }
+
+    #[test]
+    fn cancelled_and_refunded_orders_are_final() {
+        assert!(OrderStatus::Cancelled.next().is_empty());
+        assert!(OrderStatus::Refunded.next().is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+use mongodb::bson::{oid::ObjectId, DateTime};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::common_struct::{
+    app_error::validate_required,
+    money::{self, Money, MoneyInput},
+    AppError,
+};
+
+/// Names the webhook signature header for every provider.
+pub const PAYMENT_SIGNATURE_HEADER: &str = "x-payment-signature";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    /// Recorded before the provider is called; stays here if the call never returns.
+    Pending,
+    Declined,
+    /// Held on the card but not taken yet.
+    Authorized,
+    Captured,
+    /// The provider accepted the authorization but refused the capture.
+    Failed,
+    PartiallyRefunded,
+    Refunded,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Declined => "declined",
+            PaymentStatus::Authorized => "authorized",
+            PaymentStatus::Captured => "captured",
+            PaymentStatus::Failed => "failed",
+            PaymentStatus::PartiallyRefunded => "partially_refunded",
+            PaymentStatus::Refunded => "refunded",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RefundStatus {
+    /// Counted against the payment; the provider has not confirmed it yet,
+    /// possibly because asking it failed.
+    Pending,
+    Succeeded,
+    /// Refused by the provider; its amount no longer counts as refunded.
+    Declined,
+}
+
+impl RefundStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefundStatus::Pending => "pending",
+            RefundStatus::Succeeded => "succeeded",
+            RefundStatus::Declined => "declined",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PaymentRefund {
+    /// Sent with every request for this refund, so retrying one that failed
+    /// part-way never pays it out twice.
+    #[serde(rename = "idempotencyKey")]
+    pub idempotency_key: String,
+    pub status: RefundStatus,
+    /// The provider's id for the refund, once it has accepted it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(rename = "declineCode", skip_serializing_if = "Option::is_none")]
+    pub decline_code: Option<String>,
+    #[serde(with = "money::stored")]
+    pub amount: Money,
+    /// Who asked for it; `None` when the system gave the money back itself.
+    #[serde(rename = "actorId", skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub at: DateTime,
+}
+
+/// One attempt to pay for an order. Declined attempts are kept too, so an
+/// order can have several.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Payment {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "orderId")]
+    pub order_id: ObjectId,
+    /// The order id again, for as long as the attempt may take the order's
+    /// money. A unique index on it lets one attempt per order charge at a time.
+    #[serde(rename = "activeOrderId", skip_serializing_if = "Option::is_none")]
+    pub active_order_id: Option<ObjectId>,
+    #[serde(rename = "userId")]
+    pub user_id: ObjectId,
+    pub provider: String,
+    pub status: PaymentStatus,
+    #[serde(with = "money::stored")]
+    pub amount: Money,
+    /// Sum of the pending and succeeded `refunds`; bumped before the provider
+    /// is asked, so concurrent refunds can never add up to more than was captured.
+    #[serde(with = "money::stored")]
+    pub refunded: Money,
+    #[serde(rename = "providerReference", skip_serializing_if = "Option::is_none")]
+    pub provider_reference: Option<String>,
+    #[serde(rename = "declineCode", skip_serializing_if = "Option::is_none")]
+    pub decline_code: Option<String>,
+    #[serde(default)]
+    pub refunds: Vec<PaymentRefund>,
+    /// Ids of the provider webhooks already applied, to ignore resends.
+    #[serde(rename = "webhookEvents", default)]
+    pub webhook_events: Vec<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefundResponse {
+    pub status: RefundStatus,
+    pub reference: Option<String>,
+    #[serde(rename = "declineCode")]
+    pub decline_code: Option<String>,
+    pub amount: Money,
+    #[serde(rename = "actorId")]
+    pub actor_id: Option<String>,
+    pub reason: Option<String>,
+    pub at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaymentResponse {
+    pub id: String,
+    #[serde(rename = "orderId")]
+    pub order_id: String,
+    pub provider: String,
+    pub status: PaymentStatus,
+    pub amount: Money,
+    pub refunded: Money,
+    #[serde(rename = "providerReference")]
+    pub provider_reference: Option<String>,
+    #[serde(rename = "declineCode")]
+    pub decline_code: Option<String>,
+    pub refunds: Vec<RefundResponse>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+impl From<Payment> for PaymentResponse {
+    fn from(payment: Payment) -> Self {
+        PaymentResponse {
+            id: payment.id.map(|id| id.to_hex()).unwrap_or_default(),
+            order_id: payment.order_id.to_hex(),
+            provider: payment.provider,
+            status: payment.status,
+            amount: payment.amount,
+            refunded: payment.refunded,
+            provider_reference: payment.provider_reference,
+            decline_code: payment.decline_code,
+            refunds: payment
+                .refunds
+                .into_iter()
+                .map(|refund| RefundResponse {
+                    status: refund.status,
+                    reference: refund.reference,
+                    decline_code: refund.decline_code,
+                    amount: refund.amount,
+                    actor_id: refund.actor_id.map(|id| id.to_hex()),
+                    reason: refund.reason,
+                    at: refund.at.try_to_rfc3339_string().ok(),
+                })
+                .collect(),
+            created_at: payment.created_at.try_to_rfc3339_string().ok(),
+            updated_at: payment.updated_at.try_to_rfc3339_string().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PaymentPayload {
+    /// A provider token for the card; the fake provider takes any string.
+    #[serde(rename = "paymentMethod")]
+    #[validate(length(
+        min = 1,
+        max = 200,
+        code = "invalid_length",
+        message = "paymentMethod must be 1 to 200 characters"
+    ))]
+    pub payment_method: Option<String>,
+}
+
+impl PaymentPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(self, &[("paymentMethod", self.payment_method.is_none())])
+    }
+}
+
+fn validate_refund_amount(amount: &MoneyInput) -> Result<(), ValidationError> {
+    let money = amount.to_money().map_err(|error| {
+        ValidationError::new(error.code()).with_message(error.to_string().into())
+    })?;
+    if money.is_zero() || money.is_negative() {
+        return Err(ValidationError::new("out_of_range")
+            .with_message("amount must be greater than zero".into()));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct RefundPayload {
+    /// Defaults to everything not yet refunded.
+    #[validate(custom(function = "validate_refund_amount"))]
+    pub amount: Option<MoneyInput>,
+    #[validate(length(
+        min = 1,
+        max = 200,
+        code = "invalid_length",
+        message = "reason must be 1 to 200 characters"
+    ))]
+    pub reason: Option<String>,
+}
@@ -0,0 +1,286 @@
+use lazy_static::lazy_static;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidateUrl, ValidationError};
+
+use super::category_module::validate_object_id;
+use crate::common_struct::{
+    app_error::validate_required,
+    money::{self, Money, MoneyInput},
+    pagination::Page,
+    AppError,
+};
+
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 40;
+const MAX_IMAGES: usize = 10;
+
+lazy_static! {
+    static ref SKU_PATTERN: Regex = Regex::new(r"^[A-Z0-9][A-Z0-9_-]{1,63}$").unwrap();
+}
+
+fn validate_tags(tags: &[String]) -> Result<(), ValidationError> {
+    if tags.len() > MAX_TAGS {
+        return Err(ValidationError::new("too_many_tags")
+            .with_message(format!("at most {} tags are allowed", MAX_TAGS).into()));
+    }
+    if tags
+        .iter()
+        .any(|tag| tag.trim().is_empty() || tag.chars().count() > MAX_TAG_LENGTH)
+    {
+        return Err(ValidationError::new("invalid_tag")
+            .with_message(format!("tags must be 1 to {} characters", MAX_TAG_LENGTH).into()));
+    }
+    Ok(())
+}
+
+fn validate_price(price: &MoneyInput) -> Result<(), ValidationError> {
+    let money = price.to_money().map_err(|error| {
+        ValidationError::new(error.code()).with_message(error.to_string().into())
+    })?;
+    if money.is_negative() {
+        return Err(
+            ValidationError::new("out_of_range").with_message("price cannot be negative".into())
+        );
+    }
+    Ok(())
+}
+
+fn validate_images(images: &[String]) -> Result<(), ValidationError> {
+    if images.len() > MAX_IMAGES {
+        return Err(ValidationError::new("too_many_images")
+            .with_message(format!("at most {} images are allowed", MAX_IMAGES).into()));
+    }
+    if !images.iter().all(|image| image.validate_url()) {
+        return Err(
+            ValidationError::new("invalid_url").with_message("images must be absolute URLs".into())
+        );
+    }
+    Ok(())
+}
+
+/// A catalogue entry as stored in MongoDB.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Product {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sku: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(
+        default,
+        with = "money::stored_option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub price: Option<Money>,
+    #[serde(rename = "stockQuantity", skip_serializing_if = "Option::is_none")]
+    pub stock_quantity: Option<i64>,
+    #[serde(rename = "categoryId", skip_serializing_if = "Option::is_none")]
+    pub category_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub images: Option<Vec<String>>,
+    #[serde(rename = "createdAt", skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime>,
+    #[serde(rename = "updatedAt", skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<DateTime>,
+}
+
+/// A product as clients send it; the price amount is a decimal string that
+/// is only turned into `Money` once it has been validated.
+#[derive(Debug, Clone, Deserialize, Validate)]
+pub struct ProductPayload {
+    #[validate(length(
+        min = 1,
+        max = 200,
+        code = "invalid_length",
+        message = "name must be 1 to 200 characters"
+    ))]
+    pub name: Option<String>,
+    #[validate(regex(
+        path = *SKU_PATTERN,
+        code = "invalid_sku",
+        message = "sku must be 2 to 64 uppercase letters, digits, '-' or '_'"
+    ))]
+    pub sku: Option<String>,
+    #[validate(length(
+        max = 5000,
+        code = "invalid_length",
+        message = "description must be at most 5000 characters"
+    ))]
+    pub description: Option<String>,
+    #[validate(custom(function = "validate_price"))]
+    pub price: Option<MoneyInput>,
+    #[serde(rename = "stockQuantity")]
+    #[validate(range(
+        min = 0,
+        code = "out_of_range",
+        message = "stockQuantity cannot be negative"
+    ))]
+    pub stock_quantity: Option<i64>,
+    #[serde(rename = "categoryId")]
+    #[validate(custom(function = "validate_object_id"))]
+    pub category_id: Option<String>,
+    #[validate(custom(function = "validate_tags"))]
+    pub tags: Option<Vec<String>>,
+    #[validate(custom(function = "validate_images"))]
+    pub images: Option<Vec<String>>,
+}
+
+impl ProductPayload {
+    /// A new product needs a name, SKU and price; the rest is optional.
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[
+                ("name", self.name.is_none()),
+                ("sku", self.sku.is_none()),
+                ("price", self.price.is_none()),
+            ],
+        )
+    }
+
+    /// Converts a validated payload; identity and timestamps are left unset.
+    pub fn into_product(self) -> Result<Product, AppError> {
+        let price = self
+            .price
+            .map(|price| price.to_money())
+            .transpose()
+            .map_err(|error| AppError::Validation(vec![error.for_field("price")]))?;
+        Ok(Product {
+            id: None,
+            name: self.name,
+            sku: self.sku,
+            description: self.description,
+            price,
+            stock_quantity: self.stock_quantity,
+            category_id: self
+                .category_id
+                .as_deref()
+                .and_then(|id| ObjectId::parse_str(id).ok()),
+            tags: self.tags,
+            images: self.images,
+            created_at: None,
+            updated_at: None,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProductResponse {
+    pub id: String,
+    pub name: Option<String>,
+    pub sku: Option<String>,
+    pub description: Option<String>,
+    pub price: Option<Money>,
+    #[serde(rename = "stockQuantity")]
+    pub stock_quantity: i64,
+    #[serde(rename = "categoryId")]
+    pub category_id: Option<String>,
+    pub tags: Vec<String>,
+    pub images: Vec<String>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+impl From<Product> for ProductResponse {
+    fn from(product: Product) -> Self {
+        ProductResponse {
+            id: product.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: product.name,
+            sku: product.sku,
+            description: product.description,
+            price: product.price,
+            stock_quantity: product.stock_quantity.unwrap_or_default(),
+            category_id: product.category_id.map(|id| id.to_hex()),
+            tags: product.tags.unwrap_or_default(),
+            images: product.images.unwrap_or_default(),
+            created_at: product
+                .created_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+            updated_at: product
+                .updated_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProductListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<u64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+    /// A category id; matches products in it and in all of its descendants.
+    pub category: Option<String>,
+    /// Only products carrying this tag.
+    pub tag: Option<String>,
+    /// Only products priced in this ISO 4217 currency. Sorting by price
+    /// needs one, since amounts in different currencies do not compare.
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProductSearchQuery {
+    pub q: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<u64>,
+    /// `relevance` (the default), or a sortable product field.
+    pub sort: Option<String>,
+    pub category: Option<String>,
+    pub tag: Option<String>,
+    pub currency: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub product: ProductResponse,
+    /// MongoDB's text score; higher is more relevant.
+    pub score: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryFacet {
+    #[serde(rename = "categoryId")]
+    pub category_id: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TagFacet {
+    pub tag: String,
+    pub count: u64,
+}
+
+/// Products priced from `min` (inclusive) up to `max` (exclusive, open when
+/// absent) in one currency. Amounts are decimal strings like prices.
+#[derive(Debug, Serialize)]
+pub struct PriceFacet {
+    pub currency: String,
+    pub min: String,
+    pub max: Option<String>,
+    pub count: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SearchFacets {
+    pub categories: Vec<CategoryFacet>,
+    pub tags: Vec<TagFacet>,
+    pub prices: Vec<PriceFacet>,
+}
+
+/// A page of search hits with the facet counts for the whole match.
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    #[serde(flatten)]
+    pub page: Page<SearchHit>,
+    pub facets: SearchFacets,
+}
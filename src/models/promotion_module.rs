@@ -0,0 +1,642 @@
+use lazy_static::lazy_static;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use super::{cart_module::CartLine, category_module::validate_object_id};
+use crate::common_struct::{
+    app_error::validate_required,
+    money::{self, Currency, Money, MoneyError, MoneyInput},
+    AppError,
+};
+
+lazy_static! {
+    static ref CODE_PATTERN: Regex = Regex::new(r"^[A-Za-z0-9_-]{3,32}$").unwrap();
+}
+
+/// How a promotion takes money off. Category scoping is separate, on
+/// `Promotion::category_id`, and works with every rule.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PromotionRule {
+    PercentageOff {
+        percent: i64,
+    },
+    /// Off the lines in scope, never more than they add up to.
+    FixedAmount {
+        #[serde(with = "money::stored")]
+        amount: Money,
+    },
+    /// For every `buy` units of a product, `get` more are free.
+    BuyXGetY {
+        buy: i64,
+        get: i64,
+    },
+    FreeShipping,
+}
+
+impl PromotionRule {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PromotionRule::PercentageOff { .. } => "percentage_off",
+            PromotionRule::FixedAmount { .. } => "fixed_amount",
+            PromotionRule::BuyXGetY { .. } => "buy_x_get_y",
+            PromotionRule::FreeShipping => "free_shipping",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Promotion {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// The coupon code, upper-cased.
+    pub code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub rule: PromotionRule,
+    /// Limits the promotion to products in this category or below it.
+    #[serde(rename = "categoryId", skip_serializing_if = "Option::is_none")]
+    pub category_id: Option<ObjectId>,
+    /// Whether it may be combined with other promotions that are stackable too.
+    pub stackable: bool,
+    #[serde(rename = "startsAt", skip_serializing_if = "Option::is_none")]
+    pub starts_at: Option<DateTime>,
+    #[serde(rename = "endsAt", skip_serializing_if = "Option::is_none")]
+    pub ends_at: Option<DateTime>,
+    #[serde(rename = "usageLimit", skip_serializing_if = "Option::is_none")]
+    pub usage_limit: Option<i64>,
+    #[serde(rename = "perUserLimit", skip_serializing_if = "Option::is_none")]
+    pub per_user_limit: Option<i64>,
+    /// Orders placed with it so far; only ever changed with a conditional `$inc`.
+    #[serde(default)]
+    pub redemptions: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime,
+}
+
+impl Promotion {
+    /// Why the promotion cannot be used at `now`, if it cannot.
+    pub fn unavailable(&self, now: DateTime) -> Option<(&'static str, String)> {
+        if self.starts_at.is_some_and(|starts_at| now < starts_at) {
+            return Some((
+                "coupon_not_started",
+                format!("Coupon {} is not valid yet", self.code),
+            ));
+        }
+        if self.ends_at.is_some_and(|ends_at| now >= ends_at) {
+            return Some((
+                "coupon_expired",
+                format!("Coupon {} has expired", self.code),
+            ));
+        }
+        if self
+            .usage_limit
+            .is_some_and(|limit| self.redemptions >= limit)
+        {
+            return Some((
+                "coupon_exhausted",
+                format!("Coupon {} has been used up", self.code),
+            ));
+        }
+        None
+    }
+}
+
+/// How many times one user has redeemed a promotion with a per-user limit.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PromotionRedemption {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    #[serde(rename = "promotionId")]
+    pub promotion_id: ObjectId,
+    #[serde(rename = "userId")]
+    pub user_id: ObjectId,
+    pub count: i64,
+}
+
+/// Whether `promotion` may join `applied`: anything goes alone, but a
+/// combination needs every promotion in it to be stackable.
+pub fn can_stack(applied: &[&Promotion], promotion: &Promotion) -> bool {
+    applied.is_empty() || (promotion.stackable && applied.iter().all(|applied| applied.stackable))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiscountItem {
+    #[serde(rename = "productId")]
+    pub product_id: String,
+    pub amount: Money,
+}
+
+/// What one coupon takes off, line by line.
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedDiscount {
+    pub code: String,
+    #[serde(rename = "promotionId")]
+    pub promotion_id: String,
+    pub kind: &'static str,
+    pub amount: Money,
+    #[serde(rename = "freeShipping")]
+    pub free_shipping: bool,
+    pub items: Vec<DiscountItem>,
+}
+
+/// Works out what `promotion` takes off `lines`, the available cart lines in
+/// its scope. Amounts round down, so a discount never exceeds its rule. A
+/// fixed amount must be in `currency`; callers reject other coupons first.
+pub fn compute_discount(
+    promotion: &Promotion,
+    lines: &[&CartLine],
+    currency: Currency,
+) -> Result<AppliedDiscount, MoneyError> {
+    let mut totals: Vec<(&str, Money)> = lines
+        .iter()
+        .filter_map(|line| Some((line.product_id.as_str(), line.line_total?)))
+        .collect();
+
+    let mut items = Vec::new();
+    match &promotion.rule {
+        PromotionRule::PercentageOff { percent } => {
+            for (product_id, total) in &totals {
+                items.push((*product_id, total.multiply_ratio(*percent, 100)?));
+            }
+        }
+        PromotionRule::FixedAmount { amount } => {
+            let eligible = Money::sum(currency, totals.iter().map(|(_, total)| total))?;
+            // Nothing to share out, and sharing would divide by zero.
+            if eligible.is_zero() {
+                totals.clear();
+            }
+            let amount = match amount.checked_cmp(&eligible)? {
+                std::cmp::Ordering::Greater => eligible,
+                _ => *amount,
+            };
+            // Shared out in proportion to each line, rounding down. The last
+            // line takes what rounding leaves over, up to its own total, and
+            // passes the excess on to the line before it, so no line ever
+            // gets more off than it costs.
+            let mut shares = Vec::with_capacity(totals.len());
+            for (_, total) in &totals {
+                shares.push(amount.multiply_ratio(total.minor_units(), eligible.minor_units())?);
+            }
+            let mut left = amount.checked_sub(&Money::sum(currency, shares.iter())?)?;
+            for ((_, total), share) in totals.iter().zip(shares.iter_mut()).rev() {
+                let room = total.checked_sub(share)?;
+                let extra = match left.checked_cmp(&room)? {
+                    std::cmp::Ordering::Greater => room,
+                    _ => left,
+                };
+                *share = share.checked_add(&extra)?;
+                left = left.checked_sub(&extra)?;
+            }
+            items.extend(totals.iter().map(|(product_id, _)| *product_id).zip(shares));
+        }
+        PromotionRule::BuyXGetY { buy, get } => {
+            for line in lines {
+                if let Some(unit_price) = line.unit_price {
+                    let free = line.quantity / (buy + get) * get;
+                    items.push((line.product_id.as_str(), unit_price.checked_mul(free)?));
+                }
+            }
+        }
+        PromotionRule::FreeShipping => {}
+    }
+
+    let items: Vec<DiscountItem> = items
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(product_id, amount)| DiscountItem {
+            product_id: product_id.to_string(),
+            amount,
+        })
+        .collect();
+    Ok(AppliedDiscount {
+        code: promotion.code.clone(),
+        promotion_id: promotion.id.map(|id| id.to_hex()).unwrap_or_default(),
+        kind: promotion.rule.kind(),
+        amount: Money::sum(currency, items.iter().map(|item| &item.amount))?,
+        free_shipping: matches!(promotion.rule, PromotionRule::FreeShipping),
+        items,
+    })
+}
+
+/// The discount to take off `subtotal` for `discounts` together, capped so
+/// the total never goes below zero.
+pub fn total_discount(subtotal: Money, discounts: &[AppliedDiscount]) -> Result<Money, MoneyError> {
+    let sum = Money::sum(
+        subtotal.currency(),
+        discounts.iter().map(|discount| &discount.amount),
+    )?;
+    Ok(match sum.checked_cmp(&subtotal)? {
+        std::cmp::Ordering::Greater => subtotal,
+        _ => sum,
+    })
+}
+
+/// A coupon on the cart that cannot be used as things stand.
+#[derive(Debug, Clone, Serialize)]
+pub struct RejectedCoupon {
+    pub code: String,
+    pub reason: &'static str,
+    pub message: String,
+}
+
+/// The coupon side of a priced cart.
+#[derive(Debug, Clone, Serialize)]
+pub struct CouponSummary {
+    pub discounts: Vec<AppliedDiscount>,
+    #[serde(rename = "rejectedCoupons")]
+    pub rejected: Vec<RejectedCoupon>,
+    #[serde(rename = "discountTotal")]
+    pub discount_total: Option<Money>,
+    /// The subtotal less the discount.
+    pub total: Option<Money>,
+    #[serde(rename = "freeShipping")]
+    pub free_shipping: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PromotionRuleResponse {
+    PercentageOff { percent: i64 },
+    FixedAmount { amount: Money },
+    BuyXGetY { buy: i64, get: i64 },
+    FreeShipping,
+}
+
+impl From<PromotionRule> for PromotionRuleResponse {
+    fn from(rule: PromotionRule) -> Self {
+        match rule {
+            PromotionRule::PercentageOff { percent } => {
+                PromotionRuleResponse::PercentageOff { percent }
+            }
+            PromotionRule::FixedAmount { amount } => PromotionRuleResponse::FixedAmount { amount },
+            PromotionRule::BuyXGetY { buy, get } => PromotionRuleResponse::BuyXGetY { buy, get },
+            PromotionRule::FreeShipping => PromotionRuleResponse::FreeShipping,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PromotionResponse {
+    pub id: String,
+    pub code: String,
+    pub description: Option<String>,
+    pub rule: PromotionRuleResponse,
+    #[serde(rename = "categoryId")]
+    pub category_id: Option<String>,
+    pub stackable: bool,
+    #[serde(rename = "startsAt")]
+    pub starts_at: Option<String>,
+    #[serde(rename = "endsAt")]
+    pub ends_at: Option<String>,
+    #[serde(rename = "usageLimit")]
+    pub usage_limit: Option<i64>,
+    #[serde(rename = "perUserLimit")]
+    pub per_user_limit: Option<i64>,
+    pub redemptions: i64,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+impl From<Promotion> for PromotionResponse {
+    fn from(promotion: Promotion) -> Self {
+        PromotionResponse {
+            id: promotion.id.map(|id| id.to_hex()).unwrap_or_default(),
+            code: promotion.code,
+            description: promotion.description,
+            rule: promotion.rule.into(),
+            category_id: promotion.category_id.map(|id| id.to_hex()),
+            stackable: promotion.stackable,
+            starts_at: promotion
+                .starts_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+            ends_at: promotion
+                .ends_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+            usage_limit: promotion.usage_limit,
+            per_user_limit: promotion.per_user_limit,
+            redemptions: promotion.redemptions,
+            created_at: promotion.created_at.try_to_rfc3339_string().ok(),
+            updated_at: promotion.updated_at.try_to_rfc3339_string().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PromotionRuleInput {
+    PercentageOff { percent: i64 },
+    FixedAmount { amount: MoneyInput },
+    BuyXGetY { buy: i64, get: i64 },
+    FreeShipping,
+}
+
+fn validate_rule(rule: &PromotionRuleInput) -> Result<(), ValidationError> {
+    let out_of_range = |message: &str| {
+        ValidationError::new("out_of_range").with_message(message.to_string().into())
+    };
+    match rule {
+        PromotionRuleInput::PercentageOff { percent } if !(1..=100).contains(percent) => {
+            Err(out_of_range("percent must be between 1 and 100"))
+        }
+        PromotionRuleInput::FixedAmount { amount } => {
+            let money = amount.to_money().map_err(|error| {
+                ValidationError::new(error.code()).with_message(error.to_string().into())
+            })?;
+            if money.is_zero() || money.is_negative() {
+                return Err(out_of_range("amount must be greater than zero"));
+            }
+            Ok(())
+        }
+        PromotionRuleInput::BuyXGetY { buy, get }
+            if !(1..=1000).contains(buy) || !(1..=1000).contains(get) =>
+        {
+            Err(out_of_range("buy and get must be between 1 and 1000"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_timestamp(value: &str) -> Result<(), ValidationError> {
+    DateTime::parse_rfc3339_str(value).map(|_| ()).map_err(|_| {
+        ValidationError::new("invalid_timestamp")
+            .with_message("must be an RFC 3339 timestamp".into())
+    })
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct PromotionPayload {
+    #[validate(regex(
+        path = *CODE_PATTERN,
+        code = "invalid_code",
+        message = "code must be 3 to 32 letters, digits, '-' or '_'"
+    ))]
+    pub code: Option<String>,
+    #[validate(length(
+        max = 500,
+        code = "invalid_length",
+        message = "description must be at most 500 characters"
+    ))]
+    pub description: Option<String>,
+    #[validate(custom(function = "validate_rule"))]
+    pub rule: Option<PromotionRuleInput>,
+    #[serde(rename = "categoryId")]
+    #[validate(custom(function = "validate_object_id"))]
+    pub category_id: Option<String>,
+    pub stackable: Option<bool>,
+    #[serde(rename = "startsAt")]
+    #[validate(custom(function = "validate_timestamp"))]
+    pub starts_at: Option<String>,
+    #[serde(rename = "endsAt")]
+    #[validate(custom(function = "validate_timestamp"))]
+    pub ends_at: Option<String>,
+    #[serde(rename = "usageLimit")]
+    #[validate(range(
+        min = 1,
+        code = "out_of_range",
+        message = "usageLimit must be at least 1"
+    ))]
+    pub usage_limit: Option<i64>,
+    #[serde(rename = "perUserLimit")]
+    #[validate(range(
+        min = 1,
+        code = "out_of_range",
+        message = "perUserLimit must be at least 1"
+    ))]
+    pub per_user_limit: Option<i64>,
+}
+
+impl PromotionPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[("code", self.code.is_none()), ("rule", self.rule.is_none())],
+        )
+    }
+
+    /// Builds the stored promotion. Call after `validate_new`.
+    pub fn into_promotion(self) -> Result<Promotion, AppError> {
+        let timestamp =
+            |value: Option<String>| value.and_then(|value| DateTime::parse_rfc3339_str(value).ok());
+        let starts_at = timestamp(self.starts_at);
+        let ends_at = timestamp(self.ends_at);
+        if let (Some(starts_at), Some(ends_at)) = (starts_at, ends_at) {
+            if ends_at <= starts_at {
+                return Err(AppError::invalid(
+                    "endsAt",
+                    "invalid_window",
+                    "endsAt must be after startsAt",
+                ));
+            }
+        }
+        let rule = match self.rule {
+            Some(PromotionRuleInput::PercentageOff { percent }) => {
+                PromotionRule::PercentageOff { percent }
+            }
+            Some(PromotionRuleInput::FixedAmount { amount }) => PromotionRule::FixedAmount {
+                amount: amount.to_money()?,
+            },
+            Some(PromotionRuleInput::BuyXGetY { buy, get }) => PromotionRule::BuyXGetY { buy, get },
+            Some(PromotionRuleInput::FreeShipping) => PromotionRule::FreeShipping,
+            None => return Err(AppError::invalid("rule", "required", "rule is required")),
+        };
+
+        let now = DateTime::now();
+        Ok(Promotion {
+            id: None,
+            code: self.code.unwrap_or_default().to_uppercase(),
+            description: self.description,
+            rule,
+            category_id: self.category_id.and_then(|id| ObjectId::parse_str(id).ok()),
+            stackable: self.stackable.unwrap_or(false),
+            starts_at,
+            ends_at,
+            usage_limit: self.usage_limit,
+            per_user_limit: self.per_user_limit,
+            redemptions: 0,
+            created_at: now,
+            updated_at: now,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PromotionListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<u64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct CouponPayload {
+    #[validate(regex(
+        path = *CODE_PATTERN,
+        code = "invalid_code",
+        message = "code must be 3 to 32 letters, digits, '-' or '_'"
+    ))]
+    pub code: Option<String>,
+}
+
+impl CouponPayload {
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(self, &[("code", self.code.is_none())])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::cart_module::LineStatus;
+
+    fn usd(minor_units: i64) -> Money {
+        Money::from_minor(minor_units, Currency::from_code("USD").unwrap())
+    }
+
+    fn line(product_id: &str, unit: i64, quantity: i64) -> CartLine {
+        CartLine {
+            product_id: product_id.to_string(),
+            name: None,
+            sku: None,
+            quantity,
+            unit_price: Some(usd(unit)),
+            line_total: Some(usd(unit * quantity)),
+            available_quantity: quantity,
+            status: LineStatus::Available,
+        }
+    }
+
+    fn promotion(rule: PromotionRule, stackable: bool) -> Promotion {
+        Promotion {
+            id: Some(ObjectId::new()),
+            code: "SAVE".to_string(),
+            description: None,
+            rule,
+            category_id: None,
+            stackable,
+            starts_at: None,
+            ends_at: None,
+            usage_limit: None,
+            per_user_limit: None,
+            redemptions: 0,
+            created_at: DateTime::now(),
+            updated_at: DateTime::now(),
+        }
+    }
+
+    #[test]
+    fn percentages_round_down_per_line() {
+        let lines = [line("a", 333, 1), line("b", 1000, 2)];
+        let lines: Vec<&CartLine> = lines.iter().collect();
+
+        let discount = compute_discount(
+            &promotion(PromotionRule::PercentageOff { percent: 10 }, false),
+            &lines,
+            usd(0).currency(),
+        )
+        .unwrap();
+
+        let amounts: Vec<i64> = discount
+            .items
+            .iter()
+            .map(|item| item.amount.minor_units())
+            .collect();
+        assert_eq!(amounts, [33, 200]);
+        assert_eq!(discount.amount.minor_units(), 233);
+    }
+
+    #[test]
+    fn fixed_amounts_are_shared_out_and_capped() {
+        let lines = [line("a", 100, 1), line("b", 200, 1)];
+        let lines: Vec<&CartLine> = lines.iter().collect();
+
+        let shared = compute_discount(
+            &promotion(PromotionRule::FixedAmount { amount: usd(100) }, false),
+            &lines,
+            usd(0).currency(),
+        )
+        .unwrap();
+        let capped = compute_discount(
+            &promotion(PromotionRule::FixedAmount { amount: usd(1000) }, false),
+            &lines,
+            usd(0).currency(),
+        )
+        .unwrap();
+
+        let amounts: Vec<i64> = shared
+            .items
+            .iter()
+            .map(|item| item.amount.minor_units())
+            .collect();
+        assert_eq!(amounts, [33, 67]);
+        assert_eq!(capped.amount.minor_units(), 300);
+    }
+
+    #[test]
+    fn fixed_amount_shares_never_exceed_their_line() {
+        let lines = [line("a", 1, 1), line("b", 1, 1), line("c", 1, 1)];
+        let lines: Vec<&CartLine> = lines.iter().collect();
+
+        let discount = compute_discount(
+            &promotion(PromotionRule::FixedAmount { amount: usd(2) }, false),
+            &lines,
+            usd(0).currency(),
+        )
+        .unwrap();
+
+        let amounts: Vec<(&str, i64)> = discount
+            .items
+            .iter()
+            .map(|item| (item.product_id.as_str(), item.amount.minor_units()))
+            .collect();
+        assert_eq!(amounts, [("b", 1), ("c", 1)]);
+        assert_eq!(discount.amount.minor_units(), 2);
+    }
+
+    #[test]
+    fn buy_x_get_y_frees_whole_groups_only() {
+        let lines = [line("a", 500, 7)];
+        let lines: Vec<&CartLine> = lines.iter().collect();
+
+        let discount = compute_discount(
+            &promotion(PromotionRule::BuyXGetY { buy: 2, get: 1 }, false),
+            &lines,
+            usd(0).currency(),
+        )
+        .unwrap();
+
+        assert_eq!(discount.amount.minor_units(), 1000);
+    }
+
+    #[test]
+    fn only_stackable_promotions_combine() {
+        let stackable = promotion(PromotionRule::FreeShipping, true);
+        let exclusive = promotion(PromotionRule::PercentageOff { percent: 5 }, false);
+
+        assert!(can_stack(&[], &exclusive));
+        assert!(can_stack(&[&stackable], &stackable));
+        assert!(!can_stack(&[&stackable], &exclusive));
+        assert!(!can_stack(&[&exclusive], &stackable));
+    }
+
+    #[test]
+    fn fixed_amounts_take_nothing_off_free_lines() {
+        let lines = [line("a", 0, 2)];
+        let lines: Vec<&CartLine> = lines.iter().collect();
+
+        let discount = compute_discount(
+            &promotion(PromotionRule::FixedAmount { amount: usd(100) }, false),
+            &lines,
+            usd(0).currency(),
+        )
+        .unwrap();
+
+        assert!(discount.amount.is_zero());
+        assert!(discount.items.is_empty());
+    }
+}
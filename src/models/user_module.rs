@@ -1,20 +1,186 @@
-use mongodb::bson::DateTime;
+use lazy_static::lazy_static;
+use mongodb::bson::{oid::ObjectId, DateTime};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::{
+    auth::permissions::Role,
+    common_struct::{app_error::validate_required, AppError},
+};
+
+const PASSWORD_MIN_LENGTH: usize = 8;
+const PASSWORD_MAX_LENGTH: usize = 128;
+
+lazy_static! {
+    // Letters in any script plus the spaces, apostrophes and hyphens real names use.
+    static ref NAME_PATTERN: Regex = Regex::new(r"^\p{L}[\p{L}\p{M}' -]*$").unwrap();
+}
+
+fn validate_password_strength(password: &str) -> Result<(), ValidationError> {
+    let length = password.chars().count();
+    if !(PASSWORD_MIN_LENGTH..=PASSWORD_MAX_LENGTH).contains(&length) {
+        return Err(ValidationError::new("weak_password").with_message(
+            format!(
+                "password must be {} to {} characters",
+                PASSWORD_MIN_LENGTH, PASSWORD_MAX_LENGTH
+            )
+            .into(),
+        ));
+    }
+    if !password.chars().any(char::is_lowercase)
+        || !password.chars().any(char::is_uppercase)
+        || !password.chars().any(|ch| ch.is_ascii_digit())
+    {
+        return Err(ValidationError::new("weak_password").with_message(
+            "password must contain an uppercase letter, a lowercase letter and a digit".into(),
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Validate)]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
-    pub id: Option<String>,
+    pub id: Option<ObjectId>,
     #[serde(rename = "firstName")]
+    #[validate(
+        length(
+            min = 1,
+            max = 50,
+            code = "invalid_length",
+            message = "firstName must be 1 to 50 characters"
+        ),
+        regex(
+            path = *NAME_PATTERN,
+            code = "invalid_characters",
+            message = "firstName may only contain letters, spaces, apostrophes and hyphens"
+        )
+    )]
     pub first_name: Option<String>,
     #[serde(rename = "lastName")]
+    #[validate(
+        length(
+            min = 1,
+            max = 50,
+            code = "invalid_length",
+            message = "lastName must be 1 to 50 characters"
+        ),
+        regex(
+            path = *NAME_PATTERN,
+            code = "invalid_characters",
+            message = "lastName may only contain letters, spaces, apostrophes and hyphens"
+        )
+    )]
     pub last_name: Option<String>,
-    // #[serde(rename = "_id")]
+    #[validate(
+        email(
+            code = "invalid_email",
+            message = "email must be a valid email address"
+        ),
+        length(
+            max = 254,
+            code = "invalid_length",
+            message = "email must be at most 254 characters"
+        )
+    )]
     pub email: Option<String>,
-    // #[serde(rename = "_id")]
+    #[validate(custom(function = "validate_password_strength"))]
     pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roles: Option<Vec<Role>>,
     #[serde(rename = "createdAt", skip_serializing_if = "Option::is_none")]
     pub created_at: Option<DateTime>,
     #[serde(rename = "updatedAt", skip_serializing_if = "Option::is_none")]
     pub updated_at: Option<DateTime>,
 }
+
+impl User {
+    /// Creating a user needs every profile field; the format rules from
+    /// `Validate` apply to whichever fields are present on both create and update.
+    pub fn validate_new(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[
+                ("firstName", self.first_name.is_none()),
+                ("lastName", self.last_name.is_none()),
+                ("email", self.email.is_none()),
+                ("password", self.password.is_none()),
+            ],
+        )
+    }
+
+    /// A full replacement (PUT) must restate the profile; the password and
+    /// roles stay optional because they are not part of the public view.
+    pub fn validate_replacement(&self) -> Result<(), AppError> {
+        validate_required(
+            self,
+            &[
+                ("firstName", self.first_name.is_none()),
+                ("lastName", self.last_name.is_none()),
+                ("email", self.email.is_none()),
+            ],
+        )
+    }
+}
+
+/// Public view of a user. Deliberately has no password field so the hash can
+/// never leak through a read endpoint.
+#[derive(Debug, Serialize)]
+pub struct UserResponse {
+    pub id: String,
+    #[serde(rename = "firstName")]
+    pub first_name: Option<String>,
+    #[serde(rename = "lastName")]
+    pub last_name: Option<String>,
+    pub email: Option<String>,
+    pub roles: Vec<Role>,
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: Option<String>,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        UserResponse {
+            id: user.id.map(|id| id.to_hex()).unwrap_or_default(),
+            first_name: user.first_name,
+            last_name: user.last_name,
+            email: user.email,
+            // Documents created before roles existed are regular users.
+            roles: user.roles.unwrap_or_else(|| vec![Role::User]),
+            created_at: user
+                .created_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+            updated_at: user
+                .updated_at
+                .and_then(|at| at.try_to_rfc3339_string().ok()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserListQuery {
+    pub limit: Option<i64>,
+    pub offset: Option<u64>,
+    pub cursor: Option<String>,
+    pub sort: Option<String>,
+    #[serde(rename = "firstName")]
+    pub first_name: Option<String>,
+    #[serde(rename = "firstNamePrefix")]
+    pub first_name_prefix: Option<String>,
+    #[serde(rename = "lastName")]
+    pub last_name: Option<String>,
+    #[serde(rename = "lastNamePrefix")]
+    pub last_name_prefix: Option<String>,
+    pub email: Option<String>,
+    #[serde(rename = "emailPrefix")]
+    pub email_prefix: Option<String>,
+    /// Inclusive lower bound on `createdAt`, as an RFC 3339 timestamp.
+    #[serde(rename = "createdFrom")]
+    pub created_from: Option<String>,
+    /// Inclusive upper bound on `createdAt`, as an RFC 3339 timestamp.
+    #[serde(rename = "createdTo")]
+    pub created_to: Option<String>,
+}
@@ -0,0 +1,213 @@
+use axum::async_trait;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    auth::password::constant_time_eq,
+    common_struct::{money::Money, AppError},
+};
+
+use super::payment_provider::{PaymentProvider, ProviderOutcome, WebhookEvent};
+
+/// Payment method that the fake always declines as `card_declined`.
+pub const DECLINED_METHOD: &str = "fake_declined";
+/// Payment method that the fake always declines as `insufficient_funds`.
+pub const INSUFFICIENT_FUNDS_METHOD: &str = "fake_insufficient_funds";
+
+/// Public, so only accepted when `allow_fake_payments` is set.
+pub const DEV_WEBHOOK_SECRET: &str = "fake-webhook-secret";
+const CHARGE_PREFIX: &str = "fake_ch_";
+const REFUND_PREFIX: &str = "fake_re_";
+
+fn digest(input: &str) -> String {
+    Sha256::digest(input.as_bytes())
+        .iter()
+        .take(12)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn declined(code: &str, message: &str) -> ProviderOutcome {
+    ProviderOutcome::Declined {
+        code: code.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// An in-process provider for development and tests. It moves no money and
+/// keeps no state: every answer follows from its inputs, so the same call
+/// always gets the same reference and the same decline.
+pub struct FakePaymentProvider {
+    webhook_secret: String,
+}
+
+impl FakePaymentProvider {
+    pub fn new(webhook_secret: impl Into<String>) -> Self {
+        FakePaymentProvider {
+            webhook_secret: webhook_secret.into(),
+        }
+    }
+
+    /// The `X-Payment-Signature` value the fake expects for `body`.
+    pub fn sign(&self, body: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.webhook_secret.as_bytes());
+        hasher.update(b".");
+        hasher.update(body);
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for FakePaymentProvider {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn authorize(
+        &self,
+        amount: Money,
+        payment_method: &str,
+        idempotency_key: &str,
+    ) -> Result<ProviderOutcome, AppError> {
+        Ok(match payment_method {
+            DECLINED_METHOD => declined("card_declined", "The card was declined"),
+            INSUFFICIENT_FUNDS_METHOD => {
+                declined("insufficient_funds", "The card has insufficient funds")
+            }
+            _ if amount.is_zero() || amount.is_negative() => {
+                declined("invalid_amount", "Only positive amounts can be charged")
+            }
+            _ => ProviderOutcome::Approved {
+                reference: format!("{}{}", CHARGE_PREFIX, digest(idempotency_key)),
+            },
+        })
+    }
+
+    async fn capture(&self, reference: &str, _amount: Money) -> Result<ProviderOutcome, AppError> {
+        if !reference.starts_with(CHARGE_PREFIX) {
+            return Ok(declined("unknown_charge", "No such charge"));
+        }
+        Ok(ProviderOutcome::Approved {
+            reference: reference.to_string(),
+        })
+    }
+
+    async fn refund(
+        &self,
+        reference: &str,
+        amount: Money,
+        idempotency_key: &str,
+    ) -> Result<ProviderOutcome, AppError> {
+        if !reference.starts_with(CHARGE_PREFIX) {
+            return Ok(declined("unknown_charge", "No such charge"));
+        }
+        if amount.is_zero() || amount.is_negative() {
+            return Ok(declined(
+                "invalid_amount",
+                "Only positive amounts can be refunded",
+            ));
+        }
+        Ok(ProviderOutcome::Approved {
+            reference: format!(
+                "{}{}",
+                REFUND_PREFIX,
+                digest(&format!("{}:{}", reference, idempotency_key))
+            ),
+        })
+    }
+
+    fn verify_webhook(&self, signature: &str, body: &[u8]) -> Result<WebhookEvent, AppError> {
+        if !constant_time_eq(signature.as_bytes(), self.sign(body).as_bytes()) {
+            return Err(AppError::Unauthorized(
+                "Invalid webhook signature".to_string(),
+            ));
+        }
+        serde_json::from_slice(body).map_err(|error| {
+            AppError::invalid(
+                "body",
+                "invalid_webhook",
+                format!("Unreadable webhook: {}", error),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common_struct::money::Currency, payments::WebhookKind};
+
+    fn usd(minor_units: i64) -> Money {
+        Money::from_minor(minor_units, Currency::from_code("USD").unwrap())
+    }
+
+    #[tokio::test]
+    async fn answers_are_deterministic() {
+        let fake = FakePaymentProvider::new("secret");
+
+        let first = fake
+            .authorize(usd(500), "fake_visa", "attempt-1")
+            .await
+            .unwrap();
+        let again = fake
+            .authorize(usd(500), "fake_visa", "attempt-1")
+            .await
+            .unwrap();
+        let other = fake
+            .authorize(usd(500), "fake_visa", "attempt-2")
+            .await
+            .unwrap();
+
+        assert_eq!(first, again);
+        assert_ne!(first, other);
+        assert_eq!(
+            fake.authorize(usd(500), DECLINED_METHOD, "attempt-3")
+                .await
+                .unwrap(),
+            declined("card_declined", "The card was declined")
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_refunds_get_their_own_references() {
+        let fake = FakePaymentProvider::new("secret");
+        let ProviderOutcome::Approved { reference } = fake
+            .authorize(usd(500), "fake_visa", "attempt")
+            .await
+            .unwrap()
+        else {
+            panic!("expected approval");
+        };
+
+        let first = fake.refund(&reference, usd(200), "refund-1").await.unwrap();
+        let second = fake.refund(&reference, usd(300), "refund-2").await.unwrap();
+
+        assert!(matches!(first, ProviderOutcome::Approved { .. }));
+        assert_ne!(first, second);
+        assert!(matches!(
+            fake.refund("ch_elsewhere", usd(100), "refund-3")
+                .await
+                .unwrap(),
+            ProviderOutcome::Declined { .. }
+        ));
+    }
+
+    #[test]
+    fn webhooks_need_a_matching_signature() {
+        let fake = FakePaymentProvider::new("secret");
+        let body = br#"{"id":"evt_1","type":"payment.captured","reference":"fake_ch_1"}"#;
+
+        let event = fake.verify_webhook(&fake.sign(body), body).unwrap();
+
+        assert_eq!(event.kind, WebhookKind::Captured);
+        assert_eq!(event.reference, "fake_ch_1");
+        assert!(matches!(
+            FakePaymentProvider::new("other").verify_webhook(&fake.sign(body), body),
+            Err(AppError::Unauthorized(_))
+        ));
+    }
+}
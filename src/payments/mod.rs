@@ -0,0 +1,5 @@
+pub mod fake_payment_provider;
+pub mod payment_provider;
+
+pub use fake_payment_provider::FakePaymentProvider;
+pub use payment_provider::{PaymentProvider, ProviderOutcome, WebhookKind};
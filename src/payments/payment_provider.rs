@@ -0,0 +1,70 @@
+use axum::async_trait;
+use serde::Deserialize;
+
+use crate::common_struct::{money::Money, AppError};
+
+/// What a provider said about an authorize, capture or refund. A decline is
+/// an answer, not an error; errors are for providers that could not be reached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProviderOutcome {
+    Approved {
+        /// The provider's id for the charge or refund.
+        reference: String,
+    },
+    Declined {
+        code: String,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum WebhookKind {
+    #[serde(rename = "payment.captured")]
+    Captured,
+    #[serde(rename = "payment.failed")]
+    Failed,
+    #[serde(rename = "payment.refunded")]
+    Refunded,
+}
+
+/// A webhook whose signature checked out.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct WebhookEvent {
+    /// Unique per event; providers resend the same id when they retry.
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: WebhookKind,
+    /// The charge the event is about, as returned by `authorize`.
+    pub reference: String,
+}
+
+/// A card processor. Implementations must be safe to retry: calls repeated
+/// with the same idempotency key must not move money twice.
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Stored with every payment attempt, e.g. `fake`.
+    fn name(&self) -> &'static str;
+
+    /// Holds `amount` on `payment_method` without taking it yet.
+    async fn authorize(
+        &self,
+        amount: Money,
+        payment_method: &str,
+        idempotency_key: &str,
+    ) -> Result<ProviderOutcome, AppError>;
+
+    /// Takes `amount`, at most what was authorized, from an authorization.
+    async fn capture(&self, reference: &str, amount: Money) -> Result<ProviderOutcome, AppError>;
+
+    /// Pays back part or all of a captured charge.
+    async fn refund(
+        &self,
+        reference: &str,
+        amount: Money,
+        idempotency_key: &str,
+    ) -> Result<ProviderOutcome, AppError>;
+
+    /// Checks `signature` against the raw request body and parses the event.
+    /// Fails with `Unauthorized` if the signature does not match.
+    fn verify_webhook(&self, signature: &str, body: &[u8]) -> Result<WebhookEvent, AppError>;
+}
@@ -0,0 +1,75 @@
+use std::sync::Mutex;
+
+use axum::async_trait;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{common_struct::AppError, models::auth_module::RefreshToken};
+
+use super::refresh_token_repository::RefreshTokenRepository;
+
+/// Keeps refresh tokens in process memory, next to `InMemoryUserRepository`.
+#[derive(Default)]
+pub struct InMemoryRefreshTokenRepository {
+    tokens: Mutex<Vec<RefreshToken>>,
+}
+
+impl InMemoryRefreshTokenRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Vec<RefreshToken>>, AppError> {
+        self.tokens.lock().map_err(|_| {
+            AppError::Internal("In-memory refresh token store is poisoned".to_string())
+        })
+    }
+
+    fn revoke_where(&self, matches: impl Fn(&RefreshToken) -> bool) -> Result<(), AppError> {
+        let now = DateTime::now();
+        for token in self.lock()?.iter_mut() {
+            if token.revoked_at.is_none() && matches(token) {
+                token.revoked_at = Some(now);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RefreshTokenRepository for InMemoryRefreshTokenRepository {
+    async fn insert(&self, mut token: RefreshToken) -> Result<(), AppError> {
+        token.id = Some(ObjectId::new());
+        self.lock()?.push(token);
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, token_hash: &str) -> Result<Option<RefreshToken>, AppError> {
+        Ok(self
+            .lock()?
+            .iter()
+            .find(|token| token.token_hash == token_hash)
+            .cloned())
+    }
+
+    async fn claim(&self, id: ObjectId) -> Result<bool, AppError> {
+        let mut tokens = self.lock()?;
+        match tokens
+            .iter_mut()
+            .find(|token| token.id == Some(id) && token.revoked_at.is_none())
+        {
+            Some(token) => {
+                token.revoked_at = Some(DateTime::now());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn revoke_family(&self, family_id: ObjectId) -> Result<(), AppError> {
+        self.revoke_where(|token| token.family_id == family_id)
+    }
+
+    async fn revoke_user(&self, user_id: ObjectId) -> Result<(), AppError> {
+        self.revoke_where(|token| token.user_id == user_id)
+    }
+}
@@ -0,0 +1,136 @@
+use std::{cmp::Ordering, sync::Mutex};
+
+use axum::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::{
+    common_struct::{
+        pagination::{self, Page, PageRequest},
+        AppError,
+    },
+    models::user_module::User,
+};
+
+use super::user_repository::{email_taken, UserChanges, UserFilter, UserRepository};
+
+/// Keeps users in process memory. Behaves like the Mongo repository, including
+/// the case-insensitive email uniqueness check, so the API can run without a
+/// database in tests and demos.
+#[derive(Default)]
+pub struct InMemoryUserRepository {
+    users: Mutex<Vec<User>>,
+}
+
+impl InMemoryUserRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Vec<User>>, AppError> {
+        self.users
+            .lock()
+            .map_err(|_| AppError::Internal("In-memory user store is poisoned".to_string()))
+    }
+}
+
+fn same_email(a: Option<&str>, b: &str) -> bool {
+    a.is_some_and(|a| a.to_lowercase() == b.to_lowercase())
+}
+
+fn without_password(user: &User) -> User {
+    User {
+        password: None,
+        ..user.clone()
+    }
+}
+
+fn compare_field(field: &str, a: &User, b: &User) -> Ordering {
+    match field {
+        "firstName" => a.first_name.cmp(&b.first_name),
+        "lastName" => a.last_name.cmp(&b.last_name),
+        "email" => a.email.cmp(&b.email),
+        "createdAt" => a.created_at.cmp(&b.created_at),
+        "updatedAt" => a.updated_at.cmp(&b.updated_at),
+        _ => Ordering::Equal,
+    }
+}
+
+#[async_trait]
+impl UserRepository for InMemoryUserRepository {
+    async fn insert(&self, mut user: User) -> Result<User, AppError> {
+        let mut users = self.lock()?;
+        if let Some(email) = &user.email {
+            if users
+                .iter()
+                .any(|other| same_email(other.email.as_deref(), email))
+            {
+                return Err(email_taken());
+            }
+        }
+        user.id = Some(ObjectId::new());
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<User>, AppError> {
+        let users = self.lock()?;
+        Ok(users
+            .iter()
+            .find(|user| user.id == Some(id))
+            .map(without_password))
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+        let users = self.lock()?;
+        Ok(users
+            .iter()
+            .find(|user| same_email(user.email.as_deref(), email))
+            .cloned())
+    }
+
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        request: &PageRequest,
+    ) -> Result<Page<User>, AppError> {
+        let users = self.lock()?;
+        let matching = users
+            .iter()
+            .filter(|user| filter.matches(user))
+            .map(without_password)
+            .collect();
+        Ok(pagination::page_in_memory(
+            matching,
+            request,
+            |user| user.id,
+            |a, b| compare_field(&request.sort_field, a, b),
+        ))
+    }
+
+    async fn update(&self, id: ObjectId, changes: UserChanges) -> Result<Option<User>, AppError> {
+        let mut users = self.lock()?;
+        if let Some(email) = &changes.email {
+            let taken = users
+                .iter()
+                .any(|other| other.id != Some(id) && same_email(other.email.as_deref(), email));
+            if taken {
+                return Err(email_taken());
+            }
+        }
+        Ok(users
+            .iter_mut()
+            .find(|user| user.id == Some(id))
+            .map(|user| {
+                changes.apply(user);
+                without_password(user)
+            }))
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<Option<User>, AppError> {
+        let mut users = self.lock()?;
+        Ok(users
+            .iter()
+            .position(|user| user.id == Some(id))
+            .map(|index| without_password(&users.remove(index))))
+    }
+}
@@ -0,0 +1,13 @@
+pub mod memory_refresh_token_repository;
+pub mod memory_user_repository;
+pub mod mongo_refresh_token_repository;
+pub mod mongo_user_repository;
+pub mod refresh_token_repository;
+pub mod user_repository;
+
+pub use memory_refresh_token_repository::InMemoryRefreshTokenRepository;
+pub use memory_user_repository::InMemoryUserRepository;
+pub use mongo_refresh_token_repository::MongoRefreshTokenRepository;
+pub use mongo_user_repository::MongoUserRepository;
+pub use refresh_token_repository::RefreshTokenRepository;
+pub use user_repository::{TextMatch, UserChanges, UserFilter, UserRepository};
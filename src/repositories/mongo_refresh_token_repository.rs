@@ -0,0 +1,60 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{doc, oid::ObjectId, Bson, DateTime, Document},
+    Collection, Database,
+};
+
+use crate::{common_struct::AppError, models::auth_module::RefreshToken};
+
+use super::refresh_token_repository::RefreshTokenRepository;
+
+pub struct MongoRefreshTokenRepository {
+    coll: Collection<RefreshToken>,
+}
+
+impl MongoRefreshTokenRepository {
+    pub fn new(db: &Database) -> Self {
+        MongoRefreshTokenRepository {
+            coll: db.collection("refresh_tokens"),
+        }
+    }
+
+    async fn revoke_live(&self, mut filter: Document) -> Result<(), AppError> {
+        filter.insert("revokedAt", Bson::Null);
+        self.coll
+            .update_many(filter, doc! {"$set": {"revokedAt": DateTime::now()}})
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl RefreshTokenRepository for MongoRefreshTokenRepository {
+    async fn insert(&self, token: RefreshToken) -> Result<(), AppError> {
+        self.coll.insert_one(token).await?;
+        Ok(())
+    }
+
+    async fn find_by_hash(&self, token_hash: &str) -> Result<Option<RefreshToken>, AppError> {
+        Ok(self.coll.find_one(doc! {"tokenHash": token_hash}).await?)
+    }
+
+    async fn claim(&self, id: ObjectId) -> Result<bool, AppError> {
+        let claimed = self
+            .coll
+            .update_one(
+                doc! {"_id": id, "revokedAt": null},
+                doc! {"$set": {"revokedAt": DateTime::now()}},
+            )
+            .await?;
+        Ok(claimed.modified_count > 0)
+    }
+
+    async fn revoke_family(&self, family_id: ObjectId) -> Result<(), AppError> {
+        self.revoke_live(doc! {"familyId": family_id}).await
+    }
+
+    async fn revoke_user(&self, user_id: ObjectId) -> Result<(), AppError> {
+        self.revoke_live(doc! {"userId": user_id}).await
+    }
+}
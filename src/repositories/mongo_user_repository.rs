@@ -0,0 +1,168 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{self, doc, oid::ObjectId, Document},
+    options::ReturnDocument,
+    Collection, Database,
+};
+
+use crate::{
+    common_struct::{
+        app_error::is_duplicate_key,
+        pagination::{self, Page, PageRequest},
+        AppError,
+    },
+    db,
+    models::user_module::User,
+};
+
+use super::user_repository::{email_taken, TextMatch, UserChanges, UserFilter, UserRepository};
+
+pub struct MongoUserRepository {
+    coll: Collection<User>,
+}
+
+impl MongoUserRepository {
+    pub fn new(db: &Database) -> Self {
+        MongoUserRepository {
+            coll: db.collection("users"),
+        }
+    }
+}
+
+/// The unique email index is the only one on `users` besides `_id`.
+fn email_conflict(error: mongodb::error::Error) -> AppError {
+    if is_duplicate_key(&error) {
+        email_taken()
+    } else {
+        AppError::from(error)
+    }
+}
+
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if "\\^$.|?*+()[]{}".contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+fn filter_document(filter: &UserFilter) -> Document {
+    let mut query = doc! {};
+
+    let text_filters = [
+        ("firstName", &filter.first_name),
+        ("lastName", &filter.last_name),
+        ("email", &filter.email),
+    ];
+    for (field, text) in text_filters {
+        match text {
+            Some(TextMatch::Exact(exact)) => {
+                query.insert(field, exact);
+            }
+            Some(TextMatch::Prefix(prefix)) => {
+                query.insert(field, doc! {"$regex": format!("^{}", escape_regex(prefix))});
+            }
+            None => {}
+        }
+    }
+
+    let mut created_at = doc! {};
+    if let Some(from) = filter.created_from {
+        created_at.insert("$gte", from);
+    }
+    if let Some(to) = filter.created_to {
+        created_at.insert("$lte", to);
+    }
+    if !created_at.is_empty() {
+        query.insert("createdAt", created_at);
+    }
+
+    query
+}
+
+fn changes_document(changes: UserChanges) -> Result<Document, AppError> {
+    let mut set = doc! {};
+    if let Some(first_name) = changes.first_name {
+        set.insert("firstName", first_name);
+    }
+    if let Some(last_name) = changes.last_name {
+        set.insert("lastName", last_name);
+    }
+    if let Some(email) = changes.email {
+        set.insert("email", email);
+    }
+    if let Some(password) = changes.password {
+        set.insert("password", password);
+    }
+    if let Some(roles) = changes.roles {
+        let roles = bson::to_bson(&roles)
+            .map_err(|error| AppError::Internal(format!("Failed to encode roles: {}", error)))?;
+        set.insert("roles", roles);
+    }
+    if let Some(updated_at) = changes.updated_at {
+        set.insert("updatedAt", updated_at);
+    }
+    Ok(set)
+}
+
+#[async_trait]
+impl UserRepository for MongoUserRepository {
+    async fn insert(&self, mut user: User) -> Result<User, AppError> {
+        user.id = None;
+        let res = self.coll.insert_one(&user).await.map_err(email_conflict)?;
+        user.id = res.inserted_id.as_object_id();
+        Ok(user)
+    }
+
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<User>, AppError> {
+        Ok(self
+            .coll
+            .find_one(doc! {"_id": id})
+            .projection(doc! {"password": 0})
+            .await?)
+    }
+
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
+        Ok(self
+            .coll
+            .find_one(doc! {"email": email})
+            .collation(db::email_collation())
+            .await?)
+    }
+
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        request: &PageRequest,
+    ) -> Result<Page<User>, AppError> {
+        Ok(pagination::find_page(
+            &self.coll,
+            filter_document(filter),
+            Some(doc! {"password": 0}),
+            request,
+            |user| user.id,
+        )
+        .await?)
+    }
+
+    async fn update(&self, id: ObjectId, changes: UserChanges) -> Result<Option<User>, AppError> {
+        let set = changes_document(changes)?;
+        self.coll
+            .find_one_and_update(doc! {"_id": id}, doc! {"$set": set})
+            .projection(doc! {"password": 0})
+            .return_document(ReturnDocument::After)
+            .await
+            .map_err(email_conflict)
+    }
+
+    async fn delete(&self, id: ObjectId) -> Result<Option<User>, AppError> {
+        Ok(self
+            .coll
+            .find_one_and_delete(doc! {"_id": id})
+            .projection(doc! {"password": 0})
+            .await?)
+    }
+}
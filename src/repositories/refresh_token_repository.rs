@@ -0,0 +1,24 @@
+use axum::async_trait;
+use mongodb::bson::oid::ObjectId;
+
+use crate::{common_struct::AppError, models::auth_module::RefreshToken};
+
+/// Storage for refresh tokens, which are only ever looked up by the hash of
+/// their value. Revoking marks tokens rather than deleting them, so reuse of
+/// a rotated token can still be recognised.
+#[async_trait]
+pub trait RefreshTokenRepository: Send + Sync {
+    async fn insert(&self, token: RefreshToken) -> Result<(), AppError>;
+
+    async fn find_by_hash(&self, token_hash: &str) -> Result<Option<RefreshToken>, AppError>;
+
+    /// Revokes one token unless it already was. Returns whether this call did,
+    /// so two concurrent refreshes cannot both rotate the same token.
+    async fn claim(&self, id: ObjectId) -> Result<bool, AppError>;
+
+    /// Revokes every live token issued from the same login.
+    async fn revoke_family(&self, family_id: ObjectId) -> Result<(), AppError>;
+
+    /// Revokes every live token the user holds, ending all their sessions.
+    async fn revoke_user(&self, user_id: ObjectId) -> Result<(), AppError>;
+}
@@ -0,0 +1,145 @@
+use axum::async_trait;
+use mongodb::bson::{oid::ObjectId, DateTime};
+
+use crate::{
+    auth::permissions::Role,
+    common_struct::{
+        pagination::{Page, PageRequest},
+        AppError,
+    },
+    models::user_module::User,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextMatch {
+    Exact(String),
+    Prefix(String),
+}
+
+impl TextMatch {
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        match (self, value) {
+            (TextMatch::Exact(expected), Some(value)) => value == expected,
+            (TextMatch::Prefix(prefix), Some(value)) => value.starts_with(prefix.as_str()),
+            (_, None) => false,
+        }
+    }
+}
+
+/// Criteria for listing users. Every field that is set must match.
+#[derive(Debug, Clone, Default)]
+pub struct UserFilter {
+    pub first_name: Option<TextMatch>,
+    pub last_name: Option<TextMatch>,
+    pub email: Option<TextMatch>,
+    /// Inclusive bounds on `createdAt`.
+    pub created_from: Option<DateTime>,
+    pub created_to: Option<DateTime>,
+}
+
+impl UserFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        let text = [
+            (&self.first_name, &user.first_name),
+            (&self.last_name, &user.last_name),
+            (&self.email, &user.email),
+        ];
+        text.into_iter().all(|(filter, value)| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| filter.matches(value.as_deref()))
+        }) && self
+            .created_from
+            .is_none_or(|from| user.created_at.is_some_and(|at| at >= from))
+            && self
+                .created_to
+                .is_none_or(|to| user.created_at.is_some_and(|at| at <= to))
+    }
+}
+
+/// Fields to overwrite on an existing user; `None` leaves a field untouched.
+/// `password` must already be hashed.
+#[derive(Debug, Clone, Default)]
+pub struct UserChanges {
+    pub first_name: Option<String>,
+    pub last_name: Option<String>,
+    pub email: Option<String>,
+    pub password: Option<String>,
+    pub roles: Option<Vec<Role>>,
+    pub updated_at: Option<DateTime>,
+}
+
+impl UserChanges {
+    pub fn is_empty(&self) -> bool {
+        self.first_name.is_none()
+            && self.last_name.is_none()
+            && self.email.is_none()
+            && self.password.is_none()
+            && self.roles.is_none()
+            && self.updated_at.is_none()
+    }
+
+    pub fn apply(self, user: &mut User) {
+        let User {
+            first_name,
+            last_name,
+            email,
+            password,
+            roles,
+            updated_at,
+            ..
+        } = user;
+        for (value, slot) in [
+            (self.first_name, first_name),
+            (self.last_name, last_name),
+            (self.email, email),
+            (self.password, password),
+        ] {
+            if value.is_some() {
+                *slot = value;
+            }
+        }
+        if self.roles.is_some() {
+            *roles = self.roles;
+        }
+        if self.updated_at.is_some() {
+            *updated_at = self.updated_at;
+        }
+    }
+}
+
+/// Storage for users. Implementations report a taken email as
+/// `AppError::Conflict { code: "email_taken", .. }`, comparing emails
+/// case-insensitively.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Stores a new user and returns it with its assigned `_id`.
+    async fn insert(&self, user: User) -> Result<User, AppError>;
+
+    /// Looks a user up by id. The password hash is never included.
+    async fn find_by_id(&self, id: ObjectId) -> Result<Option<User>, AppError>;
+
+    /// Looks a user up by email, ignoring case. Includes the password hash so
+    /// callers can verify credentials.
+    async fn find_by_email(&self, email: &str) -> Result<Option<User>, AppError>;
+
+    /// Lists matching users without their password hashes.
+    async fn list(
+        &self,
+        filter: &UserFilter,
+        request: &PageRequest,
+    ) -> Result<Page<User>, AppError>;
+
+    /// Applies `changes` and returns the updated user, or `None` if it does not exist.
+    async fn update(&self, id: ObjectId, changes: UserChanges) -> Result<Option<User>, AppError>;
+
+    /// Removes a user and returns what was deleted, or `None` if it did not exist.
+    async fn delete(&self, id: ObjectId) -> Result<Option<User>, AppError>;
+}
+
+pub fn email_taken() -> AppError {
+    AppError::Conflict {
+        code: "email_taken",
+        message: "A user with this email already exists".to_string(),
+    }
+}
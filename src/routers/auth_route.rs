@@ -0,0 +1,24 @@
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    auth::extractor::require_auth,
+    controllers::auth_controller::{login, logout, me, refresh},
+    state::AppState,
+};
+
+pub fn auth_routes() -> Router<AppState> {
+    let public = Router::new()
+        .route("/auth/login", post(login))
+        .route("/auth/refresh", post(refresh))
+        .route("/auth/logout", post(logout));
+
+    let protected = Router::new()
+        .route("/auth/me", get(me))
+        .route_layer(middleware::from_fn(require_auth));
+
+    public.merge(protected)
+}
@@ -0,0 +1,290 @@
+use axum::{
+    routing::{delete, get, patch, post},
+    Router,
+};
+
+use crate::{
+    controllers::cart_controller::{
+        add_cart_item, apply_coupon, clear_cart, get_cart, remove_cart_item, remove_coupon,
+        update_cart_item,
+    },
+    state::AppState,
+};
+
+/// The caller's cart. Signed-in users get their own; anyone else is tracked
+/// by the `X-Cart-Token` header handed out on their first change.
+pub fn cart_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/v1/cart", get(get_cart).delete(clear_cart))
+        .route("/api/v1/cart/items", post(add_cart_item))
+        .route(
+            "/api/v1/cart/items/:productId",
+            patch(update_cart_item).delete(remove_cart_item),
+        )
+        .route("/api/v1/cart/coupons", post(apply_coupon))
+        .route("/api/v1/cart/coupons/:code", delete(remove_coupon))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::{json, Value};
+
+    use crate::{
+        auth::permissions::Role,
+        models::cart_module::CART_TOKEN_HEADER,
+        test_support::{error_codes, token, TestApp, TEST_PASSWORD},
+    };
+
+    async fn seed_promotion(app: &TestApp, code: &str) {
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/promotions",
+                Some(&token(Role::Admin)),
+                Some(json!({"code": code, "rule": {"type": "percentage_off", "percent": 10}})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    /// Sends a cart request as the anonymous holder of `cart_token`, if any.
+    async fn as_anonymous(
+        app: &TestApp,
+        method: Method,
+        uri: &str,
+        cart_token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Option<String>, Value) {
+        let headers: Vec<(&str, &str)> = cart_token
+            .map(|cart_token| (CART_TOKEN_HEADER, cart_token))
+            .into_iter()
+            .collect();
+        let (status, headers, body) = app
+            .request_sending_headers(method, uri, None, &headers, body)
+            .await;
+        let issued = headers
+            .get(CART_TOKEN_HEADER)
+            .map(|value| value.to_str().unwrap().to_string());
+        (status, issued, body)
+    }
+
+    #[tokio::test]
+    async fn empty_cart_without_an_owner_is_served_without_a_database() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app.request(Method::GET, "/api/v1/cart", None, None).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["lines"], json!([]));
+        assert_eq!(body["data"]["subtotal"], json!(null));
+        assert_eq!(body["data"]["checkoutReady"], json!(false));
+    }
+
+    #[tokio::test]
+    async fn add_item_reports_invalid_fields() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/cart/items",
+                None,
+                Some(json!({"productId": "nope", "quantity": 0})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [
+                ("productId".to_string(), "invalid_id".to_string()),
+                ("quantity".to_string(), "out_of_range".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn changing_an_item_without_a_cart_is_not_found() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/cart/items/{}", ObjectId::new()),
+                None,
+                Some(json!({"quantity": 2})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn removing_an_item_rejects_invalid_product_id() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(Method::DELETE, "/api/v1/cart/items/nope", None, None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("productId".to_string(), "invalid_id".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn applying_a_coupon_validates_the_code() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/cart/coupons",
+                None,
+                Some(json!({"code": "no spaces!"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("code".to_string(), "invalid_code".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn applying_a_coupon_without_a_cart_conflicts() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/cart/coupons",
+                None,
+                Some(json!({"code": "SAVE10"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "cart_empty".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_issued_cart_token_names_the_same_cart() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 10).await;
+        let item = json!({"productId": product_id.to_hex(), "quantity": 1});
+
+        let (first_status, issued, _) = as_anonymous(
+            &app,
+            Method::POST,
+            "/api/v1/cart/items",
+            None,
+            Some(item.clone()),
+        )
+        .await;
+        let cart_token = issued.expect("the first change issues a cart token");
+        let (again_status, reissued, body) = as_anonymous(
+            &app,
+            Method::POST,
+            "/api/v1/cart/items",
+            Some(&cart_token),
+            Some(item),
+        )
+        .await;
+
+        assert_eq!(first_status, StatusCode::OK);
+        assert_eq!(again_status, StatusCode::OK);
+        assert_eq!(reissued, None);
+        assert_eq!(body["data"]["lines"][0]["quantity"], json!(2));
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn a_cart_token_the_server_did_not_issue_is_refused() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 10).await;
+
+        let (add_status, issued, body) = as_anonymous(
+            &app,
+            Method::POST,
+            "/api/v1/cart/items",
+            Some("made-up"),
+            Some(json!({"productId": product_id.to_hex(), "quantity": 1})),
+        )
+        .await;
+        let (get_status, _, _) =
+            as_anonymous(&app, Method::GET, "/api/v1/cart", Some("made-up"), None).await;
+
+        assert_eq!(add_status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [(CART_TOKEN_HEADER.to_string(), "unknown_cart".to_string())]
+        );
+        assert_eq!(issued, None);
+        assert_eq!(get_status, StatusCode::BAD_REQUEST);
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn merging_at_login_drops_coupons_that_do_not_stack() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 10).await;
+        seed_promotion(&app, "MINE").await;
+        seed_promotion(&app, "THEIRS").await;
+        let item = json!({"productId": product_id.to_hex(), "quantity": 1});
+        let (_, user_token) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        for (uri, body) in [
+            ("/api/v1/cart/items", item.clone()),
+            ("/api/v1/cart/coupons", json!({"code": "MINE"})),
+        ] {
+            let (status, _) = app
+                .request(Method::POST, uri, Some(&user_token), Some(body))
+                .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (_, issued, _) =
+            as_anonymous(&app, Method::POST, "/api/v1/cart/items", None, Some(item)).await;
+        let cart_token = issued.unwrap();
+        let (status, _, _) = as_anonymous(
+            &app,
+            Method::POST,
+            "/api/v1/cart/coupons",
+            Some(&cart_token),
+            Some(json!({"code": "THEIRS"})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (login_status, _, _) = app
+            .request_sending_headers(
+                Method::POST,
+                "/auth/login",
+                None,
+                &[(CART_TOKEN_HEADER, &cart_token)],
+                Some(json!({"email": "shopper@example.com", "password": TEST_PASSWORD})),
+            )
+            .await;
+        let (_, cart) = app
+            .request(Method::GET, "/api/v1/cart", Some(&user_token), None)
+            .await;
+
+        assert_eq!(login_status, StatusCode::OK);
+        assert_eq!(cart["data"]["couponCodes"], json!(["MINE"]));
+        assert_eq!(cart["data"]["lines"][0]["quantity"], json!(2));
+        app.drop_database().await;
+    }
+}
@@ -0,0 +1,200 @@
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    auth::extractor::require_auth,
+    controllers::inventory_controller::{
+        adjust_stock, commit_reservation, create_reservation, get_reservation, list_movements,
+        release_reservation,
+    },
+    state::AppState,
+};
+
+/// Stock reservations and the ledger. Everything needs a token; adjustments,
+/// commits and the ledger also need `inventory:admin`.
+pub fn inventory_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/v1/inventory/reservations", post(create_reservation))
+        .route("/api/v1/inventory/reservations/:id", get(get_reservation))
+        .route(
+            "/api/v1/inventory/reservations/:id/release",
+            post(release_reservation),
+        )
+        .route(
+            "/api/v1/inventory/reservations/:id/commit",
+            post(commit_reservation),
+        )
+        .route(
+            "/api/v1/products/:id/inventory/adjustments",
+            post(adjust_stock),
+        )
+        .route(
+            "/api/v1/products/:id/inventory/movements",
+            get(list_movements),
+        )
+        .route_layer(middleware::from_fn(require_auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use mongodb::bson::{doc, oid::ObjectId};
+    use serde_json::{json, Value};
+
+    use crate::{
+        auth::permissions::Role,
+        test_support::{error_codes, token, token_for, TestApp},
+    };
+
+    async fn reserve(app: &TestApp, token: &str, product_id: ObjectId) -> (StatusCode, Value) {
+        app.request(
+            Method::POST,
+            "/api/v1/inventory/reservations",
+            Some(token),
+            Some(json!({"productId": product_id.to_hex(), "quantity": 2})),
+        )
+        .await
+    }
+
+    async fn stock(app: &TestApp, product_id: ObjectId) -> i64 {
+        let product = app.state.products.find_one(doc! {"_id": product_id}).await;
+        product.unwrap().unwrap().stock_quantity.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reserve_requires_a_token() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/inventory/reservations",
+                None,
+                Some(json!({"productId": ObjectId::new().to_hex(), "quantity": 1})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn reserve_reports_invalid_fields() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/inventory/reservations",
+                Some(&token(Role::User)),
+                Some(json!({"productId": "nope", "quantity": 0})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [
+                ("productId".to_string(), "invalid_id".to_string()),
+                ("quantity".to_string(), "out_of_range".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn commit_requires_inventory_admin() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/inventory/reservations/{}/commit", ObjectId::new()),
+                Some(&token(Role::User)),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "forbidden".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn adjustment_needs_a_nonzero_delta_and_reason() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/products/{}/inventory/adjustments", ObjectId::new()),
+                Some(&token(Role::Admin)),
+                Some(json!({"delta": 0, "reason": "recount"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("delta".to_string(), "out_of_range".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn ledger_requires_inventory_admin() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/products/{}/inventory/movements", ObjectId::new()),
+                Some(&token(Role::User)),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn a_second_hold_on_the_same_product_waits_for_the_first() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(500, 10).await;
+        let user = token_for(&ObjectId::new(), "holder@example.com", &[Role::User]);
+
+        let (first_status, first) = reserve(&app, &user, product_id).await;
+        let (second_status, second) = reserve(&app, &user, product_id).await;
+        let stock_while_held = stock(&app, product_id).await;
+        let (released, _) = app
+            .request(
+                Method::POST,
+                &format!(
+                    "/api/v1/inventory/reservations/{}/release",
+                    first["data"]["id"].as_str().unwrap()
+                ),
+                Some(&user),
+                None,
+            )
+            .await;
+        let (third_status, _) = reserve(&app, &user, product_id).await;
+        let (other_status, _) = reserve(&app, &token(Role::User), product_id).await;
+
+        assert_eq!(first_status, StatusCode::CREATED);
+        assert_eq!(second_status, StatusCode::CONFLICT);
+        assert_eq!(
+            error_codes(&second),
+            [(String::new(), "reservation_exists".to_string())]
+        );
+        assert_eq!(stock_while_held, 8);
+        assert_eq!(released, StatusCode::OK);
+        assert_eq!(third_status, StatusCode::CREATED);
+        assert_eq!(other_status, StatusCode::CREATED);
+        assert_eq!(stock(&app, product_id).await, 6);
+        app.drop_database().await;
+    }
+}
@@ -1,9 +1,41 @@
+mod auth_route;
+mod cart_route;
+mod inventory_route;
+mod order_route;
+mod payment_route;
 mod product_route;
+mod promotion_route;
 mod user_route;
-use axum::Router;
+use auth_route::auth_routes;
+use axum::{http::StatusCode, Extension, Router};
+use cart_route::cart_routes;
+use inventory_route::inventory_routes;
+use order_route::order_routes;
+use payment_route::payment_routes;
 use product_route::product_routes;
-use user_route::user_routes;
-pub async fn router() -> Router {
-    let router = Router::new().merge(user_routes()).merge(product_routes());
-    router
+use promotion_route::promotion_routes;
+use tower_http::timeout::TimeoutLayer;
+use user_route::{legacy_user_routes, user_routes};
+
+use crate::state::AppState;
+
+pub async fn router(state: AppState) -> Router {
+    let mut app = Router::new()
+        .merge(auth_routes())
+        .merge(user_routes())
+        .merge(product_routes())
+        .merge(inventory_routes())
+        .merge(cart_routes())
+        .merge(order_routes())
+        .merge(payment_routes())
+        .merge(promotion_routes());
+    if state.config.legacy_routes {
+        app = app.merge(legacy_user_routes());
+    }
+    app.layer(Extension(state.jwt_keys.clone()))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            state.config.request_timeout,
+        ))
+        .with_state(state)
 }
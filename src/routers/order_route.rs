@@ -0,0 +1,411 @@
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    auth::extractor::require_auth,
+    controllers::order_controller::{get_order, list_orders, place_order, update_order_status},
+    state::AppState,
+};
+
+/// Checkout, order history and status changes. Everything needs a token;
+/// other users' orders and any change but cancelling a pending order need
+/// `orders:admin`.
+pub fn order_routes() -> Router<AppState> {
+    Router::new()
+        .route("/api/v1/orders", get(list_orders).post(place_order))
+        .route("/api/v1/orders/:id", get(get_order))
+        .route("/api/v1/orders/:id/status", post(update_order_status))
+        .route_layer(middleware::from_fn(require_auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use mongodb::bson::{doc, oid::ObjectId};
+    use serde_json::{json, Value};
+
+    use crate::{
+        auth::permissions::Role,
+        test_support::{error_codes, token, token_for, TestApp},
+    };
+
+    /// Puts `quantity` of `product_id` in the cart of the user behind `user_token`.
+    async fn add_to_cart(app: &TestApp, user_token: &str, product_id: ObjectId, quantity: i64) {
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/cart/items",
+                Some(user_token),
+                Some(json!({"productId": product_id.to_hex(), "quantity": quantity})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    async fn place_order(app: &TestApp, user_token: &str) -> (StatusCode, Value) {
+        app.request(Method::POST, "/api/v1/orders", Some(user_token), None)
+            .await
+    }
+
+    async fn set_status(
+        app: &TestApp,
+        user_token: &str,
+        order: &Value,
+        status: &str,
+    ) -> (StatusCode, Value) {
+        app.request(
+            Method::POST,
+            &format!("/api/v1/orders/{}/status", order["id"].as_str().unwrap()),
+            Some(user_token),
+            Some(json!({"status": status})),
+        )
+        .await
+    }
+
+    async fn stock(app: &TestApp, product_id: ObjectId) -> i64 {
+        let product = app.state.products.find_one(doc! {"_id": product_id}).await;
+        product.unwrap().unwrap().stock_quantity.unwrap()
+    }
+
+    async fn cart_lines(app: &TestApp, user_token: &str) -> Value {
+        let (_, body) = app
+            .request(Method::GET, "/api/v1/cart", Some(user_token), None)
+            .await;
+        body["data"]["lines"].clone()
+    }
+
+    #[tokio::test]
+    async fn placing_an_order_requires_a_token() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(Method::POST, "/api/v1/orders", None, None)
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn get_order_rejects_invalid_id() {
+        let app = TestApp::new().await;
+        let token = token_for(&ObjectId::new(), "someone@example.com", &[Role::User]);
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/orders/nope", Some(&token), None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("id".to_string(), "invalid_id".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn listing_orders_rejects_an_out_of_range_limit() {
+        let app = TestApp::new().await;
+        let token = token_for(&ObjectId::new(), "someone@example.com", &[Role::User]);
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/orders?limit=0", Some(&token), None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("limit".to_string(), "out_of_range".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn status_change_requires_a_known_status() {
+        let app = TestApp::new().await;
+        let token = token_for(&ObjectId::new(), "someone@example.com", &[Role::Admin]);
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/orders/{}/status", ObjectId::new()),
+                Some(&token),
+                Some(json!({"reason": "customer called"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("status".to_string(), "required".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_order_admins_move_orders_past_cancelling() {
+        let app = TestApp::new().await;
+        let token = token_for(&ObjectId::new(), "someone@example.com", &[Role::User]);
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/orders/{}/status", ObjectId::new()),
+                Some(&token),
+                Some(json!({"status": "shipped"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "forbidden".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn orders_are_not_marked_refunded_directly() {
+        let app = TestApp::new().await;
+        let token = token_for(&ObjectId::new(), "admin@example.com", &[Role::Admin]);
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/orders/{}/status", ObjectId::new()),
+                Some(&token),
+                Some(json!({"status": "refunded"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("status".to_string(), "refund_only".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn an_order_with_nothing_to_pay_is_placed_paid() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(0, 5).await;
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        add_to_cart(&app, &shopper, product_id, 1).await;
+
+        let (status, body) = place_order(&app, &shopper).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["data"]["total"]["amount"], json!("0.00"));
+        assert_eq!(body["data"]["status"], json!("paid"));
+        assert_eq!(body["data"]["statusHistory"].as_array().unwrap().len(), 2);
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn cancelling_an_order_gives_back_its_coupon_use() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 5).await;
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/promotions",
+                Some(&token(Role::Admin)),
+                Some(json!({
+                    "code": "ONCE",
+                    "rule": {"type": "percentage_off", "percent": 10},
+                    "usageLimit": 1,
+                    "perUserLimit": 1,
+                })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        let use_coupon = || async {
+            add_to_cart(&app, &shopper, product_id, 1).await;
+            let (status, _) = app
+                .request(
+                    Method::POST,
+                    "/api/v1/cart/coupons",
+                    Some(&shopper),
+                    Some(json!({"code": "ONCE"})),
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK);
+            place_order(&app, &shopper).await
+        };
+        let (_, first) = use_coupon().await;
+
+        let (cancel_status, _) = app
+            .request(
+                Method::POST,
+                &format!(
+                    "/api/v1/orders/{}/status",
+                    first["data"]["id"].as_str().unwrap()
+                ),
+                Some(&shopper),
+                Some(json!({"status": "cancelled"})),
+            )
+            .await;
+        let (again_status, again) = use_coupon().await;
+
+        assert_eq!(cancel_status, StatusCode::OK);
+        assert_eq!(again_status, StatusCode::CREATED);
+        assert_eq!(again["data"]["discounts"][0]["code"], json!("ONCE"));
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn placing_an_order_takes_its_stock_and_empties_the_cart() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 5).await;
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        add_to_cart(&app, &shopper, product_id, 2).await;
+
+        let (status, body) = place_order(&app, &shopper).await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["data"]["status"], json!("pending"));
+        assert_eq!(body["data"]["total"]["amount"], json!("20.00"));
+        assert_eq!(body["data"]["lines"][0]["quantity"], json!(2));
+        assert_eq!(stock(&app, product_id).await, 3);
+        assert_eq!(cart_lines(&app, &shopper).await, json!([]));
+        let (_, movements) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/products/{}/inventory/movements", product_id),
+                Some(&token(Role::Admin)),
+                None,
+            )
+            .await;
+        let movement = &movements["data"]["items"][0];
+        assert_eq!(movement["kind"], json!("reserve"));
+        assert_eq!(movement["delta"], json!(-2));
+        assert_eq!(movement["orderId"], body["data"]["id"]);
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn an_order_the_stock_cannot_cover_changes_nothing() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 1).await;
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        add_to_cart(&app, &shopper, product_id, 2).await;
+
+        let (status, body) = place_order(&app, &shopper).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "cart_not_ready".to_string())]
+        );
+        assert_eq!(stock(&app, product_id).await, 1);
+        assert_eq!(cart_lines(&app, &shopper).await[0]["quantity"], json!(2));
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn an_empty_cart_cannot_be_ordered() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+
+        let (status, body) = place_order(&app, &shopper).await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "cart_empty".to_string())]
+        );
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn an_order_moves_along_the_table_and_keeps_its_history() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 5).await;
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        add_to_cart(&app, &shopper, product_id, 1).await;
+        let (_, placed) = place_order(&app, &shopper).await;
+        let admin = token(Role::Admin);
+
+        for status in ["paid", "fulfilled", "shipped", "delivered"] {
+            let (moved, body) = set_status(&app, &admin, &placed["data"], status).await;
+            assert_eq!(moved, StatusCode::OK);
+            assert_eq!(body["data"]["status"], json!(status));
+        }
+        let (status, body) = set_status(&app, &admin, &placed["data"], "cancelled").await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "invalid_transition".to_string())]
+        );
+        let (_, order) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/orders/{}", placed["data"]["id"].as_str().unwrap()),
+                Some(&shopper),
+                None,
+            )
+            .await;
+        let history: Vec<&Value> = order["data"]["statusHistory"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|change| &change["status"])
+            .collect();
+        assert_eq!(
+            history,
+            ["pending", "paid", "fulfilled", "shipped", "delivered"]
+        );
+        assert_eq!(stock(&app, product_id).await, 4);
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_pending_order_puts_its_stock_back() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 5).await;
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        add_to_cart(&app, &shopper, product_id, 2).await;
+        let (_, placed) = place_order(&app, &shopper).await;
+
+        let (status, body) = set_status(&app, &shopper, &placed["data"], "cancelled").await;
+        let (again, _) = set_status(&app, &shopper, &placed["data"], "cancelled").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["status"], json!("cancelled"));
+        assert_eq!(again, StatusCode::CONFLICT);
+        assert_eq!(stock(&app, product_id).await, 5);
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn owners_cannot_cancel_a_paid_order() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(1000, 5).await;
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        add_to_cart(&app, &shopper, product_id, 1).await;
+        let (_, placed) = place_order(&app, &shopper).await;
+        set_status(&app, &token(Role::Admin), &placed["data"], "paid").await;
+
+        let (status, _) = set_status(&app, &shopper, &placed["data"], "cancelled").await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(stock(&app, product_id).await, 4);
+        app.drop_database().await;
+    }
+}
@@ -0,0 +1,558 @@
+use axum::{
+    middleware,
+    routing::{get, post},
+    Router,
+};
+
+use crate::{
+    auth::extractor::require_auth,
+    controllers::payment_controller::{
+        get_payment, list_order_payments, pay_order, payment_webhook, refund_payment,
+    },
+    state::AppState,
+};
+
+/// Paying for orders and refunds. The provider webhook is public and checked
+/// by signature; everything else needs a token, and refunds `orders:admin`.
+pub fn payment_routes() -> Router<AppState> {
+    let public = Router::new().route("/api/v1/payments/webhook", post(payment_webhook));
+
+    let protected = Router::new()
+        .route(
+            "/api/v1/orders/:id/payments",
+            get(list_order_payments).post(pay_order),
+        )
+        .route("/api/v1/payments/:id", get(get_payment))
+        .route("/api/v1/payments/:id/refunds", post(refund_payment))
+        .route_layer(middleware::from_fn(require_auth));
+
+    public.merge(protected)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{Method, StatusCode};
+    use mongodb::bson::{doc, oid::ObjectId, DateTime};
+    use serde_json::{json, Value};
+
+    use crate::{
+        auth::permissions::Role,
+        common_struct::money::{Currency, Money},
+        config::PaymentProviderKind,
+        models::{
+            order_module::{Order, OrderLine, OrderStatus, StatusChange},
+            payment_module::{
+                Payment, PaymentRefund, PaymentStatus, RefundStatus, PAYMENT_SIGNATURE_HEADER,
+            },
+        },
+        payments::{
+            fake_payment_provider::{DECLINED_METHOD, DEV_WEBHOOK_SECRET},
+            FakePaymentProvider, PaymentProvider, ProviderOutcome,
+        },
+        repositories::InMemoryUserRepository,
+        test_support::{error_codes, token, token_for, TestApp},
+    };
+
+    fn usd(minor_units: i64) -> Money {
+        Money::from_minor(minor_units, Currency::from_code("USD").unwrap())
+    }
+
+    /// A pending order for 50.00 USD belonging to a new user, with that user's token.
+    async fn seed_order(app: &TestApp) -> (ObjectId, String) {
+        let user_id = ObjectId::new();
+        let now = DateTime::now();
+        let order = Order {
+            id: Some(ObjectId::new()),
+            user_id,
+            status: OrderStatus::Pending,
+            status_history: vec![StatusChange {
+                from: None,
+                status: OrderStatus::Pending,
+                actor_id: Some(user_id),
+                reason: None,
+                at: now,
+            }],
+            lines: vec![OrderLine {
+                product_id: ObjectId::new(),
+                name: "Widget".to_string(),
+                sku: "W-1".to_string(),
+                quantity: 1,
+                unit_price: usd(5000),
+                line_total: usd(5000),
+            }],
+            discounts: Vec::new(),
+            total: usd(5000),
+            created_at: now,
+            updated_at: now,
+        };
+        app.state.orders.insert_one(&order).await.unwrap();
+        (
+            order.id.unwrap(),
+            token_for(&user_id, "owner@example.com", &[Role::User]),
+        )
+    }
+
+    /// An attempt on `order_id` already in `status`, as if its webhook were still to come.
+    async fn seed_payment(app: &TestApp, order_id: ObjectId, status: PaymentStatus) -> String {
+        let reference = format!("fake_ch_{}", ObjectId::new().to_hex());
+        let now = DateTime::now();
+        app.state
+            .payments
+            .insert_one(Payment {
+                id: Some(ObjectId::new()),
+                order_id,
+                // Authorized attempts still hold the order, as they do in `pay_order`.
+                active_order_id: (status == PaymentStatus::Authorized).then_some(order_id),
+                user_id: ObjectId::new(),
+                provider: "fake".to_string(),
+                status,
+                amount: usd(5000),
+                refunded: usd(0),
+                provider_reference: Some(reference.clone()),
+                decline_code: None,
+                refunds: Vec::new(),
+                webhook_events: Vec::new(),
+                created_at: now,
+                updated_at: now,
+            })
+            .await
+            .unwrap();
+        reference
+    }
+
+    async fn pay(app: &TestApp, token: &str, order_id: ObjectId, method: &str) -> Value {
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/orders/{}/payments", order_id),
+                Some(token),
+                Some(json!({"paymentMethod": method})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        body["data"].clone()
+    }
+
+    async fn refund(app: &TestApp, payment_id: &Value, body: Value) -> (StatusCode, Value) {
+        app.request(
+            Method::POST,
+            &format!("/api/v1/payments/{}/refunds", payment_id.as_str().unwrap()),
+            Some(&token(Role::Admin)),
+            Some(body),
+        )
+        .await
+    }
+
+    async fn order_status(app: &TestApp, token: &str, order_id: ObjectId) -> Value {
+        let (_, body) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/orders/{}", order_id),
+                Some(token),
+                None,
+            )
+            .await;
+        body["data"]["status"].clone()
+    }
+
+    async fn send_webhook(app: &TestApp, event: Value, signature: &str) -> (StatusCode, Value) {
+        let (status, _, body) = app
+            .request_sending_headers(
+                Method::POST,
+                "/api/v1/payments/webhook",
+                None,
+                &[(PAYMENT_SIGNATURE_HEADER, signature)],
+                Some(event),
+            )
+            .await;
+        (status, body)
+    }
+
+    fn sign(event: &Value) -> String {
+        FakePaymentProvider::new(DEV_WEBHOOK_SECRET).sign(event.to_string().as_bytes())
+    }
+
+    #[tokio::test]
+    async fn paying_requires_a_payment_method() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/orders/{}/payments", ObjectId::new()),
+                Some(&token(Role::User)),
+                Some(json!({})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("paymentMethod".to_string(), "required".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn refunds_require_orders_admin() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/payments/{}/refunds", ObjectId::new()),
+                Some(&token(Role::User)),
+                Some(json!({})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn refund_amounts_must_be_positive() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/payments/{}/refunds", ObjectId::new()),
+                Some(&token(Role::Admin)),
+                Some(json!({"amount": {"amount": "0.00", "currency": "USD"}})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("amount".to_string(), "out_of_range".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn unsigned_webhooks_are_rejected() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/payments/webhook",
+                None,
+                Some(json!({"id": "evt_1", "type": "payment.captured", "reference": "fake_ch_1"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn payments_are_unavailable_without_a_provider() {
+        let app = TestApp::with_config(Arc::new(InMemoryUserRepository::new()), |config| {
+            config.payment_provider = PaymentProviderKind::None
+        })
+        .await;
+
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/payments/webhook",
+                None,
+                Some(json!({"id": "evt_1", "type": "payment.captured", "reference": "fake_ch_1"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn wrongly_signed_webhooks_are_rejected() {
+        let app = TestApp::new().await;
+        let event = json!({"id": "evt_1", "type": "payment.captured", "reference": "fake_ch_1"});
+
+        let (status, _) = send_webhook(&app, event, &"0".repeat(64)).await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn a_declined_payment_leaves_the_order_pending() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+
+        let declined = pay(&app, &owner, order_id, DECLINED_METHOD).await;
+        let status_after_decline = order_status(&app, &owner, order_id).await;
+        // The decline lets go of the order, so another card can be tried.
+        let captured = pay(&app, &owner, order_id, "fake_visa").await;
+
+        assert_eq!(declined["status"], json!("declined"));
+        assert_eq!(declined["declineCode"], json!("card_declined"));
+        assert_eq!(status_after_decline, json!("pending"));
+        assert_eq!(captured["status"], json!("captured"));
+        assert_eq!(order_status(&app, &owner, order_id).await, json!("paid"));
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn a_partial_refund_can_be_followed_by_the_rest() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let payment = pay(&app, &owner, order_id, "fake_visa").await;
+
+        let (partial_status, partial) = refund(
+            &app,
+            &payment["id"],
+            json!({"amount": {"amount": "20.00", "currency": "USD"}}),
+        )
+        .await;
+        let (rest_status, rest) = refund(&app, &payment["id"], json!({})).await;
+
+        assert_eq!(partial_status, StatusCode::OK);
+        assert_eq!(partial["data"]["status"], json!("partially_refunded"));
+        assert_eq!(partial["data"]["refunded"]["amount"], json!("20.00"));
+        assert_eq!(rest_status, StatusCode::OK);
+        assert_eq!(rest["data"]["status"], json!("refunded"));
+        assert_eq!(rest["data"]["refunded"]["amount"], json!("50.00"));
+        assert_eq!(rest["data"]["refunds"].as_array().unwrap().len(), 2);
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn refunds_cannot_exceed_the_payment() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let payment = pay(&app, &owner, order_id, "fake_visa").await;
+
+        let (status, body) = refund(
+            &app,
+            &payment["id"],
+            json!({"amount": {"amount": "60.00", "currency": "USD"}}),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "refund_exceeds_payment".to_string())]
+        );
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn a_resent_webhook_is_applied_once() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let reference = seed_payment(&app, order_id, PaymentStatus::Authorized).await;
+        let event = json!({"id": "evt_1", "type": "payment.captured", "reference": reference});
+
+        let (first_status, first) = send_webhook(&app, event.clone(), &sign(&event)).await;
+        let (again_status, again) = send_webhook(&app, event.clone(), &sign(&event)).await;
+
+        assert_eq!(first_status, StatusCode::OK);
+        assert_eq!(first["data"]["duplicate"], json!(false));
+        assert_eq!(again_status, StatusCode::OK);
+        assert_eq!(again["data"]["duplicate"], json!(true));
+        let (_, order) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/orders/{}", order_id),
+                Some(&owner),
+                None,
+            )
+            .await;
+        assert_eq!(order["data"]["status"], json!("paid"));
+        assert_eq!(order["data"]["statusHistory"].as_array().unwrap().len(), 2);
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn a_capture_webhook_for_a_declined_payment_does_not_pay_the_order() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let reference = seed_payment(&app, order_id, PaymentStatus::Declined).await;
+        let event = json!({"id": "evt_1", "type": "payment.captured", "reference": reference});
+
+        let (status, _) = send_webhook(&app, event.clone(), &sign(&event)).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(order_status(&app, &owner, order_id).await, json!("pending"));
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn a_failed_webhook_lets_the_order_be_paid_again() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let reference = seed_payment(&app, order_id, PaymentStatus::Authorized).await;
+        let event = json!({"id": "evt_1", "type": "payment.failed", "reference": reference});
+
+        let (status, _) = send_webhook(&app, event.clone(), &sign(&event)).await;
+        let payment = pay(&app, &owner, order_id, "fake_visa").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(payment["status"], json!("captured"));
+        assert_eq!(order_status(&app, &owner, order_id).await, json!("paid"));
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn a_full_refund_of_a_paid_order_marks_it_refunded() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let payment = pay(&app, &owner, order_id, "fake_visa").await;
+
+        let (status, body) = refund(&app, &payment["id"], json!({})).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["status"], json!("refunded"));
+        assert_eq!(body["data"]["refunds"][0]["status"], json!("succeeded"));
+        assert_eq!(
+            order_status(&app, &owner, order_id).await,
+            json!("refunded")
+        );
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn a_pending_refund_is_retried_under_its_key() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let payment = pay(&app, &owner, order_id, "fake_visa").await;
+        let payment_id = ObjectId::parse_str(payment["id"].as_str().unwrap()).unwrap();
+        let charge = payment["providerReference"].as_str().unwrap();
+        // As left by a refund whose provider call errored.
+        let pending = mongodb::bson::to_bson(&PaymentRefund {
+            idempotency_key: "refund-key".to_string(),
+            status: RefundStatus::Pending,
+            reference: None,
+            decline_code: None,
+            amount: usd(5000),
+            actor_id: None,
+            reason: None,
+            at: DateTime::now(),
+        })
+        .unwrap();
+        app.state
+            .payments
+            .update_one(
+                doc! {"_id": payment_id},
+                doc! {
+                    "$push": {"refunds": pending},
+                    "$set": {"refunded.amount": 5000},
+                },
+            )
+            .await
+            .unwrap();
+
+        let (status, body) = refund(&app, &payment["id"], json!({})).await;
+
+        let ProviderOutcome::Approved { reference } = FakePaymentProvider::new(DEV_WEBHOOK_SECRET)
+            .refund(charge, usd(5000), "refund-key")
+            .await
+            .unwrap()
+        else {
+            panic!("expected approval");
+        };
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["status"], json!("refunded"));
+        assert_eq!(body["data"]["refunds"].as_array().unwrap().len(), 1);
+        assert_eq!(body["data"]["refunds"][0]["reference"], json!(reference));
+        assert_eq!(
+            order_status(&app, &owner, order_id).await,
+            json!("refunded")
+        );
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_paid_order_refunds_it() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let (order_id, owner) = seed_order(&app).await;
+        let payment = pay(&app, &owner, order_id, "fake_visa").await;
+
+        let (status, _) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/orders/{}/status", order_id),
+                Some(&token(Role::Admin)),
+                Some(json!({"status": "cancelled"})),
+            )
+            .await;
+        let (_, refunded) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/payments/{}", payment["id"].as_str().unwrap()),
+                Some(&owner),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            order_status(&app, &owner, order_id).await,
+            json!("cancelled")
+        );
+        assert_eq!(refunded["data"]["status"], json!("refunded"));
+        assert_eq!(refunded["data"]["refunds"][0]["status"], json!("succeeded"));
+        app.drop_database().await;
+    }
+
+    #[tokio::test]
+    async fn refunding_an_unshipped_order_puts_its_stock_back() {
+        let Some(app) = TestApp::with_database().await else {
+            return;
+        };
+        let product_id = app.seed_product(5000, 3).await;
+        let (_, shopper) = app.seed_user("shopper@example.com", &[Role::User]).await;
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/cart/items",
+                Some(&shopper),
+                Some(json!({"productId": product_id.to_hex(), "quantity": 1})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, placed) = app
+            .request(Method::POST, "/api/v1/orders", Some(&shopper), None)
+            .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let order_id = ObjectId::parse_str(placed["data"]["id"].as_str().unwrap()).unwrap();
+        let payment = pay(&app, &shopper, order_id, "fake_visa").await;
+
+        let (status, _) = refund(&app, &payment["id"], json!({})).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            order_status(&app, &shopper, order_id).await,
+            json!("refunded")
+        );
+        let product = app
+            .state
+            .products
+            .find_one(doc! {"_id": product_id})
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(product.stock_quantity, Some(3));
+        app.drop_database().await;
+    }
+}
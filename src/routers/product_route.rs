@@ -1,5 +1,377 @@
-use axum::{routing::get, Router};
+use axum::{
+    middleware,
+    routing::{delete, get, patch, post},
+    Router,
+};
 
-pub fn product_routes() -> Router {
-    Router::new().route("/product", get(|| async { "Hello, This is Product" }))
+use crate::{
+    auth::extractor::require_auth,
+    controllers::{
+        category_controller::{
+            create_category, delete_category, get_category, list_categories, move_category,
+        },
+        product_controller::{
+            create_product, delete_product, get_product, list_products, search_products,
+            update_product,
+        },
+    },
+    state::AppState,
+};
+
+/// The catalogue (products and the category tree) is public to read;
+/// changes need `products:admin`.
+pub fn product_routes() -> Router<AppState> {
+    let public = Router::new()
+        .route("/api/v1/products", get(list_products))
+        .route("/api/v1/products/search", get(search_products))
+        .route("/api/v1/products/:id", get(get_product))
+        .route("/api/v1/categories", get(list_categories))
+        .route("/api/v1/categories/:id", get(get_category));
+
+    let protected = Router::new()
+        .route("/api/v1/products", post(create_product))
+        .route(
+            "/api/v1/products/:id",
+            patch(update_product).delete(delete_product),
+        )
+        .route("/api/v1/categories", post(create_category))
+        .route("/api/v1/categories/:id", delete(delete_category))
+        .route("/api/v1/categories/:id/move", post(move_category))
+        .route_layer(middleware::from_fn(require_auth));
+
+    public.merge(protected)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::{json, Value};
+
+    use crate::{
+        auth::permissions::Role,
+        test_support::{error_codes, token, TestApp},
+    };
+
+    fn new_product() -> Value {
+        json!({
+            "name": "Analytical Engine",
+            "sku": "AE-001",
+            "price": {"amount": "1843.00", "currency": "GBP"},
+        })
+    }
+
+    #[tokio::test]
+    async fn create_product_requires_a_token() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(Method::POST, "/api/v1/products", None, Some(new_product()))
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn create_product_requires_products_admin() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/products",
+                Some(&token(Role::User)),
+                Some(new_product()),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "forbidden".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn create_product_reports_missing_and_invalid_fields() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/products",
+                Some(&token(Role::Admin)),
+                Some(json!({
+                    "sku": "lower-case",
+                    "price": {"amount": "-1.00", "currency": "GBP"},
+                    "images": ["not a url"],
+                })),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [
+                ("name", "required"),
+                ("images", "invalid_url"),
+                ("price", "out_of_range"),
+                ("sku", "invalid_sku"),
+            ]
+            .map(|(field, code)| (field.to_string(), code.to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn get_product_rejects_invalid_id() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/products/not-an-id", None, None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("id".to_string(), "invalid_id".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_product_rejects_invalid_fields() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/products/{}", ObjectId::new()),
+                Some(&token(Role::Admin)),
+                Some(json!({"price": {"amount": "10.00", "currency": "usd"}})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("price".to_string(), "unsupported_currency".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_product_rejects_prices_finer_than_the_currency() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/products/{}", ObjectId::new()),
+                Some(&token(Role::Admin)),
+                Some(json!({"price": {"amount": "5.5", "currency": "JPY"}})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("price".to_string(), "too_precise".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn list_products_rejects_invalid_category() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/products?category=shoes", None, None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("category".to_string(), "invalid_id".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn search_requires_a_query() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/products/search?q=%20", None, None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("q".to_string(), "required".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn search_rejects_unknown_sort() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::GET,
+                "/api/v1/products/search?q=engine&sort=stockQuantity",
+                None,
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("sort".to_string(), "unsupported_sort".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn sorting_by_price_needs_a_currency() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/products?sort=-price", None, None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("currency".to_string(), "required".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn listing_rejects_an_unknown_currency() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::GET,
+                "/api/v1/products?sort=price&currency=XYZ",
+                None,
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("currency".to_string(), "unsupported_currency".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_product_leaves_stock_to_the_ledger() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/products/{}", ObjectId::new()),
+                Some(&token(Role::Admin)),
+                Some(json!({"stockQuantity": 5})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("stockQuantity".to_string(), "read_only".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_product_requires_products_admin() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                &format!("/api/v1/products/{}", ObjectId::new()),
+                Some(&token(Role::User)),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn create_category_requires_products_admin() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/categories",
+                Some(&token(Role::User)),
+                Some(json!({"name": "Shoes", "slug": "shoes"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn create_category_reports_invalid_fields() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/categories",
+                Some(&token(Role::Admin)),
+                Some(json!({"slug": "Running Shoes", "parentId": "nope"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [
+                ("name", "required"),
+                ("parentId", "invalid_id"),
+                ("slug", "invalid_slug"),
+            ]
+            .map(|(field, code)| (field.to_string(), code.to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn move_category_rejects_invalid_parent_id() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                &format!("/api/v1/categories/{}/move", ObjectId::new()),
+                Some(&token(Role::Admin)),
+                Some(json!({"parentId": "nope"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("parentId".to_string(), "invalid_id".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_category_requires_a_token() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                &format!("/api/v1/categories/{}", ObjectId::new()),
+                None,
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
 }
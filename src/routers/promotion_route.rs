@@ -0,0 +1,116 @@
+use axum::{middleware, routing::get, Router};
+
+use crate::{
+    auth::extractor::require_auth,
+    controllers::promotion_controller::{create_promotion, get_promotion, list_promotions},
+    state::AppState,
+};
+
+/// Promotion administration; everything needs `promotions:admin`. Shoppers
+/// use codes through the cart's coupon routes.
+pub fn promotion_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/v1/promotions",
+            get(list_promotions).post(create_promotion),
+        )
+        .route("/api/v1/promotions/:id", get(get_promotion))
+        .route_layer(middleware::from_fn(require_auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    use crate::{
+        auth::permissions::Role,
+        test_support::{error_codes, token, TestApp},
+    };
+
+    #[tokio::test]
+    async fn creating_promotions_requires_promotions_admin() {
+        let app = TestApp::new().await;
+
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/api/v1/promotions",
+                Some(&token(Role::User)),
+                Some(json!({"code": "SAVE10", "rule": {"type": "free_shipping"}})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn create_promotion_reports_missing_and_invalid_fields() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/promotions",
+                Some(&token(Role::Admin)),
+                Some(json!({"usageLimit": 0, "startsAt": "tomorrow"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [
+                ("code".to_string(), "required".to_string()),
+                ("rule".to_string(), "required".to_string()),
+                ("startsAt".to_string(), "invalid_timestamp".to_string()),
+                ("usageLimit".to_string(), "out_of_range".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn percentages_must_be_between_one_and_a_hundred() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/promotions",
+                Some(&token(Role::Admin)),
+                Some(json!({"code": "HALF", "rule": {"type": "percentage_off", "percent": 150}})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("rule".to_string(), "out_of_range".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn validity_windows_must_end_after_they_start() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/api/v1/promotions",
+                Some(&token(Role::Admin)),
+                Some(json!({
+                    "code": "SPRING",
+                    "rule": {"type": "free_shipping"},
+                    "startsAt": "2026-04-01T00:00:00Z",
+                    "endsAt": "2026-03-01T00:00:00Z",
+                })),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("endsAt".to_string(), "invalid_window".to_string())]
+        );
+    }
+}
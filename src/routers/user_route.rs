@@ -1,14 +1,727 @@
 use axum::{
+    http::{header, HeaderValue},
+    middleware,
+    response::Response,
     routing::{get, post},
     Router,
 };
 
-use crate::controllers::user_controller::{add_user, delete_user, get_user, update_user};
+use crate::{
+    auth::extractor::require_auth,
+    controllers::user_controller::{
+        add_user, create_user, delete_user, get_user, list_users, replace_user, update_user,
+    },
+    state::AppState,
+};
+
+/// When the pre-v1 user paths are due to be removed.
+const LEGACY_SUNSET: &str = "Thu, 01 Jul 2027 00:00:00 GMT";
+
+pub fn user_routes() -> Router<AppState> {
+    let public = Router::new().route("/api/v1/users", post(create_user));
+
+    let protected = Router::new()
+        .route("/api/v1/users", get(list_users))
+        .route(
+            "/api/v1/users/:id",
+            get(get_user)
+                .patch(update_user)
+                .put(replace_user)
+                .delete(delete_user),
+        )
+        .route_layer(middleware::from_fn(require_auth));
+
+    public.merge(protected)
+}
+
+async fn mark_deprecated(mut response: Response) -> Response {
+    let headers = response.headers_mut();
+    headers.insert("deprecation", HeaderValue::from_static("true"));
+    headers.insert("sunset", HeaderValue::from_static(LEGACY_SUNSET));
+    headers.insert(
+        header::LINK,
+        HeaderValue::from_static("</api/v1/users>; rel=\"successor-version\""),
+    );
+    response
+}
 
-pub fn user_routes() -> Router {
-    Router::new()
+/// The original unversioned paths, kept while clients migrate. Mutations are
+/// still reachable over GET here, which is why they are switched off by
+/// `legacy_routes = false` once nobody needs them.
+pub fn legacy_user_routes() -> Router<AppState> {
+    let public = Router::new().route("/addUser", post(add_user));
+
+    let protected = Router::new()
+        .route("/users", get(list_users))
         .route("/getUser/:id", get(get_user))
-        .route("/addUser", post(add_user))
         .route("/udateUser/:id", get(update_user))
         .route("/deleteUser/:id", get(delete_user))
+        .route_layer(middleware::from_fn(require_auth));
+
+    public
+        .merge(protected)
+        .layer(middleware::map_response(mark_deprecated))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::http::{header, Method, StatusCode};
+    use mongodb::bson::oid::ObjectId;
+    use serde_json::{json, Value};
+
+    use super::LEGACY_SUNSET;
+    use crate::{
+        auth::permissions::Role,
+        config::Config,
+        repositories::InMemoryUserRepository,
+        test_support::{error_codes, token_for, FailingUserRepository, TestApp, TEST_PASSWORD},
+    };
+
+    const MISSING_ID: &str = "64b7f0c2a1b2c3d4e5f60718";
+
+    fn new_user() -> Value {
+        json!({
+            "firstName": "Ada",
+            "lastName": "Lovelace",
+            "email": "ada@example.com",
+            "password": TEST_PASSWORD,
+        })
+    }
+
+    async fn failing_app() -> (TestApp, String) {
+        let app = TestApp::with_users(Arc::new(FailingUserRepository)).await;
+        let token = token_for(&ObjectId::new(), "admin@example.com", &[Role::Admin]);
+        (app, token)
+    }
+
+    #[tokio::test]
+    async fn create_user_creates_a_regular_user() {
+        let app = TestApp::new().await;
+
+        let (status, headers, body) = app
+            .request_with_headers(Method::POST, "/api/v1/users", None, Some(new_user()))
+            .await;
+
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            headers[header::LOCATION],
+            format!("/api/v1/users/{}", body["data"]["id"].as_str().unwrap())
+        );
+        assert_eq!(body["data"]["email"], "ada@example.com");
+        assert_eq!(body["data"]["roles"], json!(["user"]));
+        assert!(body["data"].get("password").is_none());
+        let id = ObjectId::parse_str(body["data"]["id"].as_str().unwrap()).unwrap();
+        assert!(app.users.find_by_id(id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn create_user_reports_every_missing_field() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(Method::POST, "/api/v1/users", None, Some(json!({})))
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            ["firstName", "lastName", "email", "password"]
+                .map(|field| (field.to_string(), "required".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn create_user_rejects_invalid_fields() {
+        let app = TestApp::new().await;
+        let mut payload = new_user();
+        payload["email"] = json!("not-an-email");
+        payload["password"] = json!("short");
+
+        let (status, body) = app
+            .request(Method::POST, "/api/v1/users", None, Some(payload))
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [
+                ("email".to_string(), "invalid_email".to_string()),
+                ("password".to_string(), "weak_password".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn create_user_rejects_a_taken_email() {
+        let app = TestApp::new().await;
+        app.seed_user("ADA@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(Method::POST, "/api/v1/users", None, Some(new_user()))
+            .await;
+
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "email_taken".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn create_user_reports_database_failure() {
+        let (app, _) = failing_app().await;
+
+        let (status, body) = app
+            .request(Method::POST, "/api/v1/users", None, Some(new_user()))
+            .await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "database_error".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn list_users_returns_a_page() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+        app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/users?limit=1", Some(&admin), None)
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["total"], 2);
+        assert_eq!(body["data"]["items"].as_array().unwrap().len(), 1);
+        assert!(body["data"]["nextCursor"].is_string());
+    }
+
+    #[tokio::test]
+    async fn list_users_rejects_invalid_paging() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/users?limit=0", Some(&admin), None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("limit".to_string(), "out_of_range".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn list_users_requires_permission() {
+        let app = TestApp::new().await;
+        let (_, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, _) = app
+            .request(Method::GET, "/api/v1/users", Some(&token), None)
+            .await;
+
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn list_users_reports_database_failure() {
+        let (app, admin) = failing_app().await;
+
+        let (status, _) = app
+            .request(Method::GET, "/api/v1/users", Some(&admin), None)
+            .await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn get_user_returns_own_profile() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["id"], id.to_hex());
+    }
+
+    #[tokio::test]
+    async fn get_user_requires_a_token() {
+        let app = TestApp::new().await;
+
+        let (status, body) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                None,
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "unauthorized".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_user_rejects_invalid_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, body) = app
+            .request(Method::GET, "/api/v1/users/not-an-id", Some(&admin), None)
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("id".to_string(), "invalid_id".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_user_reports_unknown_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, body) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            error_codes(&body),
+            [(String::new(), "not_found".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn get_user_reports_database_failure() {
+        let (app, admin) = failing_app().await;
+
+        let (status, _) = app
+            .request(
+                Method::GET,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn update_user_changes_fields() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                Some(json!({"firstName": "Augusta"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["firstName"], "Augusta");
+        assert_eq!(body["data"]["lastName"], "User");
+    }
+
+    #[tokio::test]
+    async fn update_user_rejects_an_empty_update() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                Some(json!({})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("body".to_string(), "empty_update".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_user_rejects_invalid_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, body) = app
+            .request(
+                Method::PATCH,
+                "/api/v1/users/not-an-id",
+                Some(&admin),
+                Some(json!({"firstName": "Augusta"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            [("id".to_string(), "invalid_id".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn update_user_reports_unknown_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, _) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                Some(json!({"firstName": "Augusta"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn update_user_reports_database_failure() {
+        let (app, admin) = failing_app().await;
+
+        let (status, _) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                Some(json!({"firstName": "Augusta"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn delete_user_removes_the_user() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(
+                Method::DELETE,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["id"], id.to_hex());
+        assert!(app.users.find_by_id(id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_user_rejects_invalid_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                "/api/v1/users/not-an-id",
+                Some(&admin),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn delete_user_reports_unknown_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn delete_user_reports_database_failure() {
+        let (app, admin) = failing_app().await;
+
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn replace_user_overwrites_the_profile() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(
+                Method::PUT,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                Some(json!({
+                    "firstName": "Augusta",
+                    "lastName": "King",
+                    "email": "augusta@example.com",
+                })),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["data"]["firstName"], "Augusta");
+        assert_eq!(body["data"]["lastName"], "King");
+        assert_eq!(body["data"]["email"], "augusta@example.com");
+        assert_eq!(body["data"]["roles"], json!(["user"]));
+    }
+
+    #[tokio::test]
+    async fn replace_user_requires_the_full_profile() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+
+        let (status, body) = app
+            .request(
+                Method::PUT,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                Some(json!({"firstName": "Augusta"})),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_codes(&body),
+            ["lastName", "email"].map(|field| (field.to_string(), "required".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn replace_user_rejects_invalid_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, _) = app
+            .request(
+                Method::PUT,
+                "/api/v1/users/not-an-id",
+                Some(&admin),
+                Some(new_user()),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn replace_user_reports_unknown_id() {
+        let app = TestApp::new().await;
+        let (_, admin) = app.seed_user("admin@example.com", &[Role::Admin]).await;
+
+        let (status, _) = app
+            .request(
+                Method::PUT,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                Some(new_user()),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn replace_user_reports_database_failure() {
+        let (app, admin) = failing_app().await;
+
+        let (status, _) = app
+            .request(
+                Method::PUT,
+                &format!("/api/v1/users/{}", MISSING_ID),
+                Some(&admin),
+                Some(new_user()),
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn legacy_routes_still_work_and_announce_their_sunset() {
+        let app = TestApp::new().await;
+
+        let (status, headers, body) = app
+            .request_with_headers(Method::POST, "/addUser", None, Some(new_user()))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["deprecation"], "true");
+        assert_eq!(headers["sunset"], LEGACY_SUNSET);
+
+        let id = body["data"]["id"].as_str().unwrap().to_string();
+        let token = token_for(
+            &ObjectId::parse_str(&id).unwrap(),
+            "ada@example.com",
+            &[Role::User],
+        );
+        for path in ["getUser", "deleteUser"] {
+            let (status, headers, _) = app
+                .request_with_headers(
+                    Method::GET,
+                    &format!("/{}/{}", path, id),
+                    Some(&token),
+                    None,
+                )
+                .await;
+            assert_eq!(status, StatusCode::OK, "{}", path);
+            assert_eq!(headers["deprecation"], "true", "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_errors_are_marked_deprecated_too() {
+        let app = TestApp::new().await;
+
+        let (status, headers, _) = app
+            .request_with_headers(Method::GET, "/getUser/not-an-id", None, None)
+            .await;
+
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(headers["deprecation"], "true");
+    }
+
+    #[tokio::test]
+    async fn legacy_routes_can_be_switched_off() {
+        let app = TestApp::with_config(
+            Arc::new(InMemoryUserRepository::new()),
+            |config: &mut Config| config.legacy_routes = false,
+        )
+        .await;
+
+        let (status, _) = app
+            .request(Method::POST, "/addUser", None, Some(new_user()))
+            .await;
+
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    async fn log_in(app: &TestApp, email: &str) -> String {
+        let (status, body) = app
+            .request(
+                Method::POST,
+                "/auth/login",
+                None,
+                Some(json!({"email": email, "password": TEST_PASSWORD})),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        body["data"]["refreshToken"].as_str().unwrap().to_string()
+    }
+
+    async fn refresh(app: &TestApp, refresh_token: &str) -> StatusCode {
+        let (status, _) = app
+            .request(
+                Method::POST,
+                "/auth/refresh",
+                None,
+                Some(json!({"refreshToken": refresh_token})),
+            )
+            .await;
+        status
+    }
+
+    #[tokio::test]
+    async fn changing_the_password_ends_existing_sessions() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+        let first = log_in(&app, "ada@example.com").await;
+        let second = log_in(&app, "ada@example.com").await;
+
+        let (renamed, _) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                Some(json!({"firstName": "Augusta"})),
+            )
+            .await;
+        let after_rename = refresh(&app, &first).await;
+        let (changed, _) = app
+            .request(
+                Method::PATCH,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                Some(json!({"password": "Different456"})),
+            )
+            .await;
+
+        assert_eq!(renamed, StatusCode::OK);
+        assert_eq!(after_rename, StatusCode::OK);
+        assert_eq!(changed, StatusCode::OK);
+        assert_eq!(refresh(&app, &second).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_user_revokes_their_refresh_tokens() {
+        let app = TestApp::new().await;
+        let (id, token) = app.seed_user("ada@example.com", &[Role::User]).await;
+        let refresh_token = log_in(&app, "ada@example.com").await;
+
+        let (status, _) = app
+            .request(
+                Method::DELETE,
+                &format!("/api/v1/users/{}", id),
+                Some(&token),
+                None,
+            )
+            .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            refresh(&app, &refresh_token).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
 }
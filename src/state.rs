@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use mongodb::{Client, Collection, Database};
+
+use crate::{
+    auth::tokens::JwtKeys,
+    common_struct::AppError,
+    config::{Config, PaymentProviderKind, UserStore},
+    models::{
+        cart_module::Cart,
+        category_module::Category,
+        inventory_module::{InventoryMovement, Reservation},
+        order_module::Order,
+        payment_module::Payment,
+        product_module::Product,
+        promotion_module::{Promotion, PromotionRedemption},
+    },
+    payments::{FakePaymentProvider, PaymentProvider},
+    repositories::{
+        InMemoryRefreshTokenRepository, InMemoryUserRepository, MongoRefreshTokenRepository,
+        MongoUserRepository, RefreshTokenRepository, UserRepository,
+    },
+};
+
+/// Shared by every handler through axum's `State` extractor. Cloning is cheap:
+/// the driver handles are reference counted.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub jwt_keys: Arc<JwtKeys>,
+    pub db: Database,
+    pub users: Arc<dyn UserRepository>,
+    pub refresh_tokens: Arc<dyn RefreshTokenRepository>,
+    pub products: Collection<Product>,
+    pub categories: Collection<Category>,
+    pub reservations: Collection<Reservation>,
+    pub inventory_movements: Collection<InventoryMovement>,
+    pub carts: Collection<Cart>,
+    pub orders: Collection<Order>,
+    pub payments: Collection<Payment>,
+    pub promotions: Collection<Promotion>,
+    pub promotion_redemptions: Collection<PromotionRedemption>,
+    /// `None` when payments are switched off; reach it through `provider()`.
+    pub payment_provider: Option<Arc<dyn PaymentProvider>>,
+}
+
+impl AppState {
+    pub fn new(config: Arc<Config>, client: Client) -> Self {
+        let users: Arc<dyn UserRepository> = match config.user_store {
+            UserStore::Mongo => Arc::new(MongoUserRepository::new(
+                &client.database(&config.database_name),
+            )),
+            UserStore::Memory => Arc::new(InMemoryUserRepository::new()),
+        };
+        AppState::with_users(config, client, users)
+    }
+
+    /// Builds state around a caller-supplied user store, e.g. a fake in tests.
+    /// Refresh tokens are kept wherever `user_store` says users are.
+    pub fn with_users(config: Arc<Config>, client: Client, users: Arc<dyn UserRepository>) -> Self {
+        let db = client.database(&config.database_name);
+        let refresh_tokens: Arc<dyn RefreshTokenRepository> = match config.user_store {
+            UserStore::Mongo => Arc::new(MongoRefreshTokenRepository::new(&db)),
+            UserStore::Memory => Arc::new(InMemoryRefreshTokenRepository::new()),
+        };
+        AppState {
+            users,
+            refresh_tokens,
+            products: db.collection("products"),
+            categories: db.collection("categories"),
+            reservations: db.collection("reservations"),
+            inventory_movements: db.collection("inventory_movements"),
+            carts: db.collection("carts"),
+            orders: db.collection("orders"),
+            payments: db.collection("payments"),
+            promotions: db.collection("promotions"),
+            promotion_redemptions: db.collection("promotion_redemptions"),
+            payment_provider: match config.payment_provider {
+                PaymentProviderKind::None => None,
+                PaymentProviderKind::Fake => Some(Arc::new(FakePaymentProvider::new(
+                    config.payment_webhook_secret.clone(),
+                ))),
+            },
+            jwt_keys: Arc::new(JwtKeys::new(&config.jwt_secret)),
+            config,
+            db,
+        }
+    }
+
+    /// The configured payment provider, or 503 when there is none.
+    pub fn provider(&self) -> Result<&dyn PaymentProvider, AppError> {
+        self.payment_provider.as_deref().ok_or_else(|| {
+            AppError::Unavailable("Payments are not enabled on this server".to_string())
+        })
+    }
+}
@@ -0,0 +1,312 @@
+//! Runs the full router in-process against an injectable user store, so HTTP
+//! behaviour can be tested without a MongoDB server. Flows that need real
+//! storage use `TestApp::with_database` and are skipped when no server is
+//! configured, except under CI, where a missing server fails them.
+
+use std::sync::Arc;
+
+use axum::{
+    async_trait,
+    body::{self, Body},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    options::ClientOptions,
+    Client,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{
+    auth::{
+        password,
+        permissions::Role,
+        tokens::{self, JwtKeys},
+    },
+    common_struct::{
+        money::{Currency, Money},
+        pagination::{Page, PageRequest},
+        AppError,
+    },
+    config::{Cli, Config, FileConfig, PaymentProviderKind, UserStore},
+    models::{product_module::Product, user_module::User},
+    repositories::{InMemoryUserRepository, UserChanges, UserFilter, UserRepository},
+    routers::router,
+    state::AppState,
+};
+
+pub const TEST_PASSWORD: &str = "Secret123";
+
+/// Names a MongoDB replica set (transactions need one) for the tests that
+/// need real storage. Each such test gets a database of its own.
+pub const TEST_MONGO_URI_VAR: &str = "TEST_MONGO_URI";
+
+const TEST_JWT_SECRET: &str = "test-secret";
+
+fn test_config() -> Config {
+    let file = FileConfig {
+        // Nothing listens here; only handlers that bypass the repository reach it.
+        mongo_uri: Some("mongodb://127.0.0.1:1".to_string()),
+        mongo_server_selection_timeout_secs: Some(1),
+        jwt_secret: Some(TEST_JWT_SECRET.to_string()),
+        // Users and refresh tokens stay in memory unless a test swaps them.
+        user_store: Some(UserStore::Memory),
+        payment_provider: Some(PaymentProviderKind::Fake),
+        allow_fake_payments: Some(true),
+        ..FileConfig::default()
+    };
+    Config::from_sources(Cli::default(), file).expect("test config is valid")
+}
+
+pub struct TestApp {
+    pub users: Arc<dyn UserRepository>,
+    /// For seeding and inspecting collections directly.
+    pub state: AppState,
+    router: Router,
+}
+
+impl TestApp {
+    /// An app backed by an empty in-memory user store.
+    pub async fn new() -> Self {
+        TestApp::with_users(Arc::new(InMemoryUserRepository::new())).await
+    }
+
+    pub async fn with_users(users: Arc<dyn UserRepository>) -> Self {
+        TestApp::with_config(users, |_| {}).await
+    }
+
+    /// Like `with_users`, letting the test adjust the config first.
+    pub async fn with_config(
+        users: Arc<dyn UserRepository>,
+        configure: impl FnOnce(&mut Config),
+    ) -> Self {
+        let mut config = test_config();
+        configure(&mut config);
+        let options = ClientOptions::parse(&config.mongo_uri)
+            .await
+            .expect("test URI parses");
+        let client = Client::with_options(options).expect("client builds without connecting");
+        let state = AppState::with_users(Arc::new(config), client, users.clone());
+        TestApp {
+            users,
+            router: router(state.clone()).await,
+            state,
+        }
+    }
+
+    /// An app on a fresh database at `TEST_MONGO_URI`, with the schema in
+    /// place. `None` when the variable is unset; callers return early. Under
+    /// CI (`CI` set) an unset variable panics instead, so a pipeline without a
+    /// database cannot report these tests as passed.
+    pub async fn with_database() -> Option<Self> {
+        let Ok(uri) = std::env::var(TEST_MONGO_URI_VAR) else {
+            if std::env::var_os("CI").is_some() {
+                panic!("{} must name a replica set under CI", TEST_MONGO_URI_VAR);
+            }
+            eprintln!(
+                "skipping: set {} to a replica set to run this test",
+                TEST_MONGO_URI_VAR
+            );
+            return None;
+        };
+        let app = TestApp::with_config(Arc::new(InMemoryUserRepository::new()), |config| {
+            config.mongo_uri = uri;
+            config.user_store = UserStore::Mongo;
+            config.database_name = format!("test_{}", ObjectId::new().to_hex());
+            config.mongo_server_selection_timeout = std::time::Duration::from_secs(5);
+        })
+        .await;
+        crate::db::schema::reconcile(&app.state.db, false)
+            .await
+            .expect("test schema applies");
+        Some(app)
+    }
+
+    /// Drops the database made by `with_database`.
+    pub async fn drop_database(&self) {
+        self.state.db.drop().await.expect("test database drops");
+    }
+
+    /// Sends one request and returns the status with the parsed JSON body
+    /// (`Value::Null` when the body is empty or not JSON).
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let (status, _, body) = self.request_with_headers(method, uri, token, body).await;
+        (status, body)
+    }
+
+    pub async fn request_with_headers(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, HeaderMap, Value) {
+        self.request_sending_headers(method, uri, token, &[], body)
+            .await
+    }
+
+    /// Like `request_with_headers`, also sending `headers` with the request.
+    pub async fn request_sending_headers(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        headers: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> (StatusCode, HeaderMap, Value) {
+        let mut builder = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let request = match body {
+            Some(body) => builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => builder.body(Body::empty()),
+        }
+        .expect("request builds");
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("router is infallible");
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("body is readable");
+        (
+            status,
+            headers,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    /// Stores a user directly and returns its id with an access token for it.
+    pub async fn seed_user(&self, email: &str, roles: &[Role]) -> (ObjectId, String) {
+        let user = User {
+            id: None,
+            first_name: Some("Test".to_string()),
+            last_name: Some("User".to_string()),
+            email: Some(email.to_string()),
+            password: Some(
+                password::hash_password(TEST_PASSWORD.to_string())
+                    .await
+                    .expect("password hashes"),
+            ),
+            roles: Some(roles.to_vec()),
+            created_at: Some(DateTime::now()),
+            updated_at: Some(DateTime::now()),
+        };
+        let id = self
+            .users
+            .insert(user)
+            .await
+            .expect("seed user inserts")
+            .id
+            .expect("seed user has an id");
+        (id, token_for(&id, email, roles))
+    }
+
+    /// Stores a product priced at `price` minor units of USD, with `stock`
+    /// units on hand, and returns its id.
+    pub async fn seed_product(&self, price: i64, stock: i64) -> ObjectId {
+        let usd = Currency::from_code("USD").expect("USD is supported");
+        let id = ObjectId::new();
+        let product = Product {
+            id: Some(id),
+            name: Some("Widget".to_string()),
+            sku: Some(format!("W-{}", id.to_hex())),
+            description: None,
+            price: Some(Money::from_minor(price, usd)),
+            stock_quantity: Some(stock),
+            category_id: None,
+            tags: None,
+            images: None,
+            created_at: Some(DateTime::now()),
+            updated_at: Some(DateTime::now()),
+        };
+        self.state
+            .products
+            .insert_one(&product)
+            .await
+            .expect("seed product inserts");
+        id
+    }
+}
+
+pub fn token_for(id: &ObjectId, email: &str, roles: &[Role]) -> String {
+    tokens::issue_access_token(&JwtKeys::new(TEST_JWT_SECRET), id, email, roles)
+        .expect("token issues")
+}
+
+/// An access token for a user nobody else shares, holding only `role`.
+pub fn token(role: Role) -> String {
+    token_for(&ObjectId::new(), "someone@example.com", &[role])
+}
+
+/// `(field, code)` for each entry in an error envelope; `field` is empty when absent.
+pub fn error_codes(body: &Value) -> Vec<(String, String)> {
+    body["errors"]
+        .as_array()
+        .expect("errors array")
+        .iter()
+        .map(|error| {
+            (
+                error["field"].as_str().unwrap_or_default().to_string(),
+                error["code"].as_str().unwrap_or_default().to_string(),
+            )
+        })
+        .collect()
+}
+
+/// A user store whose every call fails as if MongoDB were unreachable.
+pub struct FailingUserRepository;
+
+fn unavailable() -> AppError {
+    AppError::Database(mongodb::error::Error::custom("database unavailable"))
+}
+
+#[async_trait]
+impl UserRepository for FailingUserRepository {
+    async fn insert(&self, _user: User) -> Result<User, AppError> {
+        Err(unavailable())
+    }
+
+    async fn find_by_id(&self, _id: ObjectId) -> Result<Option<User>, AppError> {
+        Err(unavailable())
+    }
+
+    async fn find_by_email(&self, _email: &str) -> Result<Option<User>, AppError> {
+        Err(unavailable())
+    }
+
+    async fn list(
+        &self,
+        _filter: &UserFilter,
+        _request: &PageRequest,
+    ) -> Result<Page<User>, AppError> {
+        Err(unavailable())
+    }
+
+    async fn update(&self, _id: ObjectId, _changes: UserChanges) -> Result<Option<User>, AppError> {
+        Err(unavailable())
+    }
+
+    async fn delete(&self, _id: ObjectId) -> Result<Option<User>, AppError> {
+        Err(unavailable())
+    }
+}